@@ -19,13 +19,10 @@ pub fn main() {
             })
             .expect("failed to draw frame");
         // 2.2: Wait for user input
-        match event::read().expect("failed to read event") {
-            Event::Key(key) => {
-                if key.code == KeyCode::Esc {
-                    break;
-                }
-            }
-            _ => (),
+        if let Event::Key(key) = event::read().expect("failed to read event")
+            && key.code == KeyCode::Esc
+        {
+            break;
         }
     }
     // 3: Restore the terminal
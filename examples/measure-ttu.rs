@@ -13,7 +13,7 @@ fn main() -> Result<()> {
     let peer_id = PeerId::default();
     let mut ttds: Vec<Duration> = Vec::new();
 
-    let addrs = torrent.fetch_peer_addresses(peer_id)?;
+    let addrs = torrent.fetch_peer_addresses(peer_id, None)?;
     let mut rng = rand::rng();
 
     println!("Measuring Time to Unchoke (TTU)\n\n");
@@ -13,7 +13,7 @@ fn main() -> Result<()> {
     let peer_id = PeerId::default();
     let mut successes = 0;
 
-    let addrs = torrent.fetch_peer_addresses(peer_id)?;
+    let addrs = torrent.fetch_peer_addresses(peer_id, None)?;
     let connector = PeerConnector::new(torrent.info.sha1, peer_id, torrent.info.pieces.len())
         .with_timeout(Duration::from_secs(10));
 
@@ -38,7 +38,7 @@ fn setup_tracing() -> Result<()> {
         .with_env_filter(
             EnvFilter::from_default_env().add_directive("bt_client=trace".parse().unwrap()),
         )
-        .with_writer(std::fs::File::create(&log_filename)?)
+        .with_writer(std::fs::File::create(log_filename)?)
         .init();
 
     Ok(())
@@ -14,7 +14,7 @@ fn main() -> Result<()> {
     let peer_id = PeerId::default();
     let mut successes = 0;
 
-    let addrs = torrent.fetch_peer_addresses(peer_id)?;
+    let addrs = torrent.fetch_peer_addresses(peer_id, None)?;
     let connector = PeerConnector::new(torrent.info.sha1, peer_id, torrent.info.pieces.len())
         .with_timeout(Duration::from_secs(30));
 
@@ -35,7 +35,7 @@ fn setup_tracing() -> Result<()> {
 
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::TRACE)
-        .with_writer(std::fs::File::create(&log_filename)?)
+        .with_writer(std::fs::File::create(log_filename)?)
         .init();
 
     Ok(())
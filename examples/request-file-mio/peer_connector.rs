@@ -39,6 +39,7 @@ impl<'a> PeerConnector<'a> {
         }
     }
 
+    #[allow(dead_code)]
     pub fn with_progress_callback(
         mut self,
         progress_callback: impl Fn(SocketAddr, usize) + 'a,
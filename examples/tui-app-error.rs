@@ -30,8 +30,5 @@ fn download_file(tx: &Sender<AppEvent>) -> Result<()> {
 }
 
 fn failing_io() -> StdResult<(), std::io::Error> {
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "failed to do IO operation",
-    ))
+    Err(std::io::Error::other("failed to do IO operation"))
 }
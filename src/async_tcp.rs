@@ -1,13 +1,20 @@
 use std::{io, net::SocketAddr, time::Duration};
 
+mod accept_future;
 mod connect_future;
 mod reactor;
 mod read_exact_future;
+mod write_all_future;
 
 pub fn poll_reactor(timeout: Option<Duration>) -> io::Result<bool> {
     reactor::poll(timeout)
 }
 
+#[cfg(test)]
+pub fn registered_wakers() -> usize {
+    reactor::registered_wakers()
+}
+
 #[derive(Debug)]
 pub struct AsyncTcpStream {
     inner: mio::net::TcpStream,
@@ -22,6 +29,10 @@ impl AsyncTcpStream {
     pub fn read_exact(&mut self, buf: &mut [u8]) -> impl Future<Output = io::Result<()>> {
         read_exact_future::ReadExactFuture::new(&mut self.inner, buf)
     }
+
+    pub fn write_all(&mut self, buf: &[u8]) -> impl Future<Output = io::Result<()>> {
+        write_all_future::WriteAllFuture::new(&mut self.inner, buf)
+    }
 }
 
 impl io::Write for AsyncTcpStream {
@@ -34,6 +45,33 @@ impl io::Write for AsyncTcpStream {
     }
 }
 
+#[derive(Debug)]
+pub struct AsyncTcpListener {
+    id: usize,
+    inner: mio::net::TcpListener,
+}
+
+impl AsyncTcpListener {
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let mut inner = mio::net::TcpListener::from_std(listener);
+        let id = reactor::next_id();
+        reactor::register_source(id, &mut inner, mio::Interest::READABLE)?;
+        Ok(Self { id, inner })
+    }
+
+    pub async fn accept(&self) -> io::Result<(AsyncTcpStream, SocketAddr)> {
+        let (stream, addr) = accept_future::AcceptFuture::new(self.id, &self.inner).await?;
+        Ok((AsyncTcpStream { inner: stream }, addr))
+    }
+}
+
+impl Drop for AsyncTcpListener {
+    fn drop(&mut self) {
+        let _ = reactor::deregister_source(self.id, &mut self.inner);
+    }
+}
+
 impl TryFrom<AsyncTcpStream> for std::net::TcpStream {
     type Error = io::Error;
 
@@ -63,7 +101,7 @@ pub mod test_helpers {
 
     pub fn poll_future<T>(future: impl Future<Output = T>) -> T {
         let waker = Waker::noop();
-        let mut context = Context::from_waker(&waker);
+        let mut context = Context::from_waker(waker);
         let mut pinned = Box::pin(future);
         let mut iter = 0;
         while iter < 10 {
@@ -0,0 +1,53 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::reactor;
+
+pub struct AcceptFuture<'a> {
+    id: usize,
+    listener: &'a mio::net::TcpListener,
+}
+
+impl<'a> AcceptFuture<'a> {
+    pub fn new(id: usize, listener: &'a mio::net::TcpListener) -> Self {
+        Self { id, listener }
+    }
+}
+
+impl<'a> Future for AcceptFuture<'a> {
+    type Output = io::Result<(mio::net::TcpStream, SocketAddr)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.listener.accept() {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                reactor::set_waker(self.id, cx.waker());
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::async_tcp::{AsyncTcpListener, test_helpers::poll_future};
+
+    #[test]
+    fn accept_incoming_connection() {
+        let std_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(std_listener.local_addr().unwrap()).unwrap();
+        let listener = AsyncTcpListener::from_std(std_listener).unwrap();
+
+        let (stream, addr) = poll_future(listener.accept()).unwrap();
+
+        assert_eq!(client.local_addr().unwrap(), addr);
+        let stream: TcpStream = stream.try_into().unwrap();
+        assert_eq!(client.local_addr().unwrap(), stream.peer_addr().unwrap());
+    }
+}
@@ -92,6 +92,11 @@ pub fn poll(timeout: Option<Duration>) -> io::Result<bool> {
     REACTOR.with(|rt| rt.poll(timeout))
 }
 
+#[cfg(test)]
+pub fn registered_wakers() -> usize {
+    REACTOR.with(|rt| rt.wakers.borrow().len())
+}
+
 pub(crate) fn set_waker(id: usize, waker: &std::task::Waker) {
     REACTOR.with(|rt| rt.set_waker(id, waker.clone()))
 }
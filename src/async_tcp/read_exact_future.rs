@@ -104,7 +104,7 @@ where
     R: io::Read + mio::event::Source,
 {
     fn drop(&mut self) {
-        self.deregister().unwrap();
+        let _ = self.deregister();
     }
 }
 
@@ -0,0 +1,183 @@
+use super::reactor;
+use std::task::Waker;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub struct WriteAllFuture<'a, 'b, W>
+where
+    W: io::Write + mio::event::Source,
+{
+    id: Option<usize>,
+    stream: &'a mut W,
+    buffer: &'b [u8],
+    bytes_written: usize,
+}
+
+impl<'a, 'b, W> WriteAllFuture<'a, 'b, W>
+where
+    W: io::Write + mio::event::Source,
+{
+    pub fn new(stream: &'a mut W, buffer: &'b [u8]) -> Self {
+        Self {
+            id: None,
+            stream,
+            buffer,
+            bytes_written: 0,
+        }
+    }
+
+    fn register(&mut self, waker: &Waker) -> io::Result<()> {
+        if self.id.is_none() {
+            let id = reactor::next_id();
+            reactor::register_source(id, self.stream, mio::Interest::WRITABLE)?;
+            reactor::set_waker(id, waker);
+            self.id = Some(id);
+        }
+        Ok(())
+    }
+
+    fn deregister(&mut self) -> io::Result<()> {
+        if let Some(id) = self.id {
+            reactor::deregister_source(id, self.stream)?;
+            self.id = None;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'b, W> Future for WriteAllFuture<'a, 'b, W>
+where
+    W: io::Write + mio::event::Source,
+{
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        while self.bytes_written < self.buffer.len() {
+            let remaining = &self.buffer[self.bytes_written..];
+            match self.stream.write(remaining) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.register(cx.waker())?;
+                    let id = self.id.expect("the id should be set");
+                    reactor::set_waker(id, cx.waker());
+                    return Poll::Pending;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Ok(0) => {
+                    self.deregister()?;
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Wrote zero bytes to stream",
+                    )));
+                }
+                Ok(n) => self.bytes_written += n,
+                Err(err) => {
+                    self.deregister()?;
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+        self.deregister()?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, 'b, W> Drop for WriteAllFuture<'a, 'b, W>
+where
+    W: io::Write + mio::event::Source,
+{
+    fn drop(&mut self) {
+        let _ = self.deregister();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_tcp::test_helpers::poll_future;
+
+    use super::*;
+
+    #[test]
+    fn test_write_all_in_single_poll() {
+        let mut stream = Sink::accepting(vec![Ok(3)]);
+        let future = WriteAllFuture::new(&mut stream, &[42, 43, 44]);
+
+        poll_future(future).unwrap();
+        assert_eq!(stream.written, vec![42, 43, 44]);
+    }
+
+    #[test]
+    fn test_write_all_after_stream_would_block() {
+        let mut stream = Sink::accepting(vec![
+            Ok(1),
+            Err(io::ErrorKind::WouldBlock),
+            Err(io::ErrorKind::WouldBlock),
+            Ok(2),
+        ]);
+        let future = WriteAllFuture::new(&mut stream, &[42, 43, 44]);
+
+        poll_future(future).unwrap();
+        assert_eq!(stream.written, vec![42, 43, 44]);
+    }
+
+    #[test]
+    fn test_write_to_closed_stream() {
+        let mut stream = Sink::accepting(vec![Ok(0)]);
+        let future = WriteAllFuture::new(&mut stream, &[42]);
+
+        let err = poll_future(future).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    struct Sink {
+        results: Vec<Result<usize, io::ErrorKind>>,
+        written: Vec<u8>,
+    }
+
+    impl Sink {
+        fn accepting(results: Vec<Result<usize, io::ErrorKind>>) -> Self {
+            Self {
+                results,
+                written: vec![],
+            }
+        }
+    }
+
+    impl mio::event::Source for Sink {
+        fn register(
+            &mut self,
+            _registry: &mio::Registry,
+            _token: mio::Token,
+            _interests: mio::Interest,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn reregister(
+            &mut self,
+            _registry: &mio::Registry,
+            _token: mio::Token,
+            _interests: mio::Interest,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn deregister(&mut self, _registry: &mio::Registry) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let length = self.results.remove(0)?.min(buf.len());
+            self.written.extend_from_slice(&buf[..length]);
+            Ok(length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,19 +1,310 @@
-use std::fs::File;
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter},
+    net::SocketAddr,
+    path::Path,
+    sync::mpsc,
+    thread,
+};
 
-use bt_client::{Torrent, ratatui_ui::App, result::Result};
+use bt_client::{
+    DownloadOptions, Torrent,
+    downloader::{DownloadHandle, FilePriority, RateLimits},
+    hash_pool::HashPool,
+    ratatui_ui::{App, AppEvent},
+    result::Result,
+    storage::{Preallocation, StorageBackend},
+    verify::{self, DataStatus, VerifyReport},
+};
 use tracing::Level;
 
 pub fn main() -> Result<()> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        Some("verify") => run_verify(&args[1..]),
+        Some("seed") => run_seed(&args[1..]),
+        Some("get") => run_get(&args[1..]),
+        Some("files") => run_files(&args[1..]),
+        Some(command) if !command.starts_with("--") && !command.ends_with(".torrent") => {
+            Err(format!("Unknown command: {}", command).into())
+        }
+        _ => run_download(&args),
+    }
+}
+
+fn run_download(args: &[String]) -> Result<()> {
+    let (rate_limits, rest) = parse_rate_limits(args)?;
+    let (storage_backend, rest) = parse_storage_backend(rest);
+    let (preallocation, rest) = take_option(rest, "--preallocate")?;
+    let preallocation = match preallocation.map(String::as_str) {
+        None | Some("sparse") => Preallocation::Sparse,
+        Some("full") => Preallocation::Full,
+        Some(_) => return Err("--preallocate expects 'sparse' or 'full'".into()),
+    };
+    let (incomplete_dir, rest) = take_option(rest, "--incomplete-dir")?;
+    let incomplete_dir = incomplete_dir.cloned();
+    let (file_selection, rest) = take_option(rest, "--files")?;
+    let file_selection = file_selection
+        .map(|spec| parse_file_selection(spec))
+        .transpose()?;
+    let (stream_address, rest) = take_option(rest, "--stream")?;
+    let stream_address = stream_address
+        .map(|address| address.parse::<SocketAddr>())
+        .transpose()
+        .map_err(|e| format!("Invalid streaming address: {}", e))?;
+    let torrent_path = match rest[..] {
+        [] => None,
+        [torrent_path] => Some(torrent_path.clone()),
+        _ => {
+            return Err(
+                "Usage: main [<file>.torrent] [--download-limit <KiB/s>] [--upload-limit <KiB/s>] \
+                        [--mmap] [--preallocate <sparse|full>] [--incomplete-dir <dir>] \
+                        [--stream <addr>] [--files <index[:low|normal|high]>,...]"
+                    .into(),
+            );
+        }
+    };
     setup_tracing()?;
 
-    let mut ui = App::new();
-    ui.start_background_task(|tx| Torrent::read_default_file()?.download(tx));
+    let handle = download_handle(&rate_limits);
+    let mut ui = App::new().with_download_handle(handle.clone());
+    let download_handle = handle.clone();
+    ui.start_background_task(move |tx| {
+        let torrent = match &torrent_path {
+            Some(torrent_path) => Torrent::read_file(torrent_path)?,
+            None => Torrent::read_default_file()?,
+        };
+        let mut options = DownloadOptions::default()
+            .with_handle(download_handle)
+            .with_storage_backend(storage_backend)
+            .with_preallocation(preallocation);
+        if let Some(incomplete_dir) = incomplete_dir {
+            options = options.with_incomplete_dir(incomplete_dir);
+        }
+        if let Some(selection) = &file_selection {
+            let file_count = torrent.info.files.len();
+            options = options.with_file_priorities(file_priorities(selection, file_count)?);
+        }
+        if let Some(stream_address) = stream_address {
+            options = options.with_streaming(stream_address);
+        }
+        torrent.download(&options, Path::new("."), tx)
+    });
     ui.run_ui_loop()?;
 
-    println!("Download completed successfully");
+    if handle.is_cancelled() {
+        println!("Download cancelled");
+    } else {
+        println!("Download completed successfully");
+    }
+    Ok(())
+}
+
+fn run_verify(args: &[String]) -> Result<()> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths = args
+        .iter()
+        .filter(|arg| *arg != "--json")
+        .collect::<Vec<_>>();
+    let [torrent_path, data_dir] = paths[..] else {
+        return Err("Usage: main verify <torrent-file> <data-dir> [--json]".into());
+    };
+
+    let torrent = Torrent::read_file(torrent_path)?;
+    let report = verify::verify(&torrent.info, data_dir, &mut HashPool::default())?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    if report.is_complete() {
+        Ok(())
+    } else {
+        Err("Data does not match the torrent".into())
+    }
+}
+
+fn run_seed(args: &[String]) -> Result<()> {
+    let (rate_limits, rest) = parse_rate_limits(args)?;
+    let (storage_backend, rest) = parse_storage_backend(rest);
+    let super_seed = rest.iter().any(|arg| *arg == "--super-seed");
+    let paths = rest
+        .into_iter()
+        .filter(|arg| *arg != "--super-seed")
+        .collect::<Vec<_>>();
+    let [torrent_path, data_dir] = paths[..] else {
+        return Err(
+            "Usage: main seed <torrent-file> <data-dir> [--super-seed] [--upload-limit <KiB/s>] [--mmap]"
+                .into(),
+        );
+    };
+
+    setup_tracing()?;
+    let torrent = Torrent::read_file(torrent_path)?;
+    let options = DownloadOptions::default()
+        .with_handle(download_handle(&rate_limits))
+        .with_storage_backend(storage_backend);
+    println!("Seeding {} from {}", torrent.info.name, data_dir);
+    torrent.seed(&options, Path::new(data_dir), super_seed)
+}
+
+fn run_get(args: &[String]) -> Result<()> {
+    let (rate_limits, rest) = parse_rate_limits(args)?;
+    let [torrent_path] = rest[..] else {
+        return Err(
+            "Usage: main get <torrent-file> [--download-limit <KiB/s>] [--upload-limit <KiB/s>]"
+                .into(),
+        );
+    };
+
+    setup_tracing()?;
+    let torrent = Torrent::read_file(torrent_path)?;
+    let options = DownloadOptions::default().with_handle(download_handle(&rate_limits));
+    let (tx, rx) = mpsc::channel();
+    let progress = thread::spawn(move || {
+        for event in rx {
+            if let AppEvent::Downloading(current, total) = event {
+                eprint!("\rDownloaded {}/{} pieces", current, total);
+            }
+        }
+        eprintln!();
+    });
+    let result = torrent.download_to(&options, BufWriter::new(io::stdout().lock()), &tx);
+    drop(tx);
+    let _ = progress.join();
+    result
+}
+
+fn run_files(args: &[String]) -> Result<()> {
+    let [torrent_path] = args else {
+        return Err("Usage: main files <torrent-file>".into());
+    };
+
+    let torrent = Torrent::read_file(torrent_path)?;
+    let format = humansize::BINARY.decimal_zeroes(2);
+    for (index, file) in torrent.info.files.iter().enumerate() {
+        if file.attributes.padding {
+            continue;
+        }
+        println!(
+            "{:>4}  {:>12}  {}",
+            index,
+            humansize::format_size(file.length, format),
+            file.path.display()
+        );
+    }
     Ok(())
 }
 
+fn parse_file_selection(spec: &str) -> Result<Vec<(usize, FilePriority)>> {
+    spec.split(',')
+        .map(|item| {
+            let (index, priority) = item.split_once(':').unwrap_or((item, "normal"));
+            let index = index
+                .parse()
+                .map_err(|_| format!("Invalid file index: {}", index))?;
+            let priority = match priority {
+                "low" => FilePriority::Low,
+                "normal" => FilePriority::Normal,
+                "high" => FilePriority::High,
+                _ => return Err(format!("Invalid file priority: {}", priority).into()),
+            };
+            Ok((index, priority))
+        })
+        .collect()
+}
+
+fn file_priorities(
+    selection: &[(usize, FilePriority)],
+    file_count: usize,
+) -> Result<Vec<FilePriority>> {
+    let mut priorities = vec![FilePriority::Skip; file_count];
+    for (index, priority) in selection {
+        let file = priorities
+            .get_mut(*index)
+            .ok_or_else(|| format!("Torrent has no file with index {}", index))?;
+        *file = *priority;
+    }
+    Ok(priorities)
+}
+
+fn parse_storage_backend(args: Vec<&String>) -> (StorageBackend, Vec<&String>) {
+    let (mmap, rest): (Vec<_>, Vec<_>) = args.into_iter().partition(|arg| *arg == "--mmap");
+    let backend = if mmap.is_empty() {
+        StorageBackend::File
+    } else {
+        StorageBackend::Mmap
+    };
+    (backend, rest)
+}
+
+fn take_option<'a>(
+    args: Vec<&'a String>,
+    name: &str,
+) -> Result<(Option<&'a String>, Vec<&'a String>)> {
+    let mut value = None;
+    let mut rest = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg != name {
+            rest.push(arg);
+            continue;
+        }
+        value = Some(
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?,
+        );
+    }
+    Ok((value, rest))
+}
+
+fn parse_rate_limits(args: &[String]) -> Result<(RateLimits, Vec<&String>)> {
+    let mut download = None;
+    let mut upload = None;
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let limit = match arg.as_str() {
+            "--download-limit" => &mut download,
+            "--upload-limit" => &mut upload,
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        let kib_per_second: u64 = value
+            .parse()
+            .map_err(|_| format!("Invalid rate limit: {}", value))?;
+        *limit = Some(kib_per_second * 1024);
+    }
+    Ok((RateLimits::new(download, upload), rest))
+}
+
+fn download_handle(global_limits: &RateLimits) -> DownloadHandle {
+    DownloadHandle::new().with_rate_limits(global_limits.child(None, None))
+}
+
+fn print_report(report: &VerifyReport) {
+    for file in &report.files {
+        println!(
+            "{:>8}  {}",
+            format!("{:?}", file.status),
+            file.path.display()
+        );
+    }
+    println!(
+        "Pieces: {} good, {} missing, {} corrupt",
+        report.piece_count(DataStatus::Good),
+        report.piece_count(DataStatus::Missing),
+        report.piece_count(DataStatus::Corrupt),
+    );
+}
+
 fn setup_tracing() -> Result<()> {
     let crate_name = env!("CARGO_PKG_NAME");
     let log_filename = format!("{}.log", crate_name);
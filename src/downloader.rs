@@ -1,40 +1,18 @@
-pub use file_downloader::FileDownloader;
-use file_downloader::{Block, DownloadChannel, RequestChannel};
+pub use download_handle::{DownloadHandle, DownloadStatus};
+pub use download_stats::{DownloadStats, PeerStats};
+pub use file_downloader::{ChokerConfig, FileDownloader, TransferStats};
+use file_downloader::{DownloadChannel, DownloadEvent, PeerKey};
+pub use file_priority::FilePriority;
 pub use peer_comm::PeerChannel;
-use peer_comm::PeerMessage;
-use std::io;
+pub use peer_set::{PeerJoiner, PeerSet};
+pub use rate_limiter::{RateLimiter, RateLimits};
 
 pub mod async_peer_connector;
+mod download_handle;
+mod download_stats;
 mod file_downloader;
+mod file_priority;
 pub mod peer_comm;
-
-impl RequestChannel for PeerChannel {
-    fn request(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<()> {
-        self.send(&PeerMessage::Request {
-            piece_index,
-            offset,
-            length,
-        })
-    }
-}
-
-impl DownloadChannel for PeerChannel {
-    fn receive(&mut self) -> io::Result<Block> {
-        let msg = self.receive()?;
-        match msg {
-            PeerMessage::Piece {
-                piece_index,
-                offset,
-                block,
-            } => Ok(Block {
-                piece_index,
-                offset,
-                data: block,
-            }),
-            other => Err(io::Error::other(format!(
-                "Expected `piece` message, received: {:?}",
-                other
-            ))),
-        }
-    }
-}
+pub mod peer_listener;
+mod peer_set;
+mod rate_limiter;
@@ -18,8 +18,8 @@ mod probe_result;
 mod request_file;
 mod waker;
 
-use probe_result::ProbeResult;
-use waker::TaskWaker;
+pub(super) use probe_result::{ProbeError, ProbeResult};
+pub(super) use waker::TaskWaker;
 
 pub struct PeerConnector<'a> {
     info_hash: Sha1,
@@ -338,7 +338,10 @@ mod tests {
                 handshake.send(&mut stream).unwrap();
                 let bitfield = vec![0b11111111, 0b11111111];
                 send_bitfield_in_chunks(&mut stream, bitfield).unwrap();
-                let msg = PeerMessage::receive(&mut stream).unwrap();
+                let mut msg = PeerMessage::receive(&mut stream).unwrap();
+                while matches!(msg, PeerMessage::Extended { .. }) {
+                    msg = PeerMessage::receive(&mut stream).unwrap();
+                }
                 if msg != PeerMessage::Interested {
                     panic!("expected interested message, received: {:?}", msg);
                 }
@@ -4,8 +4,10 @@ use tracing::instrument;
 
 use crate::async_tcp::AsyncTcpStream;
 use crate::downloader::PeerChannel;
-use crate::downloader::peer_comm::{self, HandshakeMessage, PeerMessage};
-use crate::types::{PeerId, Sha1};
+use crate::downloader::peer_comm::{
+    self, ConnectionState, ExtensionHandshake, HandshakeMessage, PeerMessage,
+};
+use crate::types::{Bitfield, PeerId, Sha1};
 
 use super::probe_result::{ProbeError, ProbeResult};
 
@@ -19,12 +21,17 @@ pub async fn request_file_from_peer(
     let mut stream = init_connection(addr).await?;
 
     let handshake = HandshakeMessage::new(info_hash, peer_id);
-    let peer_id = exchange_handshake(&mut stream, handshake).await?;
-    receive_bitfield(&mut stream, piece_count).await?;
-    request_interest(&mut stream).await?;
+    let their_handshake = exchange_handshake(&mut stream, handshake).await?;
+    if their_handshake.supports_extensions() {
+        ExtensionHandshake::ours().to_message().send(&mut stream)?;
+    }
+    let mut state = ConnectionState::default().with_piece_count(piece_count);
+    receive_bitfield(&mut stream, piece_count, &mut state).await?;
+    request_interest(&mut stream, &mut state).await?;
 
     let std_stream: std::net::TcpStream = stream.try_into()?;
-    let peer_channel = PeerChannel::from_stream(std_stream, peer_id)?;
+    let peer_channel =
+        PeerChannel::from_stream(std_stream, their_handshake.peer_id)?.with_state(state);
     Ok(peer_channel)
 }
 
@@ -37,7 +44,7 @@ async fn init_connection(addr: SocketAddr) -> io::Result<AsyncTcpStream> {
 async fn exchange_handshake<S>(
     stream: &mut S,
     my_handshake: HandshakeMessage,
-) -> ProbeResult<PeerId>
+) -> ProbeResult<HandshakeMessage>
 where
     S: io::Write + peer_comm::AsyncReadExact,
 {
@@ -46,64 +53,64 @@ where
     if their_handshake.info_hash != my_handshake.info_hash {
         return Err(ProbeError::InfoHashMismatch);
     }
-    Ok(their_handshake.peer_id)
+    Ok(their_handshake)
 }
 
-#[instrument(skip(stream), err)]
-async fn receive_bitfield<S>(stream: &mut S, piece_count: usize) -> ProbeResult<()>
+#[instrument(skip(stream, state), err)]
+async fn receive_bitfield<S>(
+    stream: &mut S,
+    piece_count: usize,
+    state: &mut ConnectionState,
+) -> ProbeResult<()>
 where
     S: peer_comm::AsyncReadExact,
 {
-    let msg = PeerMessage::receive_async(stream).await?;
-    if let PeerMessage::Bitfield(bf) = msg {
+    let msg = receive_message(stream, state).await?;
+    if let PeerMessage::Bitfield(bf) = &msg {
         let expected_bitfield_size = piece_count.div_ceil(8);
         if bf.len() != expected_bitfield_size {
             return Err(ProbeError::BitfieldSizeMismatch);
         }
-        if !is_bitfield_complete(&bf, piece_count) {
+        if !Bitfield::from_bytes(bf).is_complete(piece_count) {
             return Err(ProbeError::IncompleteFile);
         }
+        state.message_received(&msg)?;
         Ok(())
     } else {
         return Err(ProbeError::UnexpectedPeerMessage(msg));
     }
 }
 
-fn is_bitfield_complete(bitfield: &[u8], piece_count: usize) -> bool {
-    for byte in &bitfield[..bitfield.len() - 1] {
-        if *byte != 255 {
-            return false;
-        }
-    }
-
-    let mut pieces_in_last_byte = piece_count % 8;
-    if pieces_in_last_byte == 0 {
-        pieces_in_last_byte = 8;
-    }
-    let last_byte_mask = (128u8 as i8 >> (pieces_in_last_byte - 1)) as u8;
-    let last_byte = bitfield[bitfield.len() - 1];
-    if last_byte & last_byte_mask != last_byte_mask {
-        return false;
-    }
-
-    true
-}
-
-#[instrument(skip(stream), err)]
-async fn request_interest<S>(stream: &mut S) -> ProbeResult<()>
+#[instrument(skip(stream, state), err)]
+async fn request_interest<S>(stream: &mut S, state: &mut ConnectionState) -> ProbeResult<()>
 where
     S: io::Write + peer_comm::AsyncReadExact,
 {
     PeerMessage::Interested.send(stream)?;
+    state.message_sent(&PeerMessage::Interested);
 
-    let response = PeerMessage::receive_async(stream).await?;
+    let response = receive_message(stream, state).await?;
     if matches!(response, PeerMessage::Unchoke) {
+        state.message_received(&response)?;
         Ok(())
     } else {
         Err(ProbeError::UnexpectedPeerMessage(response))
     }
 }
 
+async fn receive_message<S>(stream: &mut S, state: &mut ConnectionState) -> io::Result<PeerMessage>
+where
+    S: peer_comm::AsyncReadExact,
+{
+    loop {
+        let msg = PeerMessage::receive_async(stream).await?;
+        if !matches!(msg, PeerMessage::Extended { .. }) {
+            return Ok(msg);
+        }
+        state.message_received(&msg)?;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -127,8 +134,8 @@ mod tests {
             let mut stream = InMemoryStream::new();
             stream.to_send.push(their_handshake.to_vec());
 
-            let peer_id = poll_future(exchange_handshake(&mut stream, my_handshake)).unwrap();
-            assert_eq!(their_peer_id, peer_id);
+            let handshake = poll_future(exchange_handshake(&mut stream, my_handshake)).unwrap();
+            assert_eq!(their_peer_id, { handshake.peer_id });
             assert_eq!(vec![my_handshake.to_vec()], stream.received);
         }
 
@@ -158,7 +165,32 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            poll_future(receive_bitfield(&mut stream, 16)).unwrap();
+            poll_future(receive_bitfield(
+                &mut stream,
+                16,
+                &mut ConnectionState::default(),
+            ))
+            .unwrap();
+        }
+
+        #[test]
+        fn skip_extension_handshake_before_bitfield() {
+            let extension_handshake = ExtensionHandshake {
+                reqq: Some(32),
+                ..ExtensionHandshake::default()
+            };
+            let mut stream = InMemoryStream::new();
+            stream
+                .to_send
+                .push(extension_handshake.to_message().to_vec());
+            stream
+                .to_send
+                .push(PeerMessage::Bitfield(vec![0b11111111, 0b11111111]).to_vec());
+
+            let mut state = ConnectionState::default();
+            poll_future(receive_bitfield(&mut stream, 16, &mut state)).unwrap();
+            assert_eq!(state.peer_request_queue, Some(32));
+            assert!(state.peer_pieces.has_piece(15));
         }
 
         #[test]
@@ -166,8 +198,12 @@ mod tests {
             let mut stream = InMemoryStream::new();
             stream.to_send.push(PeerMessage::Unchoke.to_vec());
 
-            let err =
-                poll_future(receive_bitfield(&mut stream, 16)).expect_err("Expected an error");
+            let err = poll_future(receive_bitfield(
+                &mut stream,
+                16,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(
                 err,
                 ProbeError::UnexpectedPeerMessage(PeerMessage::Unchoke)
@@ -182,8 +218,12 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            let err =
-                poll_future(receive_bitfield(&mut stream, 16)).expect_err("Expected an error");
+            let err = poll_future(receive_bitfield(
+                &mut stream,
+                16,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(err, ProbeError::BitfieldSizeMismatch));
         }
 
@@ -195,7 +235,12 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            let err = poll_future(receive_bitfield(&mut stream, 8)).expect_err("Expected an error");
+            let err = poll_future(receive_bitfield(
+                &mut stream,
+                8,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(err, ProbeError::BitfieldSizeMismatch));
         }
 
@@ -207,8 +252,12 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            let err =
-                poll_future(receive_bitfield(&mut stream, 16)).expect_err("Expected an error");
+            let err = poll_future(receive_bitfield(
+                &mut stream,
+                16,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(err, ProbeError::IncompleteFile));
         }
 
@@ -220,8 +269,12 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            let err =
-                poll_future(receive_bitfield(&mut stream, 15)).expect_err("Expected an error");
+            let err = poll_future(receive_bitfield(
+                &mut stream,
+                15,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(err, ProbeError::IncompleteFile));
         }
 
@@ -233,7 +286,12 @@ mod tests {
                 .to_send
                 .push(PeerMessage::Bitfield(bitfield).to_vec());
 
-            poll_future(receive_bitfield(&mut stream, 10)).unwrap();
+            poll_future(receive_bitfield(
+                &mut stream,
+                10,
+                &mut ConnectionState::default(),
+            ))
+            .unwrap();
         }
     }
 
@@ -245,7 +303,11 @@ mod tests {
             let mut stream = InMemoryStream::new();
             stream.to_send.push(PeerMessage::Unchoke.to_vec());
 
-            poll_future(request_interest(&mut stream)).unwrap();
+            poll_future(request_interest(
+                &mut stream,
+                &mut ConnectionState::default(),
+            ))
+            .unwrap();
             assert_eq!(vec![PeerMessage::Interested.to_vec()], stream.received);
         }
 
@@ -254,7 +316,11 @@ mod tests {
             let mut stream = InMemoryStream::new();
             stream.to_send.push(PeerMessage::Interested.to_vec());
 
-            let err = poll_future(request_interest(&mut stream)).expect_err("Expected an error");
+            let err = poll_future(request_interest(
+                &mut stream,
+                &mut ConnectionState::default(),
+            ))
+            .expect_err("Expected an error");
             assert!(matches!(
                 err,
                 ProbeError::UnexpectedPeerMessage(PeerMessage::Interested)
@@ -0,0 +1,150 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU8, Ordering},
+};
+
+use super::{DownloadStats, RateLimits};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadStatus {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+impl DownloadStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => DownloadStatus::Paused,
+            2 => DownloadStatus::Cancelled,
+            _ => DownloadStatus::Running,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadHandle {
+    status: Arc<AtomicU8>,
+    stats: Arc<Mutex<DownloadStats>>,
+    rate_limits: RateLimits,
+}
+
+impl Default for DownloadHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DownloadHandle {
+    pub fn new() -> Self {
+        Self {
+            status: Arc::new(AtomicU8::new(DownloadStatus::Running as u8)),
+            stats: Arc::new(Mutex::new(DownloadStats::default())),
+            rate_limits: RateLimits::default(),
+        }
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    pub fn set_download_limit(&self, bytes_per_second: Option<u64>) {
+        self.rate_limits.download.set_rate(bytes_per_second);
+    }
+
+    pub fn set_upload_limit(&self, bytes_per_second: Option<u64>) {
+        self.rate_limits.upload.set_rate(bytes_per_second);
+    }
+
+    pub fn status(&self) -> DownloadStatus {
+        DownloadStatus::from_u8(self.status.load(Ordering::Acquire))
+    }
+
+    pub fn stats(&self) -> DownloadStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub(crate) fn publish_stats(&self, stats: DownloadStats) {
+        *self.stats.lock().unwrap() = stats;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.status() == DownloadStatus::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.status() == DownloadStatus::Cancelled
+    }
+
+    pub fn pause(&self) {
+        self.transition(DownloadStatus::Running, DownloadStatus::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.transition(DownloadStatus::Paused, DownloadStatus::Running);
+    }
+
+    pub fn cancel(&self) {
+        self.status
+            .store(DownloadStatus::Cancelled as u8, Ordering::Release);
+    }
+
+    fn transition(&self, from: DownloadStatus, to: DownloadStatus) {
+        let _ =
+            self.status
+                .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_and_resume_running_download() {
+        let handle = DownloadHandle::new();
+        handle.pause();
+        assert!(handle.is_paused());
+
+        handle.resume();
+        assert_eq!(handle.status(), DownloadStatus::Running);
+    }
+
+    #[test]
+    fn share_status_between_clones() {
+        let handle = DownloadHandle::new();
+        handle.clone().pause();
+        assert!(handle.is_paused());
+    }
+
+    #[test]
+    fn cancelled_download_cannot_be_resumed() {
+        let handle = DownloadHandle::new();
+        handle.pause();
+        handle.cancel();
+        handle.resume();
+        handle.pause();
+        assert!(handle.is_cancelled());
+    }
+
+    #[test]
+    fn change_torrent_rate_limits_within_global_limits() {
+        let global = RateLimits::new(Some(100), None);
+        let handle = DownloadHandle::new().with_rate_limits(global.child(None, None));
+        handle.clone().set_upload_limit(Some(50));
+        assert_eq!(handle.rate_limits().upload.rate(), Some(50));
+        assert_eq!(handle.rate_limits().download.rate(), None);
+
+        global.download.consume(300);
+        assert!(handle.rate_limits().download.is_exhausted());
+
+        handle.set_download_limit(Some(1000));
+        global.download.set_rate(None);
+        assert!(!handle.rate_limits().download.is_exhausted());
+    }
+}
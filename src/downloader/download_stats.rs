@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadStats {
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub uploaded_bytes: u64,
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub eta: Option<Duration>,
+    pub wasted_bytes: u64,
+    pub hash_failures: u32,
+    pub peers: Vec<PeerStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStats {
+    pub peer: usize,
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub queue_depth: usize,
+    pub pending_requests: usize,
+    pub latency: Option<Duration>,
+    pub snubbed: bool,
+}
+
+impl DownloadStats {
+    pub fn left_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.downloaded_bytes)
+    }
+}
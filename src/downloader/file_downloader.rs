@@ -1,13 +1,41 @@
+mod choker;
 mod file_info;
 mod piece_composer;
 mod request_emitter;
+mod request_pipeline;
+mod stats_collector;
+mod super_seeder;
+mod upload_queue;
 
-use std::{io, time::Instant};
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
 
-use crate::types::Sha1;
+use tracing::{debug, info, warn};
+
+use crate::{
+    downloader::{
+        DownloadHandle, DownloadStats, FilePriority, PeerStats, RateLimits,
+        peer_comm::{ConnectionState, PeerMessage},
+    },
+    hash_pool::{HashPool, HashResult},
+    storage::Storage,
+    streaming::StreamingHandle,
+    types::{Bitfield, Sha1},
+};
+use choker::Choker;
+pub use choker::ChokerConfig;
 use file_info::FileInfo;
 use piece_composer::{Piece, PieceComposer};
 use request_emitter::RequestEmitter;
+use request_pipeline::RequestPipeline;
+use stats_collector::StatsCollector;
+use super_seeder::SuperSeeder;
+use upload_queue::UploadQueue;
+
+pub type PeerKey = usize;
 
 #[derive(Debug, Clone)]
 pub struct Block {
@@ -16,28 +44,104 @@ pub struct Block {
     pub data: Vec<u8>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRequest {
+    pub piece_index: u32,
+    pub offset: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub downloaded_bytes: u64,
+    pub uploaded_bytes: u64,
+}
+
 pub trait RequestChannel {
     fn request(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<()>;
 }
 
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    PeerConnected {
+        peer: PeerKey,
+        state: ConnectionState,
+        incoming: bool,
+    },
+    Message(PeerKey, PeerMessage),
+    PeerDisconnected(PeerKey),
+}
+
 pub trait DownloadChannel {
-    fn receive(&mut self) -> io::Result<Block>;
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<DownloadEvent>>;
+    fn send(&mut self, peer: PeerKey, msg: &PeerMessage) -> io::Result<()>;
+    fn disconnect(&mut self, peer: PeerKey);
+}
+
+struct PeerRequests<'c, T: DownloadChannel> {
+    channel: &'c mut T,
+    peer: PeerKey,
+    pipeline: &'c mut RequestPipeline,
+}
+
+impl<'c, T: DownloadChannel> RequestChannel for PeerRequests<'c, T> {
+    fn request(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<()> {
+        self.pipeline
+            .request_sent(piece_index, offset, Instant::now());
+        self.channel.send(
+            self.peer,
+            &PeerMessage::Request {
+                piece_index,
+                offset,
+                length,
+            },
+        )
+    }
 }
 
-pub struct FileDownloader<'a, T: RequestChannel + DownloadChannel> {
+pub struct FileDownloader<'a, T: DownloadChannel> {
     channel: &'a mut T,
+    storage: &'a mut dyn Storage,
     piece_hashes: Vec<Sha1>,
+    hash_pool: Option<HashPool>,
     piece_composer: PieceComposer,
     request_emitter: RequestEmitter,
     tracker: DownloadTracker<'a>,
+    peers: HashMap<PeerKey, ConnectionState>,
+    pipelines: HashMap<PeerKey, RequestPipeline>,
+    upload_queue: UploadQueue,
+    choker: Choker,
+    super_seeder: Option<SuperSeeder>,
+    rate_limits: RateLimits,
+    requests_throttled: bool,
+    disk_backlogged: bool,
+    handle: DownloadHandle,
+    paused: bool,
+    streaming: Option<StreamingHandle>,
+    stream_focus: Option<u32>,
+    stats: TransferStats,
+    stats_collector: StatsCollector,
+    stats_callback: Box<dyn FnMut(&DownloadStats) + 'a>,
+    piece_failures: HashMap<u32, u32>,
+    checkpoint_callback: Box<dyn FnMut(&Bitfield) + 'a>,
+    last_checkpoint: Instant,
+    last_timeout_check: Instant,
 }
 
-impl<'a, T: RequestChannel + DownloadChannel> FileDownloader<'a, T> {
-    const REQUEST_QUEUE_LENGTH: u16 = 150;
+impl<'a, T: DownloadChannel> FileDownloader<'a, T> {
     const BLOCK_LENGTH: u32 = 1 << 14;
+    const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+    const DISK_BACKLOG_POLL: Duration = Duration::from_millis(50);
+    const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+    const MAX_UPLOAD_REQUESTS_PER_PEER: usize = 64;
+    const MAX_UPLOAD_BLOCK_LENGTH: u32 = 1 << 17;
+    const UPLOADS_PER_ROUND: usize = 4;
+    const MAX_PIECE_HASH_FAILURES: u32 = 3;
 
     pub fn new(
         channel: &'a mut T,
+        storage: &'a mut dyn Storage,
         piece_hashes: Vec<Sha1>,
         piece_length: u32,
         file_length: usize,
@@ -48,16 +152,38 @@ impl<'a, T: RequestChannel + DownloadChannel> FileDownloader<'a, T> {
         };
         Self {
             channel,
+            storage,
             piece_hashes,
-            piece_composer: PieceComposer::new(file_info),
+            hash_pool: None,
+            piece_composer: PieceComposer::new(file_info, Self::BLOCK_LENGTH),
             request_emitter: RequestEmitter::new(Self::BLOCK_LENGTH, file_info),
             tracker: DownloadTracker::new(file_info),
+            peers: HashMap::new(),
+            pipelines: HashMap::new(),
+            upload_queue: UploadQueue::new(Self::MAX_UPLOAD_REQUESTS_PER_PEER),
+            choker: Choker::new(ChokerConfig::default(), Instant::now()),
+            super_seeder: None,
+            rate_limits: RateLimits::default(),
+            requests_throttled: false,
+            disk_backlogged: false,
+            handle: DownloadHandle::new(),
+            paused: false,
+            streaming: None,
+            stream_focus: None,
+            stats: TransferStats::default(),
+            stats_collector: StatsCollector::new(Instant::now()),
+            stats_callback: Box::new(|_| {}),
+            piece_failures: HashMap::new(),
+            checkpoint_callback: Box::new(|_| {}),
+            last_checkpoint: Instant::now(),
+            last_timeout_check: Instant::now(),
         }
     }
 
     #[cfg(test)]
     fn with_block_length(mut self, block_length: u32) -> Self {
         self.request_emitter.set_block_length(block_length);
+        self.piece_composer.set_block_length(block_length);
         self
     }
 
@@ -66,43 +192,706 @@ impl<'a, T: RequestChannel + DownloadChannel> FileDownloader<'a, T> {
         self
     }
 
-    pub fn download(mut self) -> io::Result<Vec<u8>> {
+    pub fn with_checkpoint_callback(mut self, callback: impl FnMut(&Bitfield) + 'a) -> Self {
+        self.checkpoint_callback = Box::new(callback);
+        self
+    }
+
+    pub fn with_hash_pool(mut self, hash_pool: HashPool) -> Self {
+        self.hash_pool = Some(hash_pool);
+        self
+    }
+
+    pub fn with_stats_callback(mut self, callback: impl FnMut(&DownloadStats) + 'a) -> Self {
+        self.stats_callback = Box::new(callback);
+        self
+    }
+
+    pub fn with_choker_config(mut self, config: ChokerConfig) -> Self {
+        self.choker = Choker::new(config, Instant::now());
+        self
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn with_handle(mut self, handle: DownloadHandle) -> Self {
+        self.handle = handle;
+        self
+    }
+
+    pub fn with_streaming(mut self, streaming: StreamingHandle) -> Self {
         self.request_emitter
-            .request_first_blocks(Self::REQUEST_QUEUE_LENGTH, self.channel)?;
+            .set_priority_pieces(streaming.priority_pieces(None));
+        self.streaming = Some(streaming);
+        self
+    }
+
+    pub fn with_super_seeding(mut self) -> Self {
+        self.super_seeder = Some(SuperSeeder::new());
+        self
+    }
 
-        while self.tracker.has_more_pieces_to_download() {
+    pub fn with_piece_priorities(mut self, priorities: Vec<FilePriority>) -> Self {
+        self.tracker.skip_pieces(&priorities);
+        self.request_emitter.set_piece_priorities(priorities);
+        self
+    }
+
+    pub fn with_downloaded_pieces(mut self, pieces: &Bitfield) -> Self {
+        self.request_emitter.skip_pieces(pieces);
+        self.piece_composer.skip_pieces(pieces);
+        self.tracker.pieces_already_downloaded(pieces);
+        self
+    }
+
+    pub fn download(mut self) -> io::Result<TransferStats> {
+        if let Some(streaming) = &self.streaming {
+            streaming.pieces_verified(&self.tracker.downloaded);
+        }
+        while self.tracker.has_more_pieces_to_download() && !self.handle.is_cancelled() {
             self.tracker.waiting_for_block();
-            let block = self.channel.receive()?;
-            self.request_emitter.request_next_block(self.channel)?;
+            self.transfer_round()?;
+        }
+
+        self.checkpoint()?;
+        self.report_stats(Instant::now());
+        Ok(self.stats)
+    }
+
+    pub fn seed(mut self) -> io::Result<TransferStats> {
+        while !self.handle.is_cancelled() {
+            self.transfer_round()?;
+        }
+        self.report_stats(Instant::now());
+        Ok(self.stats)
+    }
+
+    fn transfer_round(&mut self) -> io::Result<()> {
+        self.pause_or_resume()?;
+        self.follow_stream_focus()?;
+        if self.disk_backlogged && !self.storage.is_backlogged() {
+            self.disk_backlogged = false;
+            self.fill_all_request_queues()?;
+        }
+        if self.hashes_pending() && !self.request_emitter.has_pending_requests() {
+            return match self.hash_pool.as_mut().and_then(HashPool::wait_result) {
+                Some(result) => self.piece_hashed(result),
+                None => Ok(()),
+            };
+        }
+
+        if let Some(event) = self.channel.receive(self.receive_timeout())? {
+            self.event_received(event)?;
+        }
+        while let Some(result) = self.hash_pool.as_mut().and_then(HashPool::try_result) {
+            self.piece_hashed(result)?;
+        }
+        if self.requests_throttled && !self.rate_limits.download.is_exhausted() {
+            self.requests_throttled = false;
+            self.fill_all_request_queues()?;
+        }
+        self.check_request_timeouts(Instant::now())?;
+        self.rechoke_if_due()?;
+        self.serve_uploads()?;
+        self.report_stats_if_due(Instant::now());
+        Ok(())
+    }
+
+    fn report_stats_if_due(&mut self, now: Instant) {
+        if self.stats_collector.is_sample_due(now) {
+            self.report_stats(now);
+        }
+    }
+
+    fn report_stats(&mut self, now: Instant) {
+        self.stats_collector.sample(now);
+        let stats = self.download_stats();
+        self.handle.publish_stats(stats.clone());
+        (self.stats_callback)(&stats);
+    }
+
+    fn download_stats(&self) -> DownloadStats {
+        let downloaded_bytes = self.tracker.downloaded_bytes as u64;
+        let total_bytes = self.tracker.wanted_bytes as u64;
+        let download_rate = self.stats_collector.download_rate();
+        let left_bytes = total_bytes - downloaded_bytes;
+        let eta = match (left_bytes, download_rate) {
+            (0, _) => Some(Duration::ZERO),
+            (_, 0) => None,
+            (left, rate) => Some(Duration::from_secs(left.div_ceil(rate))),
+        };
+        let peers = self
+            .stats_collector
+            .peer_rates()
+            .map(|(peer, rates)| {
+                let pipeline = self.pipelines.get(&peer);
+                PeerStats {
+                    peer,
+                    download_rate: rates.download_rate,
+                    upload_rate: rates.upload_rate,
+                    queue_depth: pipeline.map_or(0, RequestPipeline::queue_length),
+                    pending_requests: self.request_emitter.pending_count(peer),
+                    latency: pipeline.and_then(RequestPipeline::latency),
+                    snubbed: pipeline.is_some_and(RequestPipeline::is_snubbed),
+                }
+            })
+            .collect();
+
+        DownloadStats {
+            downloaded_bytes,
+            total_bytes,
+            uploaded_bytes: self.stats.uploaded_bytes,
+            download_rate,
+            upload_rate: self.stats_collector.upload_rate(),
+            eta,
+            wasted_bytes: self.stats_collector.wasted_bytes(),
+            hash_failures: self.stats_collector.hash_failures(),
+            peers,
+        }
+    }
+
+    fn pause_or_resume(&mut self) -> io::Result<()> {
+        let paused = self.handle.is_paused();
+        if paused == self.paused {
+            return Ok(());
+        }
+        self.paused = paused;
+        if paused {
+            info!("Download paused");
+            Ok(())
+        } else {
+            info!("Download resumed");
+            self.fill_all_request_queues()
+        }
+    }
+
+    fn follow_stream_focus(&mut self) -> io::Result<()> {
+        let Some(streaming) = &self.streaming else {
+            return Ok(());
+        };
+        let focus = streaming.focus();
+        if focus == self.stream_focus {
+            return Ok(());
+        }
+        debug!(?focus, "Streaming position moved");
+        self.stream_focus = focus;
+        self.request_emitter
+            .set_priority_pieces(streaming.priority_pieces(focus));
+        self.fill_all_request_queues()
+    }
+
+    fn hashes_pending(&self) -> bool {
+        self.hash_pool
+            .as_ref()
+            .is_some_and(|pool| pool.pending() > 0)
+    }
+
+    fn receive_timeout(&self) -> Duration {
+        let mut timeout = Self::RECEIVE_TIMEOUT;
+        if !self.upload_queue.is_empty() {
+            timeout = timeout.min(self.rate_limits.upload.delay());
+        }
+        if self.requests_throttled {
+            timeout = timeout.min(self.rate_limits.download.delay());
+        }
+        if self.disk_backlogged {
+            timeout = timeout.min(Self::DISK_BACKLOG_POLL);
+        }
+        timeout
+    }
+
+    fn event_received(&mut self, event: DownloadEvent) -> io::Result<()> {
+        match event {
+            DownloadEvent::PeerConnected {
+                peer,
+                state,
+                incoming,
+            } => {
+                let mut pipeline =
+                    RequestPipeline::new(self.request_emitter.block_length(), Instant::now());
+                pipeline.set_peer_limit(state.peer_request_queue);
+                self.pipelines.insert(peer, pipeline);
+                self.stats_collector.peer_connected(peer, Instant::now());
+                self.peers
+                    .insert(peer, state.with_piece_count(self.piece_hashes.len()));
+                self.choker.peer_connected(peer);
+                if incoming && self.super_seeder.is_none() {
+                    let bitfield = self.tracker.downloaded.as_bytes().to_vec();
+                    self.send(peer, &PeerMessage::Bitfield(bitfield))?;
+                }
+                self.update_interest(peer)?;
+                self.fill_request_queue(peer)?;
+                self.offer_piece(peer)
+            }
+            DownloadEvent::Message(peer, msg) => self.message_received(peer, msg),
+            DownloadEvent::PeerDisconnected(peer) => {
+                let was_unchoked = self
+                    .peers
+                    .remove(&peer)
+                    .is_some_and(|state| !state.am_choking);
+                self.pipelines.remove(&peer);
+                self.stats_collector.peer_disconnected(peer);
+                self.choker.peer_disconnected(peer);
+                if let Some(super_seeder) = &mut self.super_seeder {
+                    super_seeder.peer_disconnected(peer);
+                }
+                self.upload_queue.clear(peer);
+                if was_unchoked {
+                    self.rechoke()?;
+                }
+                self.request_emitter.requests_dropped(peer);
+                self.fill_all_request_queues()
+            }
+        }
+    }
+
+    fn message_received(&mut self, peer: PeerKey, msg: PeerMessage) -> io::Result<()> {
+        let Some(state) = self.peers.get_mut(&peer) else {
+            return Ok(());
+        };
+        if let Err(err) = state.message_received(&msg) {
+            warn!(peer, %err, "Disconnecting misbehaving peer");
+            self.channel.disconnect(peer);
+            return Ok(());
+        }
+
+        match msg {
+            PeerMessage::Piece {
+                piece_index,
+                offset,
+                block,
+            } => self.block_received(
+                peer,
+                Block {
+                    piece_index,
+                    offset,
+                    data: block,
+                },
+            ),
+            PeerMessage::Choke => {
+                self.request_emitter.requests_dropped(peer);
+                if let Some(pipeline) = self.pipelines.get_mut(&peer) {
+                    pipeline.requests_dropped();
+                }
+                self.fill_all_request_queues()
+            }
+            PeerMessage::Unchoke => self.fill_request_queue(peer),
+            PeerMessage::Have(piece_index) => {
+                self.update_interest(peer)?;
+                self.fill_request_queue(peer)?;
+                self.piece_announced(peer, piece_index)
+            }
+            PeerMessage::Bitfield(_) => {
+                self.update_interest(peer)?;
+                self.fill_request_queue(peer)?;
+                self.offer_piece(peer)
+            }
+            PeerMessage::Interested | PeerMessage::NotInterested => self.rechoke(),
+            PeerMessage::Extended { .. } => {
+                if let Some(pipeline) = self.pipelines.get_mut(&peer) {
+                    pipeline.set_peer_limit(state.peer_request_queue);
+                }
+                self.fill_request_queue(peer)
+            }
+            PeerMessage::Request {
+                piece_index,
+                offset,
+                length,
+            } => {
+                self.upload_requested(
+                    peer,
+                    BlockRequest {
+                        piece_index,
+                        offset,
+                        length,
+                    },
+                );
+                Ok(())
+            }
+            PeerMessage::Cancel {
+                piece_index,
+                offset,
+                length,
+            } => {
+                self.upload_queue.cancel(
+                    peer,
+                    BlockRequest {
+                        piece_index,
+                        offset,
+                        length,
+                    },
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_request_timeouts(&mut self, now: Instant) -> io::Result<()> {
+        if now.saturating_duration_since(self.last_timeout_check) < Self::TIMEOUT_CHECK_INTERVAL {
+            return Ok(());
+        }
+        self.last_timeout_check = now;
+
+        let mut requests_timed_out = false;
+        for (peer, pipeline) in &mut self.pipelines {
+            let was_snubbed = pipeline.is_snubbed();
+            for (piece_index, offset) in pipeline.check_timeouts(now) {
+                debug!(peer, piece_index, offset, "Block request timed out");
+                self.request_emitter
+                    .request_timed_out(*peer, piece_index, offset);
+                requests_timed_out = true;
+            }
+            if pipeline.is_snubbed() && !was_snubbed {
+                info!(peer, "Peer snubbed us, limiting requests to it");
+            }
+            if pipeline.is_stalled(now) {
+                info!(peer, "Disconnecting peer that stopped sending blocks");
+                pipeline.requests_dropped();
+                self.channel.disconnect(*peer);
+            }
+        }
+        if requests_timed_out {
+            self.fill_all_request_queues()?;
+        }
+        Ok(())
+    }
+
+    fn rechoke_if_due(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        if !self.choker.is_round_due(now) {
+            return Ok(());
+        }
+        let unchoked = self
+            .choker
+            .next_round(&self.interested_peers(), self.is_seeding(), now);
+        self.apply_choking(&unchoked)
+    }
+
+    fn rechoke(&mut self) -> io::Result<()> {
+        let unchoked = self
+            .choker
+            .unchoked_peers(&self.interested_peers(), self.is_seeding());
+        self.apply_choking(&unchoked)
+    }
+
+    fn interested_peers(&self) -> Vec<PeerKey> {
+        self.peers
+            .iter()
+            .filter(|(_, state)| state.peer_interested)
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    fn is_seeding(&self) -> bool {
+        !self.tracker.has_more_pieces_to_download()
+    }
 
-            if let Some(piece) = self.piece_composer.append_block(&block)? {
-                self.verify_piece_hash(&piece)?;
-                self.tracker.append_piece(&piece);
+    fn apply_choking(&mut self, unchoked: &[PeerKey]) -> io::Result<()> {
+        let (unchoked, choked): (Vec<_>, Vec<_>) = self
+            .peers
+            .keys()
+            .copied()
+            .partition(|peer| unchoked.contains(peer));
+        for peer in choked {
+            self.set_choking(peer, true)?;
+        }
+        for peer in unchoked {
+            self.set_choking(peer, false)?;
+        }
+        Ok(())
+    }
+
+    fn set_choking(&mut self, peer: PeerKey, choking: bool) -> io::Result<()> {
+        match self.peers.get(&peer) {
+            Some(state) if state.am_choking != choking => (),
+            _ => return Ok(()),
+        }
+        if choking {
+            self.upload_queue.clear(peer);
+            self.send(peer, &PeerMessage::Choke)
+        } else {
+            self.send(peer, &PeerMessage::Unchoke)
+        }
+    }
+
+    fn upload_requested(&mut self, peer: PeerKey, request: BlockRequest) {
+        let Some(state) = self.peers.get(&peer) else {
+            return;
+        };
+        if state.am_choking || !self.is_valid_upload_request(peer, &request) {
+            return;
+        }
+        self.upload_queue.push(peer, request);
+    }
+
+    fn is_valid_upload_request(&self, peer: PeerKey, request: &BlockRequest) -> bool {
+        let offered = self
+            .super_seeder
+            .as_ref()
+            .is_none_or(|super_seeder| super_seeder.is_offered(peer, request.piece_index));
+        offered
+            && request.piece_index < self.tracker.file_info.piece_count()
+            && self.tracker.downloaded.has_piece(request.piece_index)
+            && request.length > 0
+            && request.length <= Self::MAX_UPLOAD_BLOCK_LENGTH
+            && request.offset as u64 + request.length as u64
+                <= self.tracker.file_info.piece_length(request.piece_index) as u64
+    }
+
+    fn serve_uploads(&mut self) -> io::Result<()> {
+        for _ in 0..Self::UPLOADS_PER_ROUND {
+            if self.rate_limits.upload.is_exhausted() {
+                break;
+            }
+            let Some((peer, request)) = self.upload_queue.pop() else {
+                break;
+            };
+            let block = match self.storage.read_block(
+                request.piece_index,
+                request.offset,
+                request.length,
+            ) {
+                Ok(block) => block,
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    debug!(peer, piece_index = request.piece_index, %e, "Cannot serve request");
+                    continue;
+                }
+                Err(e) => {
+                    warn!(peer, piece_index = request.piece_index, %e, "Failed to read requested block");
+                    continue;
+                }
+            };
+            self.send(
+                peer,
+                &PeerMessage::Piece {
+                    piece_index: request.piece_index,
+                    offset: request.offset,
+                    block,
+                },
+            )?;
+            self.rate_limits.upload.consume(request.length as usize);
+            self.choker.bytes_uploaded(peer, request.length as u64);
+            self.stats_collector
+                .bytes_uploaded(peer, request.length as u64);
+            self.stats.uploaded_bytes += request.length as u64;
+        }
+        Ok(())
+    }
+
+    fn offer_piece(&mut self, peer: PeerKey) -> io::Result<()> {
+        let availability = self.piece_availability();
+        let (Some(super_seeder), Some(state)) = (&mut self.super_seeder, self.peers.get(&peer))
+        else {
+            return Ok(());
+        };
+        match super_seeder.make_offer(peer, &state.peer_pieces, &availability) {
+            Some(piece_index) => self.send(peer, &PeerMessage::Have(piece_index)),
+            None => Ok(()),
+        }
+    }
+
+    fn piece_announced(&mut self, peer: PeerKey, piece_index: u32) -> io::Result<()> {
+        let Some(super_seeder) = &mut self.super_seeder else {
+            return Ok(());
+        };
+        for propagated_to in super_seeder.piece_announced(peer, piece_index) {
+            self.offer_piece(propagated_to)?;
+        }
+        Ok(())
+    }
+
+    fn piece_availability(&self) -> Vec<usize> {
+        (0..self.tracker.file_info.piece_count())
+            .map(|index| {
+                self.peers
+                    .values()
+                    .filter(|state| state.peer_pieces.has_piece(index))
+                    .count()
+            })
+            .collect()
+    }
+
+    fn broadcast_have(&mut self, piece_index: u32) -> io::Result<()> {
+        let peers = self.peers.keys().copied().collect::<Vec<_>>();
+        for peer in peers {
+            self.send(peer, &PeerMessage::Have(piece_index))?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, peer: PeerKey, msg: &PeerMessage) -> io::Result<()> {
+        self.channel.send(peer, msg)?;
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.message_sent(msg);
+        }
+        Ok(())
+    }
+
+    fn update_interest(&mut self, peer: PeerKey) -> io::Result<()> {
+        let Some(state) = self.peers.get(&peer) else {
+            return Ok(());
+        };
+        let interested = self.tracker.wants_any_of(&state.peer_pieces);
+        if interested && !state.am_interested {
+            self.send(peer, &PeerMessage::Interested)?;
+        } else if !interested && state.am_interested {
+            self.send(peer, &PeerMessage::NotInterested)?;
+        }
+        Ok(())
+    }
+
+    fn fill_request_queue(&mut self, peer: PeerKey) -> io::Result<()> {
+        let (Some(state), Some(pipeline)) = (self.peers.get(&peer), self.pipelines.get_mut(&peer))
+        else {
+            return Ok(());
+        };
+        if self.paused || state.peer_choking || !state.am_interested {
+            return Ok(());
+        }
+        if self.rate_limits.download.is_exhausted() {
+            self.requests_throttled = true;
+            return Ok(());
+        }
+        if self.storage.is_backlogged() {
+            self.disk_backlogged = true;
+            return Ok(());
+        }
+
+        let queue_length = pipeline.queue_length();
+        let mut requests = PeerRequests {
+            channel: &mut *self.channel,
+            peer,
+            pipeline,
+        };
+        self.request_emitter.fill_request_queue(
+            peer,
+            &state.peer_pieces,
+            queue_length,
+            &mut requests,
+        )
+    }
+
+    fn fill_all_request_queues(&mut self) -> io::Result<()> {
+        let peers = &self.peers;
+        self.request_emitter
+            .release_timed_out_requests(|timed_out_peer, piece_index| {
+                peers.iter().any(|(peer, state)| {
+                    *peer != timed_out_peer
+                        && !state.peer_choking
+                        && state.peer_pieces.has_piece(piece_index)
+                })
+            });
+        let peers = self.peers.keys().copied().collect::<Vec<_>>();
+        for peer in peers {
+            self.fill_request_queue(peer)?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        self.storage.flush()?;
+        (self.checkpoint_callback)(&self.tracker.downloaded);
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    fn block_received(&mut self, peer: PeerKey, block: Block) -> io::Result<()> {
+        let block_length = block.data.len() as u64;
+        if self.piece_composer.is_duplicate(&block) {
+            self.stats_collector.bytes_wasted(block_length);
+        }
+        self.request_emitter
+            .block_received(block.piece_index, block.offset);
+        self.choker.bytes_downloaded(peer, block_length);
+        self.stats_collector.bytes_downloaded(peer, block_length);
+        if let Some(pipeline) = self.pipelines.get_mut(&peer) {
+            pipeline.block_received(
+                block.piece_index,
+                block.offset,
+                block.data.len(),
+                Instant::now(),
+            );
+        }
+        self.fill_request_queue(peer)?;
+
+        let Some(piece) = self.piece_composer.append_block(&block)? else {
+            return Ok(());
+        };
+        let expected = self.piece_hashes[piece.index as usize];
+        match piece.hash {
+            Some(hash) => self.piece_hashed(HashResult {
+                piece_index: piece.index,
+                is_valid: hash == expected,
+                data: piece.data,
+            }),
+            None => {
+                self.hash_pool.get_or_insert_with(HashPool::default).submit(
+                    piece.index,
+                    piece.data,
+                    expected,
+                );
+                Ok(())
             }
         }
+    }
 
-        Ok(self.tracker.buffer)
+    fn piece_hashed(&mut self, result: HashResult) -> io::Result<()> {
+        let piece = Piece {
+            index: result.piece_index,
+            data: result.data,
+            hash: None,
+        };
+        if !result.is_valid {
+            return self.piece_hash_failed(&piece);
+        }
+        self.storage.write_piece(piece.index, &piece.data)?;
+        if let Some(streaming) = &self.streaming {
+            streaming.piece_verified(piece.index);
+        }
+        self.tracker.piece_downloaded(&piece);
+        self.stats.downloaded_bytes += piece.data.len() as u64;
+        self.broadcast_have(piece.index)?;
+        if self.last_checkpoint.elapsed() >= Self::CHECKPOINT_INTERVAL {
+            self.checkpoint()?;
+        }
+        Ok(())
     }
 
-    fn verify_piece_hash(&self, piece: &Piece) -> io::Result<()> {
-        let piece_hash = &self.piece_hashes[piece.index as usize];
-        if !piece_hash.verify(&piece.data) {
+    fn piece_hash_failed(&mut self, piece: &Piece) -> io::Result<()> {
+        self.stats_collector.hash_failed(piece.data.len() as u64);
+        let failures = self.piece_failures.entry(piece.index).or_default();
+        *failures += 1;
+        if *failures >= Self::MAX_PIECE_HASH_FAILURES {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Downloaded piece does not match expected hash",
             ));
         }
-        Ok(())
+
+        warn!(
+            piece_index = piece.index,
+            "Downloaded piece does not match expected hash"
+        );
+        self.piece_composer.piece_failed(piece.index);
+        self.request_emitter.piece_failed(piece.index);
+        self.fill_all_request_queues()
     }
 }
 
 struct DownloadTracker<'a> {
     progress_callback: Box<dyn FnMut(usize, usize) + 'a>,
     start_timestamp: Option<Instant>,
+    downloaded: Bitfield,
     downloaded_pieces: u32,
     downloaded_bytes: usize,
+    skipped: Bitfield,
+    wanted_pieces: u32,
+    wanted_bytes: usize,
     file_info: FileInfo,
-    buffer: Vec<u8>,
 }
 
 impl<'a> DownloadTracker<'a> {
@@ -110,10 +899,13 @@ impl<'a> DownloadTracker<'a> {
         Self {
             file_info,
             start_timestamp: None,
+            downloaded: Bitfield::new(file_info.piece_count() as usize),
             downloaded_pieces: 0,
             downloaded_bytes: 0,
+            skipped: Bitfield::new(file_info.piece_count() as usize),
+            wanted_pieces: file_info.piece_count(),
+            wanted_bytes: file_info.file_length,
             progress_callback: Box::new(|_, _| {}),
-            buffer: vec![0; file_info.file_length],
         }
     }
 
@@ -124,29 +916,76 @@ impl<'a> DownloadTracker<'a> {
     }
 
     fn has_more_pieces_to_download(&self) -> bool {
-        self.downloaded_pieces < self.file_info.piece_count()
+        self.downloaded_pieces < self.wanted_pieces
+    }
+
+    fn is_wanted(&self, piece_index: u32) -> bool {
+        !self.skipped.has_piece(piece_index)
+    }
+
+    fn wants_any_of(&self, peer_pieces: &Bitfield) -> bool {
+        (0..self.file_info.piece_count()).any(|index| {
+            peer_pieces.has_piece(index)
+                && !self.downloaded.has_piece(index)
+                && self.is_wanted(index)
+        })
+    }
+
+    fn pieces_already_downloaded(&mut self, pieces: &Bitfield) {
+        for index in 0..self.file_info.piece_count() {
+            if pieces.has_piece(index) {
+                self.downloaded.set_piece(index);
+            }
+        }
+        self.count_pieces();
+    }
+
+    fn skip_pieces(&mut self, priorities: &[FilePriority]) {
+        for (index, priority) in priorities.iter().enumerate() {
+            if *priority == FilePriority::Skip {
+                self.skipped.set_piece(index as u32);
+            }
+        }
+        self.count_pieces();
     }
 
-    fn append_piece(&mut self, piece: &Piece) {
-        let (piece_start, piece_end) = self.file_info.piece_bounds(piece.index);
-        self.buffer[piece_start..piece_end].copy_from_slice(&piece.data);
-        self.piece_downloaded(piece);
+    fn count_pieces(&mut self) {
+        let wanted = (0..self.file_info.piece_count())
+            .filter(|index| self.is_wanted(*index))
+            .collect::<Vec<_>>();
+        let bytes = |index: &u32| self.file_info.piece_length(*index) as usize;
+        let downloaded = || {
+            wanted
+                .iter()
+                .filter(|index| self.downloaded.has_piece(**index))
+        };
+        self.wanted_pieces = wanted.len() as u32;
+        self.wanted_bytes = wanted.iter().map(bytes).sum();
+        self.downloaded_pieces = downloaded().count() as u32;
+        self.downloaded_bytes = downloaded().map(bytes).sum();
     }
 
     fn piece_downloaded(&mut self, piece: &Piece) {
-        self.downloaded_pieces += 1;
-        self.downloaded_bytes += piece.data.len();
+        self.downloaded.set_piece(piece.index);
+        if self.is_wanted(piece.index) {
+            self.downloaded_pieces += 1;
+            self.downloaded_bytes += piece.data.len();
+        }
 
-        (self.progress_callback)(self.downloaded_bytes, self.file_info.file_length);
+        (self.progress_callback)(self.downloaded_bytes, self.wanted_bytes);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use piece_composer::unexpected_block_offset;
-    use std::collections::VecDeque;
+    use std::{cell::Cell, collections::VecDeque, rc::Rc};
 
-    use crate::types::Sha1;
+    use crate::{
+        storage::{MemoryStorage, StorageLayout},
+        torrent::FileEntry,
+        types::Sha1,
+    };
 
     use super::*;
 
@@ -166,12 +1005,18 @@ mod tests {
             .collect::<Vec<_>>();
 
         let mut channel = DownloadChannelFromVector::new(pieces.clone());
-        let downloaded_data =
-            FileDownloader::new(&mut channel, piece_hashes, piece_length, file_data.len())
-                .with_block_length(3)
-                .download()
-                .unwrap();
-        assert_eq!(file_data, downloaded_data);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+        assert_eq!(file_data, storage.content());
     }
 
     #[test]
@@ -191,12 +1036,18 @@ mod tests {
             .collect::<Vec<_>>();
 
         let mut channel = DownloadChannelFromVector::new(pieces.clone());
-        let downloaded_data =
-            FileDownloader::new(&mut channel, piece_hashes, piece_length, file_data.len())
-                .with_block_length(3)
-                .download()
-                .unwrap();
-        assert_eq!(file_data, downloaded_data);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+        assert_eq!(file_data, storage.content());
     }
 
     #[test]
@@ -218,11 +1069,18 @@ mod tests {
         let mut reported_progress: Vec<(usize, usize)> = vec![];
 
         let mut channel = DownloadChannelFromVector::new(pieces.clone());
-        FileDownloader::new(&mut channel, piece_hashes, piece_length, file_data.len())
-            .with_progress_callback(|downloaded, total| reported_progress.push((downloaded, total)))
-            .with_block_length(3)
-            .download()
-            .unwrap();
+        let mut storage = MemoryStorage::new(piece_length, file_length);
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_progress_callback(|downloaded, total| reported_progress.push((downloaded, total)))
+        .with_block_length(3)
+        .download()
+        .unwrap();
 
         assert_eq!(
             reported_progress,
@@ -235,89 +1093,1370 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn test_downloaded_piece_does_not_match_expected_hash() {
-        let pieces = vec![
-            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
-            vec![11, 12, 13, 14, 15, 16, 17, 18, 19, 20],
-        ];
-        let piece_hashes = pieces.iter().map(|_p| zero_sha1()).collect::<Vec<_>>();
+    fn test_download_all_pieces_when_blocks_arrive_out_of_order() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces
+            .iter()
+            .map(|p| Sha1::calculate(p))
+            .collect::<Vec<_>>();
 
-        let mut channel = DownloadChannelFromVector::new(pieces.clone());
-        FileDownloader::new(&mut channel, piece_hashes, 10, 20)
-            .with_block_length(3)
-            .download()
-            .unwrap();
+        let mut channel = DownloadChannelFromVector::new(pieces.clone()).answer_in_reverse();
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+        assert_eq!(file_data, storage.content());
     }
 
     #[test]
-    fn test_unexpected_offset_in_response() {
-        let mut channel = ErrorDownloadChannel {
-            block_to_send: Block {
-                piece_index: 0,
-                offset: 1,
-                data: vec![0xff; 3],
-            },
+    fn test_pause_requests_while_choked_and_reissue_after_unchoke() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces
+            .iter()
+            .map(|p| Sha1::calculate(p))
+            .collect::<Vec<_>>();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone()).choke_after(2);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+        assert_eq!(file_data, storage.content());
+        assert_eq!(0, channel.requests_while_choked);
+    }
+
+    #[test]
+    fn test_download_only_missing_pieces() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces
+            .iter()
+            .map(|p| Sha1::calculate(p))
+            .collect::<Vec<_>>();
+        let mut downloaded_pieces = Bitfield::new(pieces.len());
+        downloaded_pieces.set_piece(1);
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        storage.write_piece(1, &pieces[1]).unwrap();
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&downloaded_pieces)
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        assert_eq!(file_data, storage.content());
+        assert!(
+            channel
+                .requested_pieces
+                .iter()
+                .all(|(_, index)| *index != 1)
+        );
+    }
+
+    #[test]
+    fn test_download_only_wanted_pieces_in_priority_order() {
+        let file_data = (1..=50).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let stats = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(piece_length)
+        .with_piece_priorities(vec![
+            FilePriority::Low,
+            FilePriority::Skip,
+            FilePriority::High,
+            FilePriority::Skip,
+            FilePriority::Normal,
+        ])
+        .download()
+        .unwrap();
+
+        let requested = channel
+            .requested_pieces
+            .iter()
+            .map(|(_, index)| *index)
+            .collect::<Vec<_>>();
+        assert_eq!(requested, vec![2, 4, 0]);
+        assert_eq!(stats.downloaded_bytes, 30);
+    }
+
+    #[test]
+    fn test_request_first_and_last_pieces_first_when_streaming() {
+        let file_data = (1..=50).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let layout = StorageLayout::new(
+            piece_length,
+            vec![FileEntry {
+                path: "data.bin".into(),
+                length: file_data.len(),
+                ..Default::default()
+            }],
+        );
+        let streaming = StreamingHandle::new(&layout);
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(piece_length)
+        .with_streaming(streaming.clone())
+        .download()
+        .unwrap();
+
+        let mut requested = channel
+            .requested_pieces
+            .iter()
+            .map(|(_, index)| *index)
+            .collect::<Vec<_>>();
+        requested.dedup();
+        assert_eq!(requested, vec![0, 4, 1, 2, 3]);
+        assert!((0..5).all(|index| streaming.is_verified(index)));
+    }
+
+    #[test]
+    fn test_report_downloaded_pieces_on_completion() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces
+            .iter()
+            .map(|p| Sha1::calculate(p))
+            .collect::<Vec<_>>();
+        let mut checkpoints = vec![];
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_checkpoint_callback(|pieces| checkpoints.push(pieces.clone()))
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        assert_eq!(
+            checkpoints.last(),
+            Some(&Bitfield::from_bytes(&[0b1110_0000]))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_downloaded_piece_does_not_match_expected_hash() {
+        let pieces = vec![
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            vec![11, 12, 13, 14, 15, 16, 17, 18, 19, 20],
+        ];
+        let piece_hashes = pieces.iter().map(|_p| zero_sha1()).collect::<Vec<_>>();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(10, 20);
+        FileDownloader::new(&mut channel, &mut storage, piece_hashes, 10, 20)
+            .with_block_length(3)
+            .download()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unexpected_offset_in_response() {
+        let mut channel = ErrorDownloadChannel {
+            connected: false,
+            block_to_send: Block {
+                piece_index: 0,
+                offset: 1,
+                data: vec![0xff; 3],
+            },
         };
 
-        let error = FileDownloader::new(&mut channel, vec![Sha1::new([0; 20])], 3, 3)
+        let mut storage = MemoryStorage::new(3, 3);
+        let error = FileDownloader::new(&mut channel, &mut storage, vec![Sha1::new([0; 20])], 3, 3)
             .with_block_length(3)
             .download()
             .unwrap_err();
         assert_eq!(unexpected_block_offset(0, 1).to_string(), error.to_string());
     }
 
+    #[test]
+    fn test_download_pieces_from_multiple_peers() {
+        let file_data = (1..=40).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone()).with_peers(vec![
+            Bitfield::from_bytes(&[0b1100_0000]),
+            Bitfield::from_bytes(&[0b0011_0000]),
+        ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        assert_eq!(file_data, storage.content());
+        let mut pieces_by_peer = channel.requested_pieces.clone();
+        pieces_by_peer.sort();
+        pieces_by_peer.dedup();
+        assert_eq!(pieces_by_peer, vec![(0, 0), (0, 1), (1, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn test_reissue_requests_of_disconnected_peer_to_other_peers() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), all_pieces(pieces.len())])
+            .disconnect_after(0, 2);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        assert_eq!(file_data, storage.content());
+    }
+
+    #[test]
+    fn test_send_bitfield_and_interest_to_incoming_peer() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let mut downloaded_pieces = Bitfield::new(pieces.len());
+        downloaded_pieces.set_piece(1);
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone()).incoming();
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        storage.write_piece(1, &pieces[1]).unwrap();
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&downloaded_pieces)
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        assert_eq!(file_data, storage.content());
+        assert_eq!(
+            channel.sent_messages,
+            vec![
+                (0, PeerMessage::Bitfield(vec![0b0100_0000])),
+                (0, PeerMessage::Interested),
+                (0, PeerMessage::Have(0)),
+                (0, PeerMessage::Have(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_serve_requests_of_unchoked_peers() {
+        let file_data = (1..=25).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let mut downloaded_pieces = Bitfield::new(pieces.len());
+        downloaded_pieces.set_piece(1);
+        let request = |piece_index, offset| {
+            DownloadEvent::Message(
+                1,
+                PeerMessage::Request {
+                    piece_index,
+                    offset,
+                    length: 3,
+                },
+            )
+        };
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), Bitfield::new(pieces.len())])
+            .with_scripted_events(vec![
+                request(1, 0),
+                DownloadEvent::Message(1, PeerMessage::Interested),
+                request(1, 0),
+                request(1, 3),
+                request(0, 0),
+            ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        storage.write_piece(1, &pieces[1]).unwrap();
+        let stats = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&downloaded_pieces)
+        .with_block_length(3)
+        .download()
+        .unwrap();
+
+        let sent_to_leecher = channel
+            .sent_messages
+            .iter()
+            .filter(|(peer, msg)| *peer == 1 && !matches!(msg, PeerMessage::Have(_)))
+            .map(|(_, msg)| msg.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent_to_leecher,
+            vec![
+                PeerMessage::NotInterested,
+                PeerMessage::Unchoke,
+                PeerMessage::Piece {
+                    piece_index: 1,
+                    offset: 0,
+                    block: vec![11, 12, 13],
+                },
+                PeerMessage::Piece {
+                    piece_index: 1,
+                    offset: 3,
+                    block: vec![14, 15, 16],
+                },
+            ]
+        );
+        assert_eq!(stats.uploaded_bytes, 6);
+        assert_eq!(stats.downloaded_bytes, 15);
+    }
+
+    #[test]
+    fn test_disconnect_peer_announcing_piece_out_of_range() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), Bitfield::new(pieces.len())])
+            .with_scripted_events(vec![DownloadEvent::Message(1, PeerMessage::Have(u32::MAX))]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .download()
+        .unwrap();
+
+        assert_eq!(channel.disconnected_peers, vec![1]);
+        assert_eq!(storage.content(), &file_data[..]);
+    }
+
+    #[test]
+    fn test_unchoke_no_more_peers_than_upload_slots() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let leecher = Bitfield::new(pieces.len());
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), leecher.clone(), leecher])
+            .with_scripted_events(vec![
+                DownloadEvent::Message(1, PeerMessage::Interested),
+                DownloadEvent::Message(2, PeerMessage::Interested),
+                DownloadEvent::Message(1, PeerMessage::NotInterested),
+            ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_choker_config(ChokerConfig {
+            upload_slots: 1,
+            optimistic_slots: 0,
+        })
+        .with_block_length(5)
+        .download()
+        .unwrap();
+
+        let choking_messages = channel
+            .sent_messages
+            .iter()
+            .filter(|(_, msg)| matches!(msg, PeerMessage::Choke | PeerMessage::Unchoke))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            choking_messages,
+            vec![
+                (1, PeerMessage::Unchoke),
+                (1, PeerMessage::Choke),
+                (2, PeerMessage::Unchoke),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seed_complete_data_to_incoming_peer() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![Bitfield::new(pieces.len())])
+            .incoming()
+            .with_scripted_events(vec![
+                DownloadEvent::Message(0, PeerMessage::Interested),
+                DownloadEvent::Message(
+                    0,
+                    PeerMessage::Request {
+                        piece_index: 1,
+                        offset: 5,
+                        length: 5,
+                    },
+                ),
+            ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        storage.write_piece(0, &pieces[0]).unwrap();
+        storage.write_piece(1, &pieces[1]).unwrap();
+        let err = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&all_pieces(pieces.len()))
+        .seed()
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "No block requested");
+        assert_eq!(
+            channel.sent_messages,
+            vec![
+                (0, PeerMessage::Bitfield(vec![0b1100_0000])),
+                (0, PeerMessage::Unchoke),
+                (
+                    0,
+                    PeerMessage::Piece {
+                        piece_index: 1,
+                        offset: 5,
+                        block: vec![16, 17, 18, 19, 20],
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keep_seeding_when_requested_block_cannot_be_read() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let request = |piece_index| {
+            DownloadEvent::Message(
+                0,
+                PeerMessage::Request {
+                    piece_index,
+                    offset: 0,
+                    length: 2,
+                },
+            )
+        };
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![Bitfield::new(pieces.len())])
+            .incoming()
+            .with_scripted_events(vec![
+                DownloadEvent::Message(0, PeerMessage::Interested),
+                request(0),
+                request(1),
+            ]);
+        let mut inner = MemoryStorage::new(piece_length, file_data.len());
+        inner.write_piece(1, &pieces[1]).unwrap();
+        let mut storage = UnreadablePieceStorage {
+            inner,
+            unreadable_piece: 0,
+        };
+        let err = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&all_pieces(pieces.len()))
+        .seed()
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "No block requested");
+        let uploaded = channel
+            .sent_messages
+            .iter()
+            .filter(|(_, msg)| matches!(msg, PeerMessage::Piece { .. }))
+            .map(|(_, msg)| msg.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uploaded,
+            vec![PeerMessage::Piece {
+                piece_index: 1,
+                offset: 0,
+                block: vec![11, 12],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_super_seed_offers_one_piece_per_peer_until_it_propagates() {
+        let file_data = (1..=30).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let request = |piece_index| {
+            DownloadEvent::Message(
+                0,
+                PeerMessage::Request {
+                    piece_index,
+                    offset: 0,
+                    length: 10,
+                },
+            )
+        };
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![
+                Bitfield::new(pieces.len()),
+                Bitfield::new(pieces.len()),
+            ])
+            .incoming()
+            .with_scripted_events(vec![
+                DownloadEvent::Message(0, PeerMessage::Interested),
+                request(1),
+                request(0),
+                DownloadEvent::Message(0, PeerMessage::Have(0)),
+                DownloadEvent::Message(1, PeerMessage::Have(0)),
+            ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        for (index, piece) in pieces.iter().enumerate() {
+            storage.write_piece(index as u32, piece).unwrap();
+        }
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&all_pieces(pieces.len()))
+        .with_super_seeding()
+        .seed()
+        .unwrap_err();
+
+        assert_eq!(
+            channel.sent_messages,
+            vec![
+                (0, PeerMessage::Have(0)),
+                (1, PeerMessage::Have(1)),
+                (0, PeerMessage::Unchoke),
+                (
+                    0,
+                    PeerMessage::Piece {
+                        piece_index: 0,
+                        offset: 0,
+                        block: pieces[0].clone(),
+                    }
+                ),
+                (0, PeerMessage::Have(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stop_serving_uploads_when_upload_limit_is_reached() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let request = |piece_index| {
+            DownloadEvent::Message(
+                0,
+                PeerMessage::Request {
+                    piece_index,
+                    offset: 0,
+                    length: 5,
+                },
+            )
+        };
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![Bitfield::new(pieces.len())])
+            .with_scripted_events(vec![
+                DownloadEvent::Message(0, PeerMessage::Interested),
+                request(0),
+                request(1),
+            ]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        storage.write_piece(0, &pieces[0]).unwrap();
+        storage.write_piece(1, &pieces[1]).unwrap();
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_downloaded_pieces(&all_pieces(pieces.len()))
+        .with_rate_limits(RateLimits::new(None, Some(4)))
+        .seed()
+        .unwrap_err();
+
+        let uploaded_pieces = channel
+            .sent_messages
+            .iter()
+            .filter(|(_, msg)| matches!(msg, PeerMessage::Piece { .. }))
+            .count();
+        assert_eq!(uploaded_pieces, 1);
+    }
+
+    #[test]
+    fn test_reassign_timed_out_requests_and_disconnect_stalled_peer() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), all_pieces(pieces.len())]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let mut downloader = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5);
+        downloader.transfer_round().unwrap();
+        downloader.transfer_round().unwrap();
+        downloader
+            .check_request_timeouts(Instant::now() + RequestPipeline::DISCONNECT_TIMEOUT)
+            .unwrap();
+        drop(downloader);
+
+        assert_eq!(
+            channel.requested_pieces,
+            vec![
+                (0, 0),
+                (0, 0),
+                (0, 1),
+                (0, 1),
+                (1, 0),
+                (1, 0),
+                (1, 1),
+                (1, 1)
+            ]
+        );
+        assert!(matches!(
+            channel.scripted_events.back(),
+            Some(DownloadEvent::PeerDisconnected(0))
+        ));
+    }
+
+    #[test]
+    fn test_reissue_timed_out_requests_to_single_peer() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len())]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let mut downloader = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5);
+        downloader.transfer_round().unwrap();
+        downloader
+            .check_request_timeouts(Instant::now() + RequestPipeline::REQUEST_TIMEOUT)
+            .unwrap();
+        drop(downloader);
+
+        assert_eq!(
+            channel.requested_pieces,
+            vec![
+                (0, 0),
+                (0, 0),
+                (0, 1),
+                (0, 1),
+                (0, 0),
+                (0, 0),
+                (0, 1),
+                (0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_pieces_received_far_out_of_order_on_hash_pool() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let mut downloader = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(1)
+        .with_hash_pool(HashPool::new(1));
+        let block = |piece_index: u32, offset: u32| Block {
+            piece_index,
+            offset,
+            data: vec![pieces[piece_index as usize][offset as usize]],
+        };
+
+        for offset in 0..piece_length {
+            downloader.block_received(0, block(0, offset)).unwrap();
+        }
+        assert_eq!(downloader.hash_pool.as_ref().unwrap().pending(), 0);
+        assert!(downloader.tracker.downloaded.has_piece(0));
+
+        for offset in (0..piece_length).rev() {
+            downloader.block_received(0, block(1, offset)).unwrap();
+        }
+        assert_eq!(downloader.hash_pool.as_ref().unwrap().pending(), 1);
+        let result = downloader
+            .hash_pool
+            .as_mut()
+            .unwrap()
+            .wait_result()
+            .unwrap();
+        assert_eq!((result.piece_index, result.is_valid), (1, true));
+    }
+
+    #[test]
+    fn test_request_blocks_only_while_not_paused() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let handle = DownloadHandle::new();
+        handle.pause();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let mut downloader = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5)
+        .with_handle(handle.clone());
+        downloader.transfer_round().unwrap();
+        assert!(downloader.transfer_round().is_err());
+
+        handle.resume();
+        downloader.download().unwrap();
+        assert_eq!(file_data, storage.content());
+    }
+
+    struct BackloggedStorage {
+        inner: MemoryStorage,
+        backlogged: Rc<Cell<bool>>,
+    }
+
+    impl Storage for BackloggedStorage {
+        fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+            self.inner.write_piece(piece_index, data)
+        }
+
+        fn read_block(
+            &mut self,
+            piece_index: u32,
+            offset: u32,
+            length: u32,
+        ) -> io::Result<Vec<u8>> {
+            self.inner.read_block(piece_index, offset, length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+
+        fn is_backlogged(&self) -> bool {
+            self.backlogged.get()
+        }
+    }
+
+    #[test]
+    fn test_hold_back_requests_while_disk_is_backlogged() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let backlogged = Rc::new(Cell::new(true));
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = BackloggedStorage {
+            inner: MemoryStorage::new(piece_length, file_data.len()),
+            backlogged: backlogged.clone(),
+        };
+        let mut downloader = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5);
+        downloader.transfer_round().unwrap();
+        assert!(downloader.transfer_round().is_err());
+
+        backlogged.set(false);
+        downloader.download().unwrap();
+        assert_eq!(file_data, storage.inner.content());
+    }
+
+    #[test]
+    fn test_checkpoint_and_stop_when_download_is_cancelled() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let handle = DownloadHandle::new();
+        handle.cancel();
+
+        let mut checkpoints = 0;
+        let mut channel = DownloadChannelFromVector::new(pieces.clone());
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        let stats = FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_handle(handle)
+        .with_checkpoint_callback(|_| checkpoints += 1)
+        .download()
+        .unwrap();
+
+        assert_eq!(stats, TransferStats::default());
+        assert_eq!(checkpoints, 1);
+        assert!(channel.requested_pieces.is_empty());
+    }
+
+    #[test]
+    fn test_download_piece_again_after_hash_failure() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut final_stats = DownloadStats::default();
+        let mut channel = DownloadChannelFromVector::new(pieces.clone()).corrupt_first_blocks(1);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5)
+        .with_stats_callback(|stats| final_stats = stats.clone())
+        .download()
+        .unwrap();
+
+        assert_eq!(file_data, storage.content());
+        assert_eq!(final_stats.hash_failures, 1);
+        assert_eq!(final_stats.wasted_bytes, piece_length as u64);
+        assert_eq!(final_stats.downloaded_bytes, file_data.len() as u64);
+        assert_eq!(final_stats.total_bytes, file_data.len() as u64);
+        assert_eq!(final_stats.eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_report_per_peer_statistics() {
+        let file_data = (1..=40).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+        let handle = DownloadHandle::new();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), all_pieces(pieces.len())]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5)
+        .with_handle(handle.clone())
+        .download()
+        .unwrap();
+
+        let stats = handle.stats();
+        assert_eq!(stats.left_bytes(), 0);
+        assert_eq!(stats.wasted_bytes, 0);
+        assert_eq!(
+            stats.peers.iter().map(|peer| peer.peer).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert!(stats.peers.iter().all(|peer| peer.latency.is_some()
+            && peer.pending_requests == 0
+            && peer.queue_depth > 0
+            && !peer.snubbed));
+    }
+
+    #[test]
+    fn test_announce_downloaded_pieces_to_all_peers() {
+        let file_data = (1..=20).collect::<Vec<u8>>();
+        let piece_length = 10_u32;
+        let pieces = file_data
+            .chunks(piece_length as usize)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let piece_hashes = pieces.iter().map(|p| Sha1::calculate(p)).collect();
+
+        let mut channel = DownloadChannelFromVector::new(pieces.clone())
+            .with_peers(vec![all_pieces(pieces.len()), Bitfield::new(pieces.len())]);
+        let mut storage = MemoryStorage::new(piece_length, file_data.len());
+        FileDownloader::new(
+            &mut channel,
+            &mut storage,
+            piece_hashes,
+            piece_length,
+            file_data.len(),
+        )
+        .with_block_length(5)
+        .download()
+        .unwrap();
+
+        let mut sent_messages = channel.sent_messages.clone();
+        sent_messages.sort_by_key(|(peer, _)| *peer);
+        assert_eq!(
+            sent_messages,
+            vec![
+                (0, PeerMessage::Have(0)),
+                (0, PeerMessage::Have(1)),
+                (1, PeerMessage::NotInterested),
+                (1, PeerMessage::Have(0)),
+                (1, PeerMessage::Have(1)),
+            ]
+        );
+    }
+
     struct DownloadChannelFromVector {
         pieces: Vec<Vec<u8>>,
-        requests: VecDeque<(u32, u32, u32)>,
+        peer_pieces: Vec<Bitfield>,
+        connected_peers: usize,
+        incoming: bool,
+        scripted_events: VecDeque<DownloadEvent>,
+        requests: VecDeque<(PeerKey, u32, u32, u32)>,
+        answer_in_reverse: bool,
+        choke_after: Option<usize>,
+        disconnect_after: Option<(PeerKey, usize)>,
+        choked: bool,
+        blocks_sent: usize,
+        requests_while_choked: usize,
+        corrupt_blocks: usize,
+        requested_pieces: Vec<(PeerKey, u32)>,
+        sent_messages: Vec<(PeerKey, PeerMessage)>,
+        disconnected_peers: Vec<PeerKey>,
     }
 
     impl DownloadChannelFromVector {
         fn new(pieces: Vec<Vec<u8>>) -> Self {
+            let all_pieces = all_pieces(pieces.len());
             Self {
                 pieces,
+                peer_pieces: vec![all_pieces],
+                connected_peers: 0,
+                incoming: false,
+                scripted_events: VecDeque::new(),
                 requests: VecDeque::new(),
+                answer_in_reverse: false,
+                choke_after: None,
+                disconnect_after: None,
+                choked: false,
+                blocks_sent: 0,
+                requests_while_choked: 0,
+                corrupt_blocks: 0,
+                requested_pieces: vec![],
+                sent_messages: vec![],
+                disconnected_peers: vec![],
             }
         }
-    }
 
-    impl RequestChannel for DownloadChannelFromVector {
-        fn request(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<()> {
-            self.requests.push_back((piece_index, offset, length));
-            Ok(())
+        fn with_peers(mut self, peer_pieces: Vec<Bitfield>) -> Self {
+            self.peer_pieces = peer_pieces;
+            self
+        }
+
+        fn with_scripted_events(mut self, events: Vec<DownloadEvent>) -> Self {
+            self.scripted_events.extend(events);
+            self
+        }
+
+        fn incoming(mut self) -> Self {
+            self.incoming = true;
+            self
+        }
+
+        fn answer_in_reverse(mut self) -> Self {
+            self.answer_in_reverse = true;
+            self
+        }
+
+        fn choke_after(mut self, blocks: usize) -> Self {
+            self.choke_after = Some(blocks);
+            self
+        }
+
+        fn corrupt_first_blocks(mut self, blocks: usize) -> Self {
+            self.corrupt_blocks = blocks;
+            self
+        }
+
+        fn disconnect_after(mut self, peer: PeerKey, blocks: usize) -> Self {
+            self.disconnect_after = Some((peer, blocks));
+            self
+        }
+
+        fn connect_next_peer(&mut self) -> DownloadEvent {
+            let peer = self.connected_peers;
+            self.connected_peers += 1;
+            let state = if self.incoming {
+                let bitfield = self.peer_pieces[peer].as_bytes().to_vec();
+                self.scripted_events.push_back(DownloadEvent::Message(
+                    peer,
+                    PeerMessage::Bitfield(bitfield),
+                ));
+                ConnectionState::default()
+            } else {
+                ConnectionState {
+                    am_interested: true,
+                    peer_choking: false,
+                    peer_pieces: self.peer_pieces[peer].clone(),
+                    ..ConnectionState::default()
+                }
+            };
+            DownloadEvent::PeerConnected {
+                peer,
+                state,
+                incoming: self.incoming,
+            }
         }
     }
 
     impl DownloadChannel for DownloadChannelFromVector {
-        fn receive(&mut self) -> io::Result<Block> {
-            if let Some((piece_index, offset, length)) = self.requests.pop_front() {
+        fn receive(&mut self, _timeout: Duration) -> io::Result<Option<DownloadEvent>> {
+            if self.connected_peers < self.peer_pieces.len() {
+                return Ok(Some(self.connect_next_peer()));
+            }
+            if let Some(event) = self.scripted_events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.choked {
+                self.choked = false;
+                return Ok(Some(DownloadEvent::Message(0, PeerMessage::Unchoke)));
+            }
+            if self.choke_after == Some(self.blocks_sent) {
+                self.choke_after = None;
+                self.choked = true;
+                self.requests.retain(|(peer, ..)| *peer != 0);
+                return Ok(Some(DownloadEvent::Message(0, PeerMessage::Choke)));
+            }
+            if let Some((peer, blocks)) = self.disconnect_after
+                && blocks == self.blocks_sent
+            {
+                self.disconnect_after = None;
+                self.requests
+                    .retain(|(requested_from, ..)| *requested_from != peer);
+                return Ok(Some(DownloadEvent::PeerDisconnected(peer)));
+            }
+
+            self.blocks_sent += 1;
+            let next_request = if self.answer_in_reverse {
+                self.requests.pop_back()
+            } else {
+                self.requests.pop_front()
+            };
+            if let Some((peer, piece_index, offset, length)) = next_request {
                 let piece = &self.pieces[piece_index as usize];
-                let data = piece[offset as usize..(offset + length) as usize].to_vec();
-                Ok(Block {
+                let mut block = piece[offset as usize..(offset + length) as usize].to_vec();
+                if self.corrupt_blocks > 0 {
+                    self.corrupt_blocks -= 1;
+                    block.iter_mut().for_each(|byte| *byte = !*byte);
+                }
+                Ok(Some(DownloadEvent::Message(
+                    peer,
+                    PeerMessage::Piece {
+                        piece_index,
+                        offset,
+                        block,
+                    },
+                )))
+            } else {
+                Err(io::Error::other("No block requested"))
+            }
+        }
+
+        fn send(&mut self, peer: PeerKey, msg: &PeerMessage) -> io::Result<()> {
+            match msg {
+                PeerMessage::Request {
                     piece_index,
                     offset,
-                    data,
-                })
-            } else {
-                Err(io::Error::new(io::ErrorKind::Other, "No block requested"))
+                    length,
+                } => {
+                    if !self.peer_pieces[peer].has_piece(*piece_index) {
+                        return Err(io::Error::other("Requested piece the peer doesn't have"));
+                    }
+                    if self.choked && peer == 0 {
+                        self.requests_while_choked += 1;
+                    }
+                    self.requested_pieces.push((peer, *piece_index));
+                    self.requests
+                        .push_back((peer, *piece_index, *offset, *length));
+                }
+                PeerMessage::Interested if self.incoming => {
+                    self.sent_messages.push((peer, msg.clone()));
+                    self.scripted_events
+                        .push_back(DownloadEvent::Message(peer, PeerMessage::Unchoke));
+                }
+                _ => self.sent_messages.push((peer, msg.clone())),
+            }
+            Ok(())
+        }
+
+        fn disconnect(&mut self, peer: PeerKey) {
+            self.disconnected_peers.push(peer);
+            self.requests
+                .retain(|(requested_from, ..)| *requested_from != peer);
+            self.scripted_events
+                .push_back(DownloadEvent::PeerDisconnected(peer));
+        }
+    }
+
+    struct UnreadablePieceStorage {
+        inner: MemoryStorage,
+        unreadable_piece: u32,
+    }
+
+    impl Storage for UnreadablePieceStorage {
+        fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+            self.inner.write_piece(piece_index, data)
+        }
+
+        fn read_block(
+            &mut self,
+            piece_index: u32,
+            offset: u32,
+            length: u32,
+        ) -> io::Result<Vec<u8>> {
+            if piece_index == self.unreadable_piece {
+                return Err(io::Error::other("Unreadable piece"));
             }
+            self.inner.read_block(piece_index, offset, length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
         }
     }
 
     struct ErrorDownloadChannel {
+        connected: bool,
         block_to_send: Block,
     }
 
-    impl RequestChannel for ErrorDownloadChannel {
-        fn request(&mut self, _piece_index: u32, _offset: u32, _length: u32) -> io::Result<()> {
+    impl DownloadChannel for ErrorDownloadChannel {
+        fn receive(&mut self, _timeout: Duration) -> io::Result<Option<DownloadEvent>> {
+            if !self.connected {
+                self.connected = true;
+                return Ok(Some(DownloadEvent::PeerConnected {
+                    peer: 0,
+                    state: ConnectionState {
+                        am_interested: true,
+                        peer_choking: false,
+                        peer_pieces: all_pieces(1),
+                        ..ConnectionState::default()
+                    },
+                    incoming: false,
+                }));
+            }
+            let block = self.block_to_send.clone();
+            Ok(Some(DownloadEvent::Message(
+                0,
+                PeerMessage::Piece {
+                    piece_index: block.piece_index,
+                    offset: block.offset,
+                    block: block.data,
+                },
+            )))
+        }
+
+        fn send(&mut self, _peer: PeerKey, _msg: &PeerMessage) -> io::Result<()> {
             Ok(())
         }
+
+        fn disconnect(&mut self, _peer: PeerKey) {}
     }
 
-    impl DownloadChannel for ErrorDownloadChannel {
-        fn receive(&mut self) -> io::Result<Block> {
-            Ok(self.block_to_send.clone())
+    fn all_pieces(piece_count: usize) -> Bitfield {
+        let mut pieces = Bitfield::new(piece_count);
+        for index in 0..piece_count {
+            pieces.set_piece(index as u32);
         }
+        pieces
     }
 
     fn zero_sha1() -> Sha1 {
@@ -0,0 +1,265 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    mem,
+    time::{Duration, Instant},
+};
+
+use super::PeerKey;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChokerConfig {
+    pub upload_slots: usize,
+    pub optimistic_slots: usize,
+}
+
+impl Default for ChokerConfig {
+    fn default() -> Self {
+        Self {
+            upload_slots: 4,
+            optimistic_slots: 1,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeerRates {
+    downloaded: u64,
+    uploaded: u64,
+    download_rate: u64,
+    upload_rate: u64,
+}
+
+pub struct Choker {
+    config: ChokerConfig,
+    peers: BTreeMap<PeerKey, PeerRates>,
+    optimistic: Vec<PeerKey>,
+    last_round: Instant,
+    last_rotation: Instant,
+}
+
+impl Choker {
+    const ROUND_INTERVAL: Duration = Duration::from_secs(10);
+    const ROTATION_INTERVAL: Duration = Duration::from_secs(30);
+
+    pub fn new(config: ChokerConfig, now: Instant) -> Self {
+        Self {
+            config,
+            peers: BTreeMap::new(),
+            optimistic: Vec::new(),
+            last_round: now,
+            last_rotation: now,
+        }
+    }
+
+    pub fn peer_connected(&mut self, peer: PeerKey) {
+        self.peers.entry(peer).or_default();
+    }
+
+    pub fn peer_disconnected(&mut self, peer: PeerKey) {
+        self.peers.remove(&peer);
+        self.optimistic.retain(|optimistic| *optimistic != peer);
+    }
+
+    pub fn bytes_downloaded(&mut self, peer: PeerKey, bytes: u64) {
+        if let Some(rates) = self.peers.get_mut(&peer) {
+            rates.downloaded += bytes;
+        }
+    }
+
+    pub fn bytes_uploaded(&mut self, peer: PeerKey, bytes: u64) {
+        if let Some(rates) = self.peers.get_mut(&peer) {
+            rates.uploaded += bytes;
+        }
+    }
+
+    pub fn is_round_due(&self, now: Instant) -> bool {
+        now.duration_since(self.last_round) >= Self::ROUND_INTERVAL
+    }
+
+    pub fn next_round(
+        &mut self,
+        interested: &[PeerKey],
+        seeding: bool,
+        now: Instant,
+    ) -> Vec<PeerKey> {
+        for rates in self.peers.values_mut() {
+            rates.download_rate = mem::take(&mut rates.downloaded);
+            rates.upload_rate = mem::take(&mut rates.uploaded);
+        }
+        self.last_round = now;
+
+        let rotate = now.duration_since(self.last_rotation) >= Self::ROTATION_INTERVAL;
+        if rotate {
+            self.last_rotation = now;
+        }
+        self.select(interested, seeding, rotate)
+    }
+
+    pub fn unchoked_peers(&mut self, interested: &[PeerKey], seeding: bool) -> Vec<PeerKey> {
+        self.select(interested, seeding, false)
+    }
+
+    fn select(&mut self, interested: &[PeerKey], seeding: bool, rotate: bool) -> Vec<PeerKey> {
+        let mut candidates = interested
+            .iter()
+            .copied()
+            .filter(|peer| self.peers.contains_key(peer))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|peer| (Reverse(self.rate(*peer, seeding)), *peer));
+        let regular_count = candidates.len().min(self.config.upload_slots);
+        let mut rest = candidates.split_off(regular_count);
+
+        let previous = mem::take(&mut self.optimistic);
+        if !rotate {
+            self.optimistic = previous
+                .iter()
+                .copied()
+                .filter(|peer| rest.contains(peer))
+                .collect();
+        }
+
+        rest.sort_unstable();
+        let last_optimistic = previous.last().copied();
+        let start = rest
+            .iter()
+            .position(|peer| Some(*peer) > last_optimistic)
+            .unwrap_or(0);
+        rest.rotate_left(start);
+        for peer in rest {
+            if self.optimistic.len() >= self.config.optimistic_slots {
+                break;
+            }
+            if !self.optimistic.contains(&peer) {
+                self.optimistic.push(peer);
+            }
+        }
+
+        candidates.extend(&self.optimistic);
+        candidates
+    }
+
+    fn rate(&self, peer: PeerKey, seeding: bool) -> u64 {
+        let rates = &self.peers[&peer];
+        if seeding {
+            rates.upload_rate
+        } else {
+            rates.download_rate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_choker(upload_slots: usize, peer_count: usize, now: Instant) -> Choker {
+        let mut choker = Choker::new(
+            ChokerConfig {
+                upload_slots,
+                optimistic_slots: 1,
+            },
+            now,
+        );
+        for peer in 0..peer_count {
+            choker.peer_connected(peer);
+        }
+        choker
+    }
+
+    #[test]
+    fn unchoke_fastest_downloading_peers_and_one_optimistic() {
+        let now = Instant::now();
+        let mut choker = make_choker(2, 4, now);
+        choker.bytes_downloaded(0, 100);
+        choker.bytes_downloaded(1, 300);
+        choker.bytes_downloaded(2, 200);
+        choker.bytes_uploaded(3, 1000);
+
+        let unchoked = choker.next_round(&[0, 1, 2, 3], false, now + Choker::ROUND_INTERVAL);
+        assert_eq!(unchoked, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn unchoke_by_upload_rate_when_seeding() {
+        let now = Instant::now();
+        let mut choker = make_choker(2, 4, now);
+        choker.bytes_uploaded(0, 100);
+        choker.bytes_uploaded(2, 300);
+        choker.bytes_uploaded(3, 200);
+        choker.bytes_downloaded(1, 1000);
+
+        let unchoked = choker.next_round(&[0, 1, 2, 3], true, now + Choker::ROUND_INTERVAL);
+        assert_eq!(unchoked, vec![2, 3, 0]);
+    }
+
+    #[test]
+    fn only_unchoke_interested_peers() {
+        let now = Instant::now();
+        let mut choker = make_choker(2, 4, now);
+        choker.bytes_downloaded(0, 500);
+
+        let unchoked = choker.next_round(&[1, 3], false, now + Choker::ROUND_INTERVAL);
+        assert_eq!(unchoked, vec![1, 3]);
+    }
+
+    #[test]
+    fn keep_optimistic_unchoke_until_rotation_is_due() {
+        let now = Instant::now();
+        let mut choker = make_choker(1, 4, now);
+        choker.bytes_downloaded(0, 100);
+        let unchoked = choker.next_round(&[0, 1, 2, 3], false, now + Choker::ROUND_INTERVAL);
+        assert_eq!(unchoked, vec![0, 1]);
+
+        choker.bytes_downloaded(0, 100);
+        let unchoked = choker.next_round(&[0, 1, 2, 3], false, now + 2 * Choker::ROUND_INTERVAL);
+        assert_eq!(unchoked, vec![0, 1]);
+    }
+
+    #[test]
+    fn rotate_optimistic_unchoke_among_remaining_peers() {
+        let now = Instant::now();
+        let mut choker = make_choker(1, 4, now);
+        let mut optimistic = vec![];
+        for round in 1..=4 {
+            choker.bytes_downloaded(0, 100);
+            let unchoked = choker.next_round(
+                &[0, 1, 2, 3],
+                false,
+                now + round * Choker::ROTATION_INTERVAL,
+            );
+            assert_eq!(unchoked[0], 0);
+            optimistic.push(unchoked[1]);
+        }
+        assert_eq!(optimistic, vec![1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn replace_optimistic_peer_that_lost_interest() {
+        let now = Instant::now();
+        let mut choker = make_choker(1, 3, now);
+        choker.bytes_downloaded(0, 100);
+        assert_eq!(
+            choker.next_round(&[0, 1, 2], false, now + Choker::ROUND_INTERVAL),
+            vec![0, 1]
+        );
+
+        assert_eq!(choker.unchoked_peers(&[0, 2], false), vec![0, 2]);
+    }
+
+    #[test]
+    fn promote_optimistic_peer_when_it_becomes_one_of_the_fastest() {
+        let now = Instant::now();
+        let mut choker = make_choker(1, 3, now);
+        assert_eq!(
+            choker.next_round(&[0, 1, 2], false, now + Choker::ROUND_INTERVAL),
+            vec![0, 1]
+        );
+
+        choker.bytes_downloaded(1, 100);
+        assert_eq!(
+            choker.next_round(&[0, 1, 2], false, now + 2 * Choker::ROUND_INTERVAL),
+            vec![1, 2]
+        );
+    }
+}
@@ -1,88 +1,200 @@
 use super::{Block, file_info::FileInfo};
-use std::io;
+use crate::types::{Bitfield, Sha1, Sha1Hasher};
+use std::{collections::HashMap, io};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Piece {
     pub index: u32,
     pub data: Vec<u8>,
+    pub hash: Option<Sha1>,
 }
 
 impl Piece {
-    fn new(index: u32, data: Vec<u8>) -> Self {
-        Self { index, data }
+    fn new(index: u32, data: Vec<u8>, hash: Option<Sha1>) -> Self {
+        Self { index, data, hash }
     }
 }
 
-pub struct PieceComposer {
-    piece_index: Option<u32>,
+struct PartialPiece {
     buffer: Vec<u8>,
+    received_blocks: Vec<bool>,
+    blocks_remaining: usize,
+    hasher: Option<Sha1Hasher>,
+    hashed_blocks: usize,
+}
+
+impl PartialPiece {
+    const MAX_CATCH_UP_BLOCKS: usize = 4;
+
+    fn new(piece_length: u32, block_length: u32) -> Self {
+        let block_count = piece_length.div_ceil(block_length) as usize;
+        Self {
+            buffer: vec![0; piece_length as usize],
+            received_blocks: vec![false; block_count],
+            blocks_remaining: block_count,
+            hasher: Some(Sha1Hasher::new()),
+            hashed_blocks: 0,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.blocks_remaining == 0
+    }
+
+    // Hashing the in-order prefix inline costs at most a few blocks per received block,
+    // so the network loop never stalls on a whole piece. Pieces that fall further behind
+    // drop the hasher and are verified on the HashPool once complete.
+    fn hash_received_prefix(&mut self, block_length: u32) {
+        let Some(hasher) = &mut self.hasher else {
+            return;
+        };
+        let ready_blocks = self.received_blocks[self.hashed_blocks..]
+            .iter()
+            .take_while(|received| **received)
+            .count();
+        if ready_blocks > Self::MAX_CATCH_UP_BLOCKS {
+            self.hasher = None;
+            return;
+        }
+
+        let start = self.hashed_blocks * block_length as usize;
+        let end =
+            ((self.hashed_blocks + ready_blocks) * block_length as usize).min(self.buffer.len());
+        hasher.update(&self.buffer[start..end]);
+        self.hashed_blocks += ready_blocks;
+    }
+
+    fn into_piece(self, index: u32) -> Piece {
+        let hash = self
+            .hasher
+            .filter(|_| self.hashed_blocks == self.received_blocks.len())
+            .map(Sha1Hasher::finish);
+        Piece::new(index, self.buffer, hash)
+    }
+}
+
+pub struct PieceComposer {
+    partial_pieces: HashMap<u32, PartialPiece>,
+    completed_pieces: Vec<bool>,
+    block_length: u32,
     file_info: FileInfo,
 }
 
 impl PieceComposer {
-    pub fn new(file_info: FileInfo) -> Self {
+    pub fn new(file_info: FileInfo, block_length: u32) -> Self {
         Self {
-            buffer: Vec::with_capacity(file_info.piece_length as usize),
+            partial_pieces: HashMap::new(),
+            completed_pieces: vec![false; file_info.piece_count() as usize],
+            block_length,
             file_info,
-            piece_index: None,
         }
     }
 
     pub fn append_block(&mut self, block: &Block) -> io::Result<Option<Piece>> {
-        if self.piece_index.is_none() {
-            self.piece_index = Some(block.piece_index);
+        self.verify_piece_index(block.piece_index)?;
+        if self.completed_pieces[block.piece_index as usize] {
+            return Ok(None);
         }
 
-        self.verify_piece_index(block.piece_index)?;
-        self.verify_block_offset(block.offset)?;
-        self.buffer.extend(&block.data);
-
-        if self.buffer.len() >= self.current_piece_length() {
-            let piece = Piece::new(self.piece_index.unwrap(), self.buffer.clone());
-            self.buffer.clear();
-            self.piece_index = None;
-            Ok(Some(piece))
+        let piece_length = self.file_info.piece_length(block.piece_index);
+        let block_index = self.verify_block_bounds(block, piece_length)?;
+        let partial_piece = self
+            .partial_pieces
+            .entry(block.piece_index)
+            .or_insert_with(|| PartialPiece::new(piece_length, self.block_length));
+
+        if partial_piece.received_blocks[block_index] {
+            return Ok(None);
+        }
+
+        let start = block.offset as usize;
+        partial_piece.buffer[start..start + block.data.len()].copy_from_slice(&block.data);
+        partial_piece.received_blocks[block_index] = true;
+        partial_piece.blocks_remaining -= 1;
+        partial_piece.hash_received_prefix(self.block_length);
+
+        if partial_piece.is_complete() {
+            let completed = self.partial_pieces.remove(&block.piece_index).unwrap();
+            self.completed_pieces[block.piece_index as usize] = true;
+            Ok(Some(completed.into_piece(block.piece_index)))
         } else {
             Ok(None)
         }
     }
 
-    fn current_piece_length(&self) -> usize {
-        self.file_info.piece_length(self.piece_index.unwrap()) as usize
+    pub fn is_duplicate(&self, block: &Block) -> bool {
+        let piece_index = block.piece_index as usize;
+        if self.completed_pieces.get(piece_index) == Some(&true) {
+            return true;
+        }
+        let block_index = (block.offset / self.block_length) as usize;
+        self.partial_pieces
+            .get(&block.piece_index)
+            .and_then(|piece| piece.received_blocks.get(block_index))
+            .is_some_and(|received| *received)
+    }
+
+    pub fn piece_failed(&mut self, piece_index: u32) {
+        self.completed_pieces[piece_index as usize] = false;
     }
 
-    fn verify_block_offset(&self, offset: u32) -> io::Result<()> {
-        let expected_offset = self.buffer.len() as u32;
-        if expected_offset != offset {
-            return Err(unexpected_block_offset(expected_offset, offset));
+    pub fn skip_pieces(&mut self, pieces: &Bitfield) {
+        for (index, completed) in self.completed_pieces.iter_mut().enumerate() {
+            *completed |= pieces.has_piece(index as u32);
         }
-        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn set_block_length(&mut self, block_length: u32) {
+        self.block_length = block_length;
     }
 
     fn verify_piece_index(&self, piece_index: u32) -> io::Result<()> {
-        let expected = self.piece_index.unwrap();
-        if expected != piece_index {
-            return Err(unexpected_piece_index(expected, piece_index));
+        if piece_index >= self.file_info.piece_count() {
+            return Err(invalid_piece_index(piece_index));
         }
         Ok(())
     }
+
+    fn verify_block_bounds(&self, block: &Block, piece_length: u32) -> io::Result<usize> {
+        if !block.offset.is_multiple_of(self.block_length) || block.offset >= piece_length {
+            return Err(unexpected_block_offset(block.piece_index, block.offset));
+        }
+
+        let expected_length = self.block_length.min(piece_length - block.offset);
+        if block.data.len() != expected_length as usize {
+            return Err(unexpected_block_length(
+                expected_length,
+                block.data.len() as u32,
+            ));
+        }
+
+        Ok((block.offset / self.block_length) as usize)
+    }
+}
+
+fn invalid_piece_index(piece_index: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid piece index in response: {}", piece_index),
+    )
 }
 
-fn unexpected_piece_index(expected: u32, actual: u32) -> io::Error {
+pub fn unexpected_block_offset(piece_index: u32, offset: u32) -> io::Error {
     io::Error::new(
         io::ErrorKind::InvalidData,
         format!(
-            "Unexpected piece index in response: expected {}, got {}",
-            expected, actual
+            "Unexpected block offset in response: piece {}, offset {}",
+            piece_index, offset
         ),
     )
 }
 
-pub fn unexpected_block_offset(expected: u32, actual: u32) -> io::Error {
+fn unexpected_block_length(expected: u32, actual: u32) -> io::Error {
     io::Error::new(
         io::ErrorKind::InvalidData,
         format!(
-            "Unexpected block offset in response: expected {}, got {}",
+            "Unexpected block length in response: expected {}, got {}",
             expected, actual
         ),
     )
@@ -94,10 +206,13 @@ mod tests {
 
     #[test]
     fn compose_piece_from_blocks() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 100,
-        });
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
         let first_block = Block {
             piece_index: 0,
             offset: 0,
@@ -116,16 +231,19 @@ mod tests {
         let buffer = composer.append_block(&second_block).unwrap();
         assert_eq!(
             buffer,
-            Some(Piece::new(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
         );
     }
 
     #[test]
     fn compose_last_piece_with_reduced_length_from_blocks() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 17,
-        });
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 17,
+            },
+            5,
+        );
         let last_piece_index = 1;
         let first_block = Block {
             piece_index: last_piece_index,
@@ -145,56 +263,188 @@ mod tests {
         let buffer = composer.append_block(&second_block).unwrap();
         assert_eq!(
             buffer,
-            Some(Piece::new(last_piece_index, vec![1, 2, 3, 4, 5, 6, 7]))
+            Some(hashed_piece(last_piece_index, vec![1, 2, 3, 4, 5, 6, 7]))
         );
     }
 
     #[test]
-    fn starts_composing_new_piece_when_current_is_finished() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 17,
-        });
+    fn compose_piece_from_blocks_received_out_of_order() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            4,
+        );
+
+        let blocks = [
+            Block {
+                piece_index: 0,
+                offset: 8,
+                data: vec![9, 10],
+            },
+            Block {
+                piece_index: 0,
+                offset: 0,
+                data: vec![1, 2, 3, 4],
+            },
+            Block {
+                piece_index: 0,
+                offset: 4,
+                data: vec![5, 6, 7, 8],
+            },
+        ];
+
+        assert_eq!(composer.append_block(&blocks[0]).unwrap(), None);
+        assert_eq!(composer.append_block(&blocks[1]).unwrap(), None);
+        assert_eq!(
+            composer.append_block(&blocks[2]).unwrap(),
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
+        );
+    }
+
+    #[test]
+    fn compose_multiple_pieces_concurrently() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 17,
+            },
+            5,
+        );
+
+        let blocks = [
+            Block {
+                piece_index: 1,
+                offset: 0,
+                data: vec![11, 12, 13, 14, 15],
+            },
+            Block {
+                piece_index: 0,
+                offset: 5,
+                data: vec![6, 7, 8, 9, 10],
+            },
+            Block {
+                piece_index: 1,
+                offset: 5,
+                data: vec![16, 17],
+            },
+            Block {
+                piece_index: 0,
+                offset: 0,
+                data: vec![1, 2, 3, 4, 5],
+            },
+        ];
+
+        assert_eq!(composer.append_block(&blocks[0]).unwrap(), None);
+        assert_eq!(composer.append_block(&blocks[1]).unwrap(), None);
+        assert_eq!(
+            composer.append_block(&blocks[2]).unwrap(),
+            Some(hashed_piece(1, vec![11, 12, 13, 14, 15, 16, 17]))
+        );
+        assert_eq!(
+            composer.append_block(&blocks[3]).unwrap(),
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
+        );
+    }
+
+    #[test]
+    fn ignore_duplicate_blocks() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
         let first_block = Block {
             piece_index: 0,
             offset: 0,
             data: vec![1, 2, 3, 4, 5],
         };
-
+        let duplicate_block = Block {
+            piece_index: 0,
+            offset: 0,
+            data: vec![0xff; 5],
+        };
         let second_block = Block {
             piece_index: 0,
             offset: 5,
             data: vec![6, 7, 8, 9, 10],
         };
 
-        let third_block = Block {
-            piece_index: 1,
+        assert_eq!(composer.append_block(&first_block).unwrap(), None);
+        assert_eq!(composer.append_block(&duplicate_block).unwrap(), None);
+        assert_eq!(
+            composer.append_block(&second_block).unwrap(),
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
+        );
+    }
+
+    #[test]
+    fn ignore_blocks_of_completed_pieces() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 5,
+                file_length: 100,
+            },
+            5,
+        );
+        let block = Block {
+            piece_index: 0,
             offset: 0,
-            data: vec![11, 12, 13, 14, 15, 16, 17],
+            data: vec![1, 2, 3, 4, 5],
         };
 
-        let buffer = composer.append_block(&first_block).unwrap();
-        assert_eq!(buffer, None);
-
-        let buffer = composer.append_block(&second_block).unwrap();
         assert_eq!(
-            buffer,
-            Some(Piece::new(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
+            composer.append_block(&block).unwrap(),
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5]))
         );
+        assert_eq!(composer.append_block(&block).unwrap(), None);
+    }
 
-        let buffer = composer.append_block(&third_block).unwrap();
+    #[test]
+    fn compose_failed_piece_again() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
+        let first_block = Block {
+            piece_index: 0,
+            offset: 0,
+            data: vec![1, 2, 3, 4, 5],
+        };
+        let second_block = Block {
+            piece_index: 0,
+            offset: 5,
+            data: vec![6, 7, 8, 9, 10],
+        };
+        composer.append_block(&first_block).unwrap();
+        assert!(composer.is_duplicate(&first_block));
+        assert!(!composer.is_duplicate(&second_block));
+        composer.append_block(&second_block).unwrap();
+
+        composer.piece_failed(0);
+        assert!(!composer.is_duplicate(&first_block));
+        assert_eq!(composer.append_block(&first_block).unwrap(), None);
         assert_eq!(
-            buffer,
-            Some(Piece::new(1, vec![11, 12, 13, 14, 15, 16, 17]))
+            composer.append_block(&second_block).unwrap(),
+            Some(hashed_piece(0, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]))
         );
     }
 
     #[test]
-    fn append_first_block_with_wrong_offset() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 100,
-        });
+    fn append_block_with_misaligned_offset() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
         let block = Block {
             piece_index: 0,
             offset: 1,
@@ -205,48 +455,89 @@ mod tests {
     }
 
     #[test]
-    fn append_next_block_with_wrong_offset() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 100,
-        });
-        let first_block = Block {
+    fn append_block_with_offset_past_end_of_piece() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
+        let block = Block {
             piece_index: 0,
-            offset: 0,
+            offset: 10,
             data: vec![1, 2, 3, 4, 5],
         };
+        let error = composer.append_block(&block).unwrap_err();
+        assert_eq!(
+            unexpected_block_offset(0, 10).to_string(),
+            error.to_string()
+        );
+    }
 
-        let second_block = Block {
+    #[test]
+    fn append_block_with_wrong_length() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
+        let block = Block {
             piece_index: 0,
-            offset: 3,
-            data: vec![6, 7, 8, 9, 10],
+            offset: 5,
+            data: vec![1, 2, 3],
         };
-
-        composer.append_block(&first_block).unwrap();
-        let error = composer.append_block(&second_block).unwrap_err();
-        assert_eq!(unexpected_block_offset(5, 3).to_string(), error.to_string());
+        let error = composer.append_block(&block).unwrap_err();
+        assert_eq!(unexpected_block_length(5, 3).to_string(), error.to_string());
     }
 
     #[test]
-    fn append_next_block_with_wrong_piece_index() {
-        let mut composer = PieceComposer::new(FileInfo {
-            piece_length: 10,
-            file_length: 100,
-        });
-        let first_block = Block {
-            piece_index: 0,
+    fn append_block_with_invalid_piece_index() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 10,
+                file_length: 100,
+            },
+            5,
+        );
+        let block = Block {
+            piece_index: 10,
             offset: 0,
             data: vec![1, 2, 3, 4, 5],
         };
+        let error = composer.append_block(&block).unwrap_err();
+        assert_eq!(invalid_piece_index(10).to_string(), error.to_string());
+    }
 
-        let second_block = Block {
-            piece_index: 1,
-            offset: 5,
-            data: vec![6, 7, 8, 9, 10],
+    #[test]
+    fn hash_piece_when_blocks_arrive_far_out_of_order() {
+        let mut composer = PieceComposer::new(
+            FileInfo {
+                piece_length: 30,
+                file_length: 100,
+            },
+            5,
+        );
+        let data = (1..=30).collect::<Vec<u8>>();
+        let block = |index: usize| Block {
+            piece_index: 0,
+            offset: (index * 5) as u32,
+            data: data[index * 5..(index + 1) * 5].to_vec(),
         };
 
-        composer.append_block(&first_block).unwrap();
-        let error = composer.append_block(&second_block).unwrap_err();
-        assert_eq!(unexpected_piece_index(0, 1).to_string(), error.to_string());
+        for index in 1..6 {
+            assert_eq!(composer.append_block(&block(index)).unwrap(), None);
+        }
+        assert_eq!(
+            composer.append_block(&block(0)).unwrap(),
+            Some(Piece::new(0, data.clone(), None))
+        );
+    }
+
+    fn hashed_piece(index: u32, data: Vec<u8>) -> Piece {
+        let hash = Sha1::calculate(&data);
+        Piece::new(index, data, Some(hash))
     }
 }
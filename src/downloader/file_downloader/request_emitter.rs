@@ -1,57 +1,231 @@
-use std::io;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+};
 
-use super::RequestChannel;
 use super::file_info::FileInfo;
+use super::{BlockRequest, PeerKey, RequestChannel};
+use crate::{downloader::FilePriority, types::Bitfield};
 
 pub struct RequestEmitter {
     block_length: u32,
-    piece_index: u32,
-    next_block_index: u32,
     file_info: FileInfo,
+    requested_blocks: Vec<u32>,
+    first_unrequested_piece: u32,
+    skipped_pieces: Bitfield,
+    priority_pieces: Vec<u32>,
+    piece_priorities: Vec<FilePriority>,
+    pending_requests: HashMap<PeerKey, VecDeque<BlockRequest>>,
+    dropped_requests: VecDeque<DroppedRequest>,
+}
+
+struct DroppedRequest {
+    request: BlockRequest,
+    timed_out_from: Option<PeerKey>,
 }
 
 impl RequestEmitter {
     pub fn new(block_length: u32, file_info: FileInfo) -> Self {
         Self {
             block_length,
-            piece_index: 0,
-            next_block_index: 0,
             file_info,
+            requested_blocks: vec![0; file_info.piece_count() as usize],
+            first_unrequested_piece: 0,
+            skipped_pieces: Bitfield::default(),
+            priority_pieces: vec![],
+            piece_priorities: vec![],
+            pending_requests: HashMap::new(),
+            dropped_requests: VecDeque::new(),
         }
     }
 
-    pub fn request_next_block(&mut self, channel: &mut impl RequestChannel) -> io::Result<()> {
-        if self.piece_index >= self.file_info.piece_count() {
-            return Ok(());
-        }
+    pub fn skip_pieces(&mut self, pieces: &Bitfield) {
+        self.skipped_pieces = pieces.clone();
+        self.advance_first_unrequested_piece();
+    }
 
-        let piece_length = self.file_info.piece_length(self.piece_index);
-        let block_count = piece_length.div_ceil(self.block_length);
-        let block_offset = self.next_block_index * self.block_length;
-        let block_length = self.block_length.min(piece_length - block_offset);
+    pub fn set_priority_pieces(&mut self, pieces: Vec<u32>) {
+        self.priority_pieces = pieces;
+    }
 
-        channel.request(self.piece_index, block_offset, block_length)?;
+    pub fn set_piece_priorities(&mut self, priorities: Vec<FilePriority>) {
+        self.piece_priorities = priorities;
+        self.advance_first_unrequested_piece();
+    }
 
-        self.next_block_index += 1;
-        if self.next_block_index >= block_count {
-            self.next_block_index = 0;
-            self.piece_index += 1;
-        }
+    pub fn request_next_block(
+        &mut self,
+        peer: PeerKey,
+        peer_pieces: &Bitfield,
+        channel: &mut impl RequestChannel,
+    ) -> io::Result<bool> {
+        let Some(request) = self
+            .take_dropped_request(peer, peer_pieces)
+            .or_else(|| self.next_new_request(peer_pieces))
+        else {
+            return Ok(false);
+        };
 
-        Ok(())
+        channel.request(request.piece_index, request.offset, request.length)?;
+        self.pending_requests
+            .entry(peer)
+            .or_default()
+            .push_back(request);
+        Ok(true)
     }
 
-    pub fn request_first_blocks(
+    pub fn fill_request_queue(
         &mut self,
-        n_requests: u16,
+        peer: PeerKey,
+        peer_pieces: &Bitfield,
+        queue_length: usize,
         channel: &mut impl RequestChannel,
     ) -> io::Result<()> {
-        for _ in 0..n_requests {
-            self.request_next_block(channel)?;
-        }
+        while self.pending_count(peer) < queue_length
+            && self.request_next_block(peer, peer_pieces, channel)?
+        {}
         Ok(())
     }
 
+    pub fn pending_count(&self, peer: PeerKey) -> usize {
+        self.pending_requests.get(&peer).map_or(0, VecDeque::len)
+    }
+
+    pub fn has_pending_requests(&self) -> bool {
+        self.pending_requests
+            .values()
+            .any(|requests| !requests.is_empty())
+    }
+
+    pub fn block_received(&mut self, piece_index: u32, offset: u32) {
+        let is_received =
+            |request: &BlockRequest| request.piece_index == piece_index && request.offset == offset;
+        for requests in self.pending_requests.values_mut() {
+            requests.retain(|request| !is_received(request));
+        }
+        self.dropped_requests
+            .retain(|dropped| !is_received(&dropped.request));
+    }
+
+    pub fn requests_dropped(&mut self, peer: PeerKey) {
+        if let Some(requests) = self.pending_requests.remove(&peer) {
+            self.dropped_requests
+                .extend(requests.into_iter().map(|request| DroppedRequest {
+                    request,
+                    timed_out_from: None,
+                }));
+        }
+    }
+
+    pub fn request_timed_out(&mut self, peer: PeerKey, piece_index: u32, offset: u32) {
+        let Some(requests) = self.pending_requests.get_mut(&peer) else {
+            return;
+        };
+        let Some(position) = requests
+            .iter()
+            .position(|request| request.piece_index == piece_index && request.offset == offset)
+        else {
+            return;
+        };
+        let request = requests.remove(position).unwrap();
+        self.dropped_requests.push_back(DroppedRequest {
+            request,
+            timed_out_from: Some(peer),
+        });
+    }
+
+    pub fn release_timed_out_requests(&mut self, has_other_source: impl Fn(PeerKey, u32) -> bool) {
+        for dropped in &mut self.dropped_requests {
+            if let Some(peer) = dropped.timed_out_from
+                && !has_other_source(peer, dropped.request.piece_index)
+            {
+                dropped.timed_out_from = None;
+            }
+        }
+    }
+
+    pub fn piece_failed(&mut self, piece_index: u32) {
+        self.requested_blocks[piece_index as usize] = 0;
+        self.first_unrequested_piece = self.first_unrequested_piece.min(piece_index);
+    }
+
+    fn take_dropped_request(
+        &mut self,
+        peer: PeerKey,
+        peer_pieces: &Bitfield,
+    ) -> Option<BlockRequest> {
+        let position = self.dropped_requests.iter().position(|dropped| {
+            dropped.timed_out_from != Some(peer)
+                && peer_pieces.has_piece(dropped.request.piece_index)
+        })?;
+        self.dropped_requests
+            .remove(position)
+            .map(|dropped| dropped.request)
+    }
+
+    fn next_new_request(&mut self, peer_pieces: &Bitfield) -> Option<BlockRequest> {
+        let can_request =
+            |index: &u32| peer_pieces.has_piece(*index) && !self.is_fully_requested(*index);
+        let priorities = if self.piece_priorities.is_empty() {
+            &[FilePriority::Normal][..]
+        } else {
+            &[FilePriority::High, FilePriority::Normal, FilePriority::Low][..]
+        };
+        let piece_index = self
+            .priority_pieces
+            .iter()
+            .copied()
+            .find(can_request)
+            .or_else(|| {
+                priorities.iter().find_map(|priority| {
+                    (self.first_unrequested_piece..self.file_info.piece_count()).find(|index| {
+                        self.piece_priority(*index) == *priority && can_request(index)
+                    })
+                })
+            })?;
+
+        let piece_length = self.file_info.piece_length(piece_index);
+        let block_offset = self.requested_blocks[piece_index as usize] * self.block_length;
+        let request = BlockRequest {
+            piece_index,
+            offset: block_offset,
+            length: self.block_length.min(piece_length - block_offset),
+        };
+
+        self.requested_blocks[piece_index as usize] += 1;
+        self.advance_first_unrequested_piece();
+        Some(request)
+    }
+
+    fn is_fully_requested(&self, piece_index: u32) -> bool {
+        let block_count = self
+            .file_info
+            .piece_length(piece_index)
+            .div_ceil(self.block_length);
+        self.skipped_pieces.has_piece(piece_index)
+            || self.piece_priority(piece_index) == FilePriority::Skip
+            || self.requested_blocks[piece_index as usize] >= block_count
+    }
+
+    fn piece_priority(&self, piece_index: u32) -> FilePriority {
+        self.piece_priorities
+            .get(piece_index as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn advance_first_unrequested_piece(&mut self) {
+        while self.first_unrequested_piece < self.file_info.piece_count()
+            && self.is_fully_requested(self.first_unrequested_piece)
+        {
+            self.first_unrequested_piece += 1;
+        }
+    }
+
+    pub fn block_length(&self) -> u32 {
+        self.block_length
+    }
+
     #[cfg(test)]
     pub fn set_block_length(&mut self, block_length: u32) {
         self.block_length = block_length;
@@ -62,6 +236,8 @@ impl RequestEmitter {
 mod tests {
     use super::*;
 
+    const PEER: PeerKey = 0;
+
     #[test]
     fn request_next_block() {
         let block_length = 10;
@@ -74,8 +250,12 @@ mod tests {
         );
         let mut channel = RequestRecorder::new();
 
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
         assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 10)]);
     }
 
@@ -91,8 +271,12 @@ mod tests {
         );
         let mut channel = RequestRecorder::new();
 
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
         assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 5)]);
     }
 
@@ -108,9 +292,15 @@ mod tests {
         );
         let mut channel = RequestRecorder::new();
 
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
 
         assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 5), (1, 0, 10)]);
     }
@@ -127,15 +317,40 @@ mod tests {
         );
         let mut channel = RequestRecorder::new();
 
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
-        emitter.request_next_block(&mut channel).unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
+        emitter
+            .request_next_block(PEER, &all_pieces(), &mut channel)
+            .unwrap();
 
         assert_eq!(channel.requests, vec![(0, 0, 10), (1, 0, 5)]);
     }
 
     #[test]
-    fn request_first_blocks() {
+    fn skip_pieces_that_are_already_downloaded() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 10,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+        emitter.skip_pieces(&Bitfield::from_bytes(&[0b1010_0000]));
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 10, &mut channel)
+            .unwrap();
+        assert_eq!(channel.requests, vec![(1, 0, 10), (3, 0, 10)]);
+    }
+
+    #[test]
+    fn fill_request_queue() {
         let block_length = 10;
         let queue_length = 3;
         let mut emitter = RequestEmitter::new(
@@ -148,11 +363,307 @@ mod tests {
         let mut channel = RequestRecorder::new();
 
         emitter
-            .request_first_blocks(queue_length, &mut channel)
+            .fill_request_queue(PEER, &all_pieces(), queue_length, &mut channel)
             .unwrap();
         assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 10), (0, 20, 10)]);
     }
 
+    #[test]
+    fn fill_request_queue_up_to_pending_requests() {
+        let block_length = 10;
+        let queue_length = 3;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 1000,
+                piece_length: 100,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), queue_length, &mut channel)
+            .unwrap();
+        emitter.block_received(0, 10);
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), queue_length, &mut channel)
+            .unwrap();
+
+        assert_eq!(
+            channel.requests,
+            vec![(0, 0, 10), (0, 10, 10), (0, 20, 10), (0, 30, 10)]
+        );
+    }
+
+    #[test]
+    fn reissue_dropped_requests_first() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 1000,
+                piece_length: 100,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 3, &mut channel)
+            .unwrap();
+        emitter.block_received(0, 0);
+        emitter.requests_dropped(PEER);
+        channel.requests.clear();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 3, &mut channel)
+            .unwrap();
+        assert_eq!(
+            channel.requests,
+            vec![(0, 10, 10), (0, 20, 10), (0, 30, 10)]
+        );
+    }
+
+    #[test]
+    fn do_not_reissue_dropped_requests_received_later() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 1000,
+                piece_length: 100,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 2, &mut channel)
+            .unwrap();
+        emitter.requests_dropped(PEER);
+        emitter.block_received(0, 0);
+        channel.requests.clear();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 2, &mut channel)
+            .unwrap();
+        assert_eq!(channel.requests, vec![(0, 10, 10), (0, 20, 10)]);
+    }
+
+    #[test]
+    fn request_only_pieces_the_peer_has() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 10,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(
+                PEER,
+                &Bitfield::from_bytes(&[0b0101_0000]),
+                10,
+                &mut channel,
+            )
+            .unwrap();
+        assert_eq!(channel.requests, vec![(1, 0, 10), (3, 0, 10)]);
+    }
+
+    #[test]
+    fn share_pieces_between_peers() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 20,
+            },
+        );
+        let mut first_channel = RequestRecorder::new();
+        let mut second_channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 1, &mut first_channel)
+            .unwrap();
+        emitter
+            .fill_request_queue(1, &all_pieces(), 2, &mut second_channel)
+            .unwrap();
+
+        assert_eq!(first_channel.requests, vec![(0, 0, 10)]);
+        assert_eq!(second_channel.requests, vec![(0, 10, 10), (1, 0, 10)]);
+    }
+
+    #[test]
+    fn reissue_requests_of_choking_peer_to_another_peer() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 20,
+            },
+        );
+        let mut first_channel = RequestRecorder::new();
+        let mut second_channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 2, &mut first_channel)
+            .unwrap();
+        emitter.requests_dropped(0);
+        emitter
+            .fill_request_queue(1, &all_pieces(), 3, &mut second_channel)
+            .unwrap();
+
+        assert_eq!(emitter.pending_count(0), 0);
+        assert_eq!(
+            second_channel.requests,
+            vec![(0, 0, 10), (0, 10, 10), (1, 0, 10)]
+        );
+    }
+
+    #[test]
+    fn reissue_timed_out_request_to_another_peer_only() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 20,
+            },
+        );
+        let mut first_channel = RequestRecorder::new();
+        let mut second_channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 2, &mut first_channel)
+            .unwrap();
+        emitter.request_timed_out(0, 0, 10);
+        assert_eq!(emitter.pending_count(0), 1);
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 2, &mut first_channel)
+            .unwrap();
+        emitter
+            .fill_request_queue(1, &all_pieces(), 1, &mut second_channel)
+            .unwrap();
+
+        assert_eq!(
+            first_channel.requests,
+            vec![(0, 0, 10), (0, 10, 10), (1, 0, 10)]
+        );
+        assert_eq!(second_channel.requests, vec![(0, 10, 10)]);
+    }
+
+    #[test]
+    fn reissue_timed_out_request_to_same_peer_without_other_source() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 20,
+                piece_length: 20,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 2, &mut channel)
+            .unwrap();
+        emitter.request_timed_out(PEER, 0, 10);
+        emitter.release_timed_out_requests(|_, _| true);
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 2, &mut channel)
+            .unwrap();
+        assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 10)]);
+
+        emitter.release_timed_out_requests(|_, _| false);
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 2, &mut channel)
+            .unwrap();
+        assert_eq!(channel.requests, vec![(0, 0, 10), (0, 10, 10), (0, 10, 10)]);
+    }
+
+    #[test]
+    fn request_priority_pieces_first() {
+        let file_info = FileInfo {
+            piece_length: 10,
+            file_length: 50,
+        };
+        let mut emitter = RequestEmitter::new(10, file_info);
+        emitter.set_priority_pieces(vec![3, 4, 0]);
+        let mut recorder = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 5, &mut recorder)
+            .unwrap();
+        let pieces = recorder
+            .requests
+            .iter()
+            .map(|(piece_index, _, _)| *piece_index)
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, vec![3, 4, 0, 1, 2]);
+    }
+
+    #[test]
+    fn request_pieces_by_priority_and_never_skipped_ones() {
+        let file_info = FileInfo {
+            piece_length: 10,
+            file_length: 50,
+        };
+        let mut emitter = RequestEmitter::new(10, file_info);
+        emitter.set_piece_priorities(vec![
+            FilePriority::Skip,
+            FilePriority::Low,
+            FilePriority::Normal,
+            FilePriority::High,
+            FilePriority::Skip,
+        ]);
+        let mut recorder = RequestRecorder::new();
+
+        emitter
+            .fill_request_queue(0, &all_pieces(), 5, &mut recorder)
+            .unwrap();
+        let pieces = recorder
+            .requests
+            .iter()
+            .map(|(piece_index, _, _)| *piece_index)
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn request_failed_piece_again() {
+        let block_length = 10;
+        let mut emitter = RequestEmitter::new(
+            block_length,
+            FileInfo {
+                file_length: 40,
+                piece_length: 20,
+            },
+        );
+        let mut channel = RequestRecorder::new();
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 3, &mut channel)
+            .unwrap();
+        emitter.block_received(0, 0);
+        emitter.block_received(0, 10);
+
+        emitter.piece_failed(0);
+        emitter
+            .fill_request_queue(PEER, &all_pieces(), 3, &mut channel)
+            .unwrap();
+        assert_eq!(
+            channel.requests,
+            vec![(0, 0, 10), (0, 10, 10), (1, 0, 10), (0, 0, 10), (0, 10, 10)]
+        );
+    }
+
+    fn all_pieces() -> Bitfield {
+        Bitfield::from_bytes(&[0xff; 16])
+    }
+
     struct RequestRecorder {
         requests: Vec<(u32, u32, u32)>,
     }
@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub struct RequestPipeline {
+    block_length: u32,
+    queue_length: usize,
+    max_queue_length: usize,
+    sent_at: HashMap<(u32, u32), Instant>,
+    min_round_trip: Option<Duration>,
+    latency: Option<Duration>,
+    window_start: Instant,
+    window_bytes: u64,
+    waiting_since: Option<Instant>,
+    snubbed: bool,
+}
+
+impl RequestPipeline {
+    const INITIAL_QUEUE_LENGTH: usize = 4;
+    const MIN_QUEUE_LENGTH: usize = 2;
+    const MAX_QUEUE_LENGTH: usize = 500;
+    const MEASUREMENT_WINDOW: Duration = Duration::from_secs(1);
+    pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+    const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(180);
+
+    pub fn new(block_length: u32, now: Instant) -> Self {
+        Self {
+            block_length,
+            queue_length: Self::INITIAL_QUEUE_LENGTH,
+            max_queue_length: Self::MAX_QUEUE_LENGTH,
+            sent_at: HashMap::new(),
+            min_round_trip: None,
+            latency: None,
+            window_start: now,
+            window_bytes: 0,
+            waiting_since: None,
+            snubbed: false,
+        }
+    }
+
+    pub fn queue_length(&self) -> usize {
+        if self.snubbed { 1 } else { self.queue_length }
+    }
+
+    pub fn is_snubbed(&self) -> bool {
+        self.snubbed
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn set_peer_limit(&mut self, peer_request_queue: Option<u32>) {
+        self.max_queue_length = peer_request_queue
+            .map_or(Self::MAX_QUEUE_LENGTH, |limit| limit as usize)
+            .clamp(1, Self::MAX_QUEUE_LENGTH);
+        self.queue_length = self.queue_length.min(self.max_queue_length);
+    }
+
+    pub fn request_sent(&mut self, piece_index: u32, offset: u32, now: Instant) {
+        self.sent_at.insert((piece_index, offset), now);
+        self.waiting_since.get_or_insert(now);
+    }
+
+    pub fn requests_dropped(&mut self) {
+        self.sent_at.clear();
+        self.waiting_since = None;
+    }
+
+    pub fn check_timeouts(&mut self, now: Instant) -> Vec<(u32, u32)> {
+        let mut timed_out = self
+            .sent_at
+            .iter()
+            .filter(|(_, sent_at)| {
+                now.saturating_duration_since(**sent_at) >= Self::REQUEST_TIMEOUT
+            })
+            .map(|(block, _)| *block)
+            .collect::<Vec<_>>();
+        timed_out.sort_unstable();
+        for block in &timed_out {
+            self.sent_at.remove(block);
+        }
+
+        self.snubbed = self.waiting_time(now) >= Self::SNUB_TIMEOUT;
+        timed_out
+    }
+
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        self.waiting_time(now) >= Self::DISCONNECT_TIMEOUT
+    }
+
+    fn waiting_time(&self, now: Instant) -> Duration {
+        self.waiting_since
+            .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+
+    pub fn block_received(&mut self, piece_index: u32, offset: u32, length: usize, now: Instant) {
+        if let Some(sent_at) = self.sent_at.remove(&(piece_index, offset)) {
+            let round_trip = now.saturating_duration_since(sent_at);
+            self.min_round_trip = Some(
+                self.min_round_trip
+                    .map_or(round_trip, |min| min.min(round_trip)),
+            );
+            self.latency = Some(self.latency.map_or(round_trip, |latency| {
+                latency.mul_f64(7.0 / 8.0) + round_trip.mul_f64(1.0 / 8.0)
+            }));
+        }
+        self.waiting_since = (!self.sent_at.is_empty()).then_some(now);
+        self.snubbed = false;
+
+        self.window_bytes += length as u64;
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= Self::MEASUREMENT_WINDOW {
+            let throughput = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.adapt(throughput);
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+    }
+
+    fn adapt(&mut self, throughput: f64) {
+        let Some(round_trip) = self.min_round_trip else {
+            return;
+        };
+        let blocks_in_flight = throughput * round_trip.as_secs_f64() / self.block_length as f64;
+        let desired = (2.0 * blocks_in_flight).ceil() as usize;
+        self.queue_length = desired.clamp(
+            Self::MIN_QUEUE_LENGTH.min(self.max_queue_length),
+            self.max_queue_length,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_LENGTH: u32 = 1000;
+
+    fn receive_blocks(
+        pipeline: &mut RequestPipeline,
+        piece_index: u32,
+        start: Instant,
+        count: u32,
+        round_trip: Duration,
+        interval: Duration,
+    ) -> Instant {
+        for block in 0..count {
+            let sent_at = start + interval * block;
+            let offset = block * BLOCK_LENGTH;
+            pipeline.request_sent(piece_index, offset, sent_at);
+            pipeline.block_received(
+                piece_index,
+                offset,
+                BLOCK_LENGTH as usize,
+                sent_at + round_trip,
+            );
+        }
+        start + interval * count + round_trip
+    }
+
+    #[test]
+    fn start_with_short_queue() {
+        let pipeline = RequestPipeline::new(BLOCK_LENGTH, Instant::now());
+        assert_eq!(
+            pipeline.queue_length(),
+            RequestPipeline::INITIAL_QUEUE_LENGTH
+        );
+    }
+
+    #[test]
+    fn grow_queue_to_cover_bandwidth_delay_product() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+
+        receive_blocks(
+            &mut pipeline,
+            0,
+            start,
+            200,
+            Duration::from_millis(100),
+            Duration::from_millis(10),
+        );
+        assert_eq!(pipeline.queue_length(), 20);
+    }
+
+    #[test]
+    fn shrink_queue_when_peer_slows_down() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        let now = receive_blocks(
+            &mut pipeline,
+            0,
+            start,
+            200,
+            Duration::from_millis(100),
+            Duration::from_millis(10),
+        );
+
+        receive_blocks(
+            &mut pipeline,
+            1,
+            now,
+            30,
+            Duration::from_millis(100),
+            Duration::from_millis(100),
+        );
+        assert_eq!(pipeline.queue_length(), RequestPipeline::MIN_QUEUE_LENGTH);
+    }
+
+    #[test]
+    fn cap_queue_by_peer_request_limit() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        pipeline.set_peer_limit(Some(8));
+
+        receive_blocks(
+            &mut pipeline,
+            0,
+            start,
+            200,
+            Duration::from_millis(100),
+            Duration::from_millis(10),
+        );
+        assert_eq!(pipeline.queue_length(), 8);
+
+        pipeline.set_peer_limit(Some(3));
+        assert_eq!(pipeline.queue_length(), 3);
+    }
+
+    #[test]
+    fn cap_queue_at_maximum_when_peer_has_no_limit() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+
+        receive_blocks(
+            &mut pipeline,
+            0,
+            start,
+            20_000,
+            Duration::from_millis(500),
+            Duration::from_micros(50),
+        );
+        assert_eq!(pipeline.queue_length(), RequestPipeline::MAX_QUEUE_LENGTH);
+    }
+
+    #[test]
+    fn time_out_requests_left_unanswered() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        pipeline.request_sent(0, 0, start);
+        pipeline.request_sent(0, BLOCK_LENGTH, start + Duration::from_secs(5));
+
+        let now = start + RequestPipeline::REQUEST_TIMEOUT;
+        assert_eq!(pipeline.check_timeouts(now), vec![(0, 0)]);
+        assert!(pipeline.check_timeouts(now).is_empty());
+        assert!(!pipeline.is_snubbed());
+    }
+
+    #[test]
+    fn snub_peer_that_delivers_nothing_for_a_while() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        pipeline.request_sent(0, 0, start);
+
+        pipeline.check_timeouts(start + RequestPipeline::SNUB_TIMEOUT);
+        assert!(pipeline.is_snubbed());
+        assert_eq!(pipeline.queue_length(), 1);
+        assert!(!pipeline.is_stalled(start + RequestPipeline::SNUB_TIMEOUT));
+        assert!(pipeline.is_stalled(start + RequestPipeline::DISCONNECT_TIMEOUT));
+
+        pipeline.request_sent(0, BLOCK_LENGTH, start + RequestPipeline::SNUB_TIMEOUT);
+        pipeline.block_received(
+            0,
+            BLOCK_LENGTH,
+            BLOCK_LENGTH as usize,
+            start + RequestPipeline::SNUB_TIMEOUT,
+        );
+        assert!(!pipeline.is_snubbed());
+        assert!(pipeline.queue_length() > 1);
+    }
+
+    #[test]
+    fn do_not_snub_peer_we_are_not_requesting_from() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        pipeline.request_sent(0, 0, start);
+        pipeline.requests_dropped();
+
+        pipeline.check_timeouts(start + RequestPipeline::DISCONNECT_TIMEOUT);
+        assert!(!pipeline.is_snubbed());
+        assert!(!pipeline.is_stalled(start + RequestPipeline::DISCONNECT_TIMEOUT));
+    }
+
+    #[test]
+    fn smooth_latency_of_received_blocks() {
+        let start = Instant::now();
+        let mut pipeline = RequestPipeline::new(BLOCK_LENGTH, start);
+        assert_eq!(pipeline.latency(), None);
+
+        receive_blocks(
+            &mut pipeline,
+            0,
+            start,
+            1,
+            Duration::from_millis(800),
+            Duration::ZERO,
+        );
+        assert_eq!(pipeline.latency(), Some(Duration::from_millis(800)));
+
+        receive_blocks(
+            &mut pipeline,
+            1,
+            start,
+            1,
+            Duration::from_millis(0),
+            Duration::ZERO,
+        );
+        assert_eq!(pipeline.latency(), Some(Duration::from_millis(700)));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::PeerKey;
+
+struct RateMeter {
+    bytes: u64,
+    rate: f64,
+    last_sample: Instant,
+}
+
+impl RateMeter {
+    const TIME_CONSTANT: Duration = Duration::from_secs(5);
+
+    fn new(now: Instant) -> Self {
+        Self {
+            bytes: 0,
+            rate: 0.0,
+            last_sample: now,
+        }
+    }
+
+    fn add(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    fn sample(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_sample);
+        if elapsed.is_zero() {
+            return;
+        }
+        let current = self.bytes as f64 / elapsed.as_secs_f64();
+        let weight = 1.0 - (-elapsed.as_secs_f64() / Self::TIME_CONSTANT.as_secs_f64()).exp();
+        self.rate += weight * (current - self.rate);
+        self.bytes = 0;
+        self.last_sample = now;
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate.round() as u64
+    }
+}
+
+struct PeerMeters {
+    download: RateMeter,
+    upload: RateMeter,
+}
+
+pub struct PeerRates {
+    pub download_rate: u64,
+    pub upload_rate: u64,
+}
+
+pub struct StatsCollector {
+    download: RateMeter,
+    upload: RateMeter,
+    peers: BTreeMap<PeerKey, PeerMeters>,
+    wasted_bytes: u64,
+    hash_failures: u32,
+    last_sample: Instant,
+}
+
+impl StatsCollector {
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(now: Instant) -> Self {
+        Self {
+            download: RateMeter::new(now),
+            upload: RateMeter::new(now),
+            peers: BTreeMap::new(),
+            wasted_bytes: 0,
+            hash_failures: 0,
+            last_sample: now,
+        }
+    }
+
+    pub fn peer_connected(&mut self, peer: PeerKey, now: Instant) {
+        self.peers.insert(
+            peer,
+            PeerMeters {
+                download: RateMeter::new(now),
+                upload: RateMeter::new(now),
+            },
+        );
+    }
+
+    pub fn peer_disconnected(&mut self, peer: PeerKey) {
+        self.peers.remove(&peer);
+    }
+
+    pub fn bytes_downloaded(&mut self, peer: PeerKey, bytes: u64) {
+        self.download.add(bytes);
+        if let Some(meters) = self.peers.get_mut(&peer) {
+            meters.download.add(bytes);
+        }
+    }
+
+    pub fn bytes_uploaded(&mut self, peer: PeerKey, bytes: u64) {
+        self.upload.add(bytes);
+        if let Some(meters) = self.peers.get_mut(&peer) {
+            meters.upload.add(bytes);
+        }
+    }
+
+    pub fn bytes_wasted(&mut self, bytes: u64) {
+        self.wasted_bytes += bytes;
+    }
+
+    pub fn hash_failed(&mut self, piece_length: u64) {
+        self.hash_failures += 1;
+        self.wasted_bytes += piece_length;
+    }
+
+    pub fn is_sample_due(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_sample) >= Self::SAMPLE_INTERVAL
+    }
+
+    pub fn sample(&mut self, now: Instant) {
+        self.download.sample(now);
+        self.upload.sample(now);
+        for meters in self.peers.values_mut() {
+            meters.download.sample(now);
+            meters.upload.sample(now);
+        }
+        self.last_sample = now;
+    }
+
+    pub fn download_rate(&self) -> u64 {
+        self.download.rate()
+    }
+
+    pub fn upload_rate(&self) -> u64 {
+        self.upload.rate()
+    }
+
+    pub fn wasted_bytes(&self) -> u64 {
+        self.wasted_bytes
+    }
+
+    pub fn hash_failures(&self) -> u32 {
+        self.hash_failures
+    }
+
+    pub fn peer_rates(&self) -> impl Iterator<Item = (PeerKey, PeerRates)> + '_ {
+        self.peers.iter().map(|(peer, meters)| {
+            (
+                *peer,
+                PeerRates {
+                    download_rate: meters.download.rate(),
+                    upload_rate: meters.upload.rate(),
+                },
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_rate_towards_current_throughput() {
+        let start = Instant::now();
+        let mut meter = RateMeter::new(start);
+
+        meter.add(10_000);
+        meter.sample(start + Duration::from_secs(1));
+        let first = meter.rate();
+        assert!(first > 0 && first < 10_000);
+
+        for second in 2..=30 {
+            meter.add(10_000);
+            meter.sample(start + Duration::from_secs(second));
+        }
+        assert!(meter.rate().abs_diff(10_000) < 100);
+    }
+
+    #[test]
+    fn decay_rate_when_transfer_stops() {
+        let start = Instant::now();
+        let mut meter = RateMeter::new(start);
+        meter.rate = 10_000.0;
+
+        meter.sample(start + Duration::from_secs(5));
+        assert!(meter.rate() < 4_000);
+    }
+
+    #[test]
+    fn track_rates_per_peer() {
+        let start = Instant::now();
+        let mut collector = StatsCollector::new(start);
+        collector.peer_connected(0, start);
+        collector.peer_connected(1, start);
+
+        collector.bytes_downloaded(0, 5_000);
+        collector.bytes_uploaded(1, 2_000);
+        collector.sample(start + Duration::from_secs(1));
+
+        let rates = collector.peer_rates().collect::<Vec<_>>();
+        assert!(rates[0].1.download_rate > 0);
+        assert_eq!(rates[0].1.upload_rate, 0);
+        assert_eq!(rates[1].1.download_rate, 0);
+        assert!(rates[1].1.upload_rate > 0);
+        assert!(collector.download_rate() > collector.upload_rate());
+    }
+
+    #[test]
+    fn count_hash_failures_as_waste() {
+        let mut collector = StatsCollector::new(Instant::now());
+        collector.bytes_wasted(100);
+        collector.hash_failed(1000);
+
+        assert_eq!(collector.hash_failures(), 1);
+        assert_eq!(collector.wasted_bytes(), 1100);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use super::PeerKey;
+use crate::types::Bitfield;
+
+pub struct SuperSeeder {
+    offers: HashMap<PeerKey, u32>,
+    offered_pieces: HashMap<PeerKey, HashSet<u32>>,
+}
+
+impl SuperSeeder {
+    pub fn new() -> Self {
+        Self {
+            offers: HashMap::new(),
+            offered_pieces: HashMap::new(),
+        }
+    }
+
+    pub fn peer_disconnected(&mut self, peer: PeerKey) {
+        self.offers.remove(&peer);
+        self.offered_pieces.remove(&peer);
+    }
+
+    pub fn is_offered(&self, peer: PeerKey, piece_index: u32) -> bool {
+        self.offered_pieces
+            .get(&peer)
+            .is_some_and(|pieces| pieces.contains(&piece_index))
+    }
+
+    pub fn make_offer(
+        &mut self,
+        peer: PeerKey,
+        peer_pieces: &Bitfield,
+        availability: &[usize],
+    ) -> Option<u32> {
+        if let Some(offer) = self.offers.get(&peer)
+            && !peer_pieces.has_piece(*offer)
+        {
+            return None;
+        }
+
+        let piece_index = (0..availability.len() as u32)
+            .filter(|index| !peer_pieces.has_piece(*index))
+            .min_by_key(|index| {
+                (
+                    self.offer_count(*index),
+                    availability[*index as usize],
+                    *index,
+                )
+            })?;
+        self.offers.insert(peer, piece_index);
+        self.offered_pieces
+            .entry(peer)
+            .or_default()
+            .insert(piece_index);
+        Some(piece_index)
+    }
+
+    pub fn piece_announced(&mut self, peer: PeerKey, piece_index: u32) -> Vec<PeerKey> {
+        let propagated = self
+            .offers
+            .iter()
+            .filter(|(offered_to, offer)| **offered_to != peer && **offer == piece_index)
+            .map(|(offered_to, _)| *offered_to)
+            .collect::<Vec<_>>();
+        for offered_to in &propagated {
+            self.offers.remove(offered_to);
+        }
+        propagated
+    }
+
+    fn offer_count(&self, piece_index: u32) -> usize {
+        self.offers
+            .values()
+            .filter(|offer| **offer == piece_index)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offer_different_pieces_to_each_peer() {
+        let mut seeder = SuperSeeder::new();
+        let no_pieces = Bitfield::new(3);
+
+        assert_eq!(seeder.make_offer(0, &no_pieces, &[0, 0, 0]), Some(0));
+        assert_eq!(seeder.make_offer(1, &no_pieces, &[0, 0, 0]), Some(1));
+        assert_eq!(seeder.make_offer(2, &no_pieces, &[0, 0, 0]), Some(2));
+        assert!(seeder.is_offered(1, 1));
+        assert!(!seeder.is_offered(1, 0));
+    }
+
+    #[test]
+    fn offer_rarest_piece_the_peer_does_not_have() {
+        let mut seeder = SuperSeeder::new();
+        let mut peer_pieces = Bitfield::new(4);
+        peer_pieces.set_piece(2);
+
+        assert_eq!(seeder.make_offer(0, &peer_pieces, &[2, 1, 0, 3]), Some(1));
+    }
+
+    #[test]
+    fn keep_single_offer_until_it_propagates() {
+        let mut seeder = SuperSeeder::new();
+        let no_pieces = Bitfield::new(3);
+        assert_eq!(seeder.make_offer(0, &no_pieces, &[0, 0, 0]), Some(0));
+        assert_eq!(seeder.make_offer(0, &no_pieces, &[0, 0, 0]), None);
+
+        assert!(seeder.piece_announced(0, 0).is_empty());
+        assert_eq!(seeder.piece_announced(1, 0), vec![0]);
+        assert_eq!(seeder.make_offer(0, &no_pieces, &[2, 0, 0]), Some(1));
+        assert!(seeder.is_offered(0, 0));
+        assert!(seeder.is_offered(0, 1));
+    }
+
+    #[test]
+    fn replace_offer_of_piece_the_peer_already_has() {
+        let mut seeder = SuperSeeder::new();
+        assert_eq!(seeder.make_offer(0, &Bitfield::new(2), &[0, 0]), Some(0));
+
+        let mut peer_pieces = Bitfield::new(2);
+        peer_pieces.set_piece(0);
+        assert_eq!(seeder.make_offer(0, &peer_pieces, &[1, 0]), Some(1));
+    }
+
+    #[test]
+    fn forget_offers_of_disconnected_peer() {
+        let mut seeder = SuperSeeder::new();
+        let no_pieces = Bitfield::new(2);
+        seeder.make_offer(0, &no_pieces, &[0, 0]);
+        seeder.peer_disconnected(0);
+
+        assert!(!seeder.is_offered(0, 0));
+        assert_eq!(seeder.make_offer(1, &no_pieces, &[0, 0]), Some(0));
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{BlockRequest, PeerKey};
+
+pub struct UploadQueue {
+    max_requests_per_peer: usize,
+    requests: HashMap<PeerKey, VecDeque<BlockRequest>>,
+    peer_order: VecDeque<PeerKey>,
+}
+
+impl UploadQueue {
+    pub fn new(max_requests_per_peer: usize) -> Self {
+        Self {
+            max_requests_per_peer,
+            requests: HashMap::new(),
+            peer_order: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.values().all(VecDeque::is_empty)
+    }
+
+    pub fn push(&mut self, peer: PeerKey, request: BlockRequest) -> bool {
+        let requests = self.requests.entry(peer).or_default();
+        if requests.len() >= self.max_requests_per_peer || requests.contains(&request) {
+            return false;
+        }
+
+        if requests.is_empty() {
+            self.peer_order.push_back(peer);
+        }
+        requests.push_back(request);
+        true
+    }
+
+    pub fn cancel(&mut self, peer: PeerKey, request: BlockRequest) {
+        if let Some(requests) = self.requests.get_mut(&peer) {
+            requests.retain(|queued| *queued != request);
+        }
+    }
+
+    pub fn clear(&mut self, peer: PeerKey) {
+        self.requests.remove(&peer);
+    }
+
+    pub fn pop(&mut self) -> Option<(PeerKey, BlockRequest)> {
+        while let Some(peer) = self.peer_order.pop_front() {
+            let Some(requests) = self.requests.get_mut(&peer) else {
+                continue;
+            };
+            let Some(request) = requests.pop_front() else {
+                continue;
+            };
+            if !requests.is_empty() {
+                self.peer_order.push_back(peer);
+            }
+            return Some((peer, request));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(piece_index: u32, offset: u32) -> BlockRequest {
+        BlockRequest {
+            piece_index,
+            offset,
+            length: 10,
+        }
+    }
+
+    #[test]
+    fn serve_peers_in_turn() {
+        let mut queue = UploadQueue::new(10);
+        queue.push(0, request(0, 0));
+        queue.push(0, request(0, 10));
+        queue.push(1, request(1, 0));
+
+        assert_eq!(queue.pop(), Some((0, request(0, 0))));
+        assert_eq!(queue.pop(), Some((1, request(1, 0))));
+        assert_eq!(queue.pop(), Some((0, request(0, 10))));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn limit_outstanding_requests_per_peer() {
+        let mut queue = UploadQueue::new(2);
+
+        assert!(queue.push(0, request(0, 0)));
+        assert!(queue.push(0, request(0, 10)));
+        assert!(!queue.push(0, request(0, 20)));
+        assert!(queue.push(1, request(0, 20)));
+    }
+
+    #[test]
+    fn ignore_duplicate_requests() {
+        let mut queue = UploadQueue::new(10);
+
+        assert!(queue.push(0, request(0, 0)));
+        assert!(!queue.push(0, request(0, 0)));
+    }
+
+    #[test]
+    fn drop_cancelled_requests() {
+        let mut queue = UploadQueue::new(10);
+        queue.push(0, request(0, 0));
+        queue.push(0, request(0, 10));
+
+        queue.cancel(0, request(0, 0));
+        assert_eq!(queue.pop(), Some((0, request(0, 10))));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn drop_all_requests_of_choked_peer() {
+        let mut queue = UploadQueue::new(10);
+        queue.push(0, request(0, 0));
+        queue.push(1, request(1, 0));
+
+        queue.clear(0);
+        assert_eq!(queue.pop(), Some((1, request(1, 0))));
+        assert_eq!(queue.pop(), None);
+    }
+}
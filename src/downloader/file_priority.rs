@@ -0,0 +1,63 @@
+use crate::storage::StorageLayout;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilePriority {
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FilePriority {
+    pub fn piece_priorities(layout: &StorageLayout, file_priorities: &[FilePriority]) -> Vec<Self> {
+        let mut pieces = vec![FilePriority::Skip; layout.piece_count() as usize];
+        for file_index in 0..layout.files().len() {
+            if !layout.files()[file_index].has_data() {
+                continue;
+            }
+            let priority = file_priorities.get(file_index).copied().unwrap_or_default();
+            for piece_index in layout.file_pieces(file_index) {
+                let piece = &mut pieces[piece_index as usize];
+                *piece = (*piece).max(priority);
+            }
+        }
+        pieces
+    }
+
+    pub fn skipped_files(file_priorities: &[FilePriority]) -> Vec<bool> {
+        file_priorities
+            .iter()
+            .map(|priority| *priority == FilePriority::Skip)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::torrent::FileEntry;
+
+    use super::*;
+
+    #[test]
+    fn piece_takes_highest_priority_of_overlapping_files() {
+        let files = [25, 10, 30, 5]
+            .iter()
+            .enumerate()
+            .map(|(index, length)| FileEntry {
+                path: PathBuf::from(format!("file-{index}")),
+                length: *length,
+                ..Default::default()
+            })
+            .collect();
+        let layout = StorageLayout::new(10, files);
+
+        use FilePriority::*;
+        assert_eq!(
+            FilePriority::piece_priorities(&layout, &[Skip, High, Skip]),
+            vec![Skip, Skip, High, High, Skip, Skip, Normal]
+        );
+    }
+}
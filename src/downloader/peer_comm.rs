@@ -1,7 +1,12 @@
+mod connection_state;
+mod extension_handshake;
 mod handshake_message;
+mod message_buffer;
 mod peer_channel;
 mod peer_message;
 
+pub use connection_state::ConnectionState;
+pub use extension_handshake::ExtensionHandshake;
 pub use handshake_message::HandshakeMessage;
 pub use peer_channel::PeerChannel;
 pub use peer_message::PeerMessage;
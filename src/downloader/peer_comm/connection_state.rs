@@ -0,0 +1,198 @@
+use std::io;
+
+use crate::types::Bitfield;
+
+use super::{ExtensionHandshake, PeerMessage};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionState {
+    pub am_choking: bool,
+    pub am_interested: bool,
+    pub peer_choking: bool,
+    pub peer_interested: bool,
+    pub peer_pieces: Bitfield,
+    pub peer_request_queue: Option<u32>,
+    pub piece_count: Option<usize>,
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self {
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+            peer_pieces: Bitfield::default(),
+            peer_request_queue: None,
+            piece_count: None,
+        }
+    }
+}
+
+impl ConnectionState {
+    pub fn with_piece_count(mut self, piece_count: usize) -> Self {
+        if self.peer_pieces.as_bytes().len() != piece_count.div_ceil(8) {
+            self.peer_pieces = Bitfield::new(piece_count);
+        }
+        self.piece_count = Some(piece_count);
+        self
+    }
+
+    pub fn message_received(&mut self, msg: &PeerMessage) -> io::Result<()> {
+        self.check_piece_range(msg)?;
+        match msg {
+            PeerMessage::Choke => self.peer_choking = true,
+            PeerMessage::Unchoke => self.peer_choking = false,
+            PeerMessage::Interested => self.peer_interested = true,
+            PeerMessage::NotInterested => self.peer_interested = false,
+            PeerMessage::Have(piece_index) => self.peer_pieces.set_piece(*piece_index),
+            PeerMessage::Bitfield(bitfield) => self.peer_pieces = Bitfield::from_bytes(bitfield),
+            PeerMessage::Extended {
+                id: ExtensionHandshake::MESSAGE_ID,
+                payload,
+            } => {
+                if let Ok(handshake) = ExtensionHandshake::from_payload(payload) {
+                    self.peer_request_queue = handshake.reqq;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn check_piece_range(&self, msg: &PeerMessage) -> io::Result<()> {
+        let Some(piece_count) = self.piece_count else {
+            return Ok(());
+        };
+        match msg {
+            PeerMessage::Have(piece_index) if *piece_index as usize >= piece_count => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Peer announced piece {} of a torrent with {} pieces",
+                        piece_index, piece_count
+                    ),
+                ))
+            }
+            PeerMessage::Bitfield(bitfield) if bitfield.len() != piece_count.div_ceil(8) => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Peer sent a bitfield of {} bytes for a torrent with {} pieces",
+                        bitfield.len(),
+                        piece_count
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn message_sent(&mut self, msg: &PeerMessage) {
+        match msg {
+            PeerMessage::Choke => self.am_choking = true,
+            PeerMessage::Unchoke => self.am_choking = false,
+            PeerMessage::Interested => self.am_interested = true,
+            PeerMessage::NotInterested => self.am_interested = false,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_state_is_choked_and_not_interested() {
+        let state = ConnectionState::default();
+        assert!(state.am_choking);
+        assert!(!state.am_interested);
+        assert!(state.peer_choking);
+        assert!(!state.peer_interested);
+    }
+
+    #[test]
+    fn track_peer_choking() {
+        let mut state = ConnectionState::default();
+
+        state.message_received(&PeerMessage::Unchoke).unwrap();
+        assert!(!state.peer_choking);
+
+        state.message_received(&PeerMessage::Choke).unwrap();
+        assert!(state.peer_choking);
+    }
+
+    #[test]
+    fn track_peer_interest() {
+        let mut state = ConnectionState::default();
+
+        state.message_received(&PeerMessage::Interested).unwrap();
+        assert!(state.peer_interested);
+
+        state.message_received(&PeerMessage::NotInterested).unwrap();
+        assert!(!state.peer_interested);
+    }
+
+    #[test]
+    fn track_own_choking_and_interest() {
+        let mut state = ConnectionState::default();
+
+        state.message_sent(&PeerMessage::Interested);
+        state.message_sent(&PeerMessage::Unchoke);
+        assert!(state.am_interested);
+        assert!(!state.am_choking);
+
+        state.message_sent(&PeerMessage::NotInterested);
+        state.message_sent(&PeerMessage::Choke);
+        assert!(!state.am_interested);
+        assert!(state.am_choking);
+    }
+
+    #[test]
+    fn track_request_queue_length_from_extension_handshake() {
+        let mut state = ConnectionState::default();
+
+        state
+            .message_received(&PeerMessage::Extended {
+                id: ExtensionHandshake::MESSAGE_ID,
+                payload: b"d4:reqqi32ee".to_vec(),
+            })
+            .unwrap();
+        assert_eq!(state.peer_request_queue, Some(32));
+    }
+
+    #[test]
+    fn update_peer_pieces_on_bitfield_and_have() {
+        let mut state = ConnectionState::default();
+
+        state
+            .message_received(&PeerMessage::Bitfield(vec![0b1000_0000, 0]))
+            .unwrap();
+        state.message_received(&PeerMessage::Have(9)).unwrap();
+
+        assert!(state.peer_pieces.has_piece(0));
+        assert!(!state.peer_pieces.has_piece(1));
+        assert!(state.peer_pieces.has_piece(9));
+    }
+
+    #[test]
+    fn reject_pieces_out_of_torrent_range() {
+        let mut state = ConnectionState::default().with_piece_count(10);
+        assert_eq!(state.peer_pieces.as_bytes(), &[0, 0]);
+
+        let err = state
+            .message_received(&PeerMessage::Have(u32::MAX))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(state.peer_pieces.as_bytes(), &[0, 0]);
+
+        let err = state
+            .message_received(&PeerMessage::Bitfield(vec![0xff; 1 << 20]))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        state.message_received(&PeerMessage::Have(9)).unwrap();
+        assert!(state.peer_pieces.has_piece(9));
+    }
+}
@@ -0,0 +1,86 @@
+use std::{collections::BTreeMap, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::PeerMessage;
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionHandshake {
+    #[serde(default)]
+    pub m: BTreeMap<String, i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reqq: Option<u32>,
+}
+
+impl ExtensionHandshake {
+    pub const MESSAGE_ID: u8 = 0;
+    pub const REQUEST_QUEUE_LENGTH: u32 = 64;
+
+    pub fn ours() -> Self {
+        Self {
+            m: BTreeMap::new(),
+            v: Some(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )),
+            reqq: Some(Self::REQUEST_QUEUE_LENGTH),
+        }
+    }
+
+    pub fn from_payload(payload: &[u8]) -> io::Result<Self> {
+        serde_bencode::from_bytes(payload).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid extension handshake: {}", err),
+            )
+        })
+    }
+
+    pub fn to_message(&self) -> PeerMessage {
+        PeerMessage::Extended {
+            id: Self::MESSAGE_ID,
+            payload: serde_bencode::to_bytes(self).expect("handshake is always serializable"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_handshake_with_request_queue_length() {
+        let handshake =
+            ExtensionHandshake::from_payload(b"d1:md11:ut_metadatai3ee4:reqqi250e1:v5:qBit!e")
+                .unwrap();
+        assert_eq!(handshake.reqq, Some(250));
+        assert_eq!(handshake.m.get("ut_metadata"), Some(&3));
+    }
+
+    #[test]
+    fn parse_handshake_without_optional_fields() {
+        let handshake = ExtensionHandshake::from_payload(b"de").unwrap();
+        assert_eq!(handshake, ExtensionHandshake::default());
+    }
+
+    #[test]
+    fn error_on_malformed_handshake() {
+        let err = ExtensionHandshake::from_payload(b"d4:reqq").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn send_our_handshake_as_extended_message() {
+        let PeerMessage::Extended { id, payload } = ExtensionHandshake::ours().to_message() else {
+            panic!("Expected an extended message");
+        };
+        assert_eq!(id, ExtensionHandshake::MESSAGE_ID);
+        assert_eq!(
+            ExtensionHandshake::from_payload(&payload).unwrap().reqq,
+            Some(ExtensionHandshake::REQUEST_QUEUE_LENGTH)
+        );
+    }
+}
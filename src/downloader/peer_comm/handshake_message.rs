@@ -6,6 +6,8 @@ use crate::{
 };
 
 const PROTOCOL_ID: &[u8; 19] = b"BitTorrent protocol";
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
 
 #[derive(Debug, PartialEq, Default, Clone, Copy, Eq)]
 #[repr(C, packed)]
@@ -34,12 +36,16 @@ impl HandshakeMessage {
         Self {
             pstrlen: PROTOCOL_ID.len() as u8,
             pstr: *PROTOCOL_ID,
-            reserved: [0; 8],
+            reserved: [0, 0, 0, 0, 0, EXTENSION_PROTOCOL_BIT, 0, 0],
             info_hash,
             peer_id,
         }
     }
 
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0
+    }
+
     pub fn receive(src: &mut impl io::Read) -> io::Result<Self> {
         let mut instance = Self::default();
         let buffer_ptr = &mut instance as *mut Self as *mut [u8; size_of::<Self>()];
@@ -91,14 +97,15 @@ mod tests {
         assert_eq!(
             vec![
                 19, 66, 105, 116, 84, 111, 114, 114, 101, 110, 116, 32, 112, 114, 111, 116, 111,
-                99, 111, 108, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-                1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2
+                99, 111, 108, 0, 0, 0, 0, 0, 0x10, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+                1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2
             ],
             buffer
         );
 
         let received_message = HandshakeMessage::receive(&mut buffer.as_slice()).unwrap();
         assert_eq!(message_to_send, received_message);
+        assert!(received_message.supports_extensions());
     }
 
     #[test]
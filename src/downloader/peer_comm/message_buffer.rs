@@ -0,0 +1,114 @@
+use std::io;
+
+use super::PeerMessage;
+
+pub struct MessageBuffer {
+    buffer: Vec<u8>,
+}
+
+impl MessageBuffer {
+    const MESSAGE_LENGTH_SIZE: usize = 4;
+    const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self { buffer: vec![] }
+    }
+
+    pub fn fill(&mut self, src: &mut impl io::Read) -> io::Result<usize> {
+        let mut chunk = [0; Self::READ_CHUNK_SIZE];
+        match src.read(&mut chunk)? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Received zero bytes from stream",
+            )),
+            n => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                Ok(n)
+            }
+        }
+    }
+
+    pub fn next_message(&mut self) -> io::Result<Option<PeerMessage>> {
+        if self.buffer.len() < Self::MESSAGE_LENGTH_SIZE {
+            return Ok(None);
+        }
+
+        let length_bytes = self.buffer[..Self::MESSAGE_LENGTH_SIZE].try_into().unwrap();
+        let msg_len = u32::from_be_bytes(length_bytes) as usize;
+        if msg_len > PeerMessage::MAX_MESSAGE_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Message length is too big: {}", msg_len),
+            ));
+        }
+
+        let msg_end = Self::MESSAGE_LENGTH_SIZE + msg_len;
+        if self.buffer.len() < msg_end {
+            return Ok(None);
+        }
+
+        let message = PeerMessage::from_bytes(&self.buffer[Self::MESSAGE_LENGTH_SIZE..msg_end]);
+        self.buffer.drain(..msg_end);
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_message_in_empty_buffer() {
+        let mut buffer = MessageBuffer::new();
+        assert_eq!(buffer.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn error_when_source_is_exhausted() {
+        let mut buffer = MessageBuffer::new();
+        let mut src: &[u8] = &[];
+        let err = buffer.fill(&mut src).expect_err("expected error");
+
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn assemble_message_from_multiple_reads() {
+        let mut buffer = MessageBuffer::new();
+
+        buffer.fill(&mut &[0, 0, 0][..]).unwrap();
+        assert_eq!(buffer.next_message().unwrap(), None);
+
+        buffer.fill(&mut &[5, 4, 0][..]).unwrap();
+        assert_eq!(buffer.next_message().unwrap(), None);
+
+        buffer.fill(&mut &[0, 0, 7, 0, 0][..]).unwrap();
+        assert_eq!(buffer.next_message().unwrap(), Some(PeerMessage::Have(7)));
+        assert_eq!(buffer.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn read_multiple_messages_from_single_read() {
+        let mut buffer = MessageBuffer::new();
+
+        buffer.fill(&mut &[0, 0, 0, 0, 0, 0, 0, 1, 1][..]).unwrap();
+        assert_eq!(buffer.next_message().unwrap(), Some(PeerMessage::KeepAlive));
+        assert_eq!(buffer.next_message().unwrap(), Some(PeerMessage::Unchoke));
+        assert_eq!(buffer.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn reading_too_large_message_length() {
+        let too_large_length = PeerMessage::MAX_MESSAGE_LENGTH + 1;
+        let mut buffer = MessageBuffer::new();
+        buffer
+            .fill(&mut &(too_large_length as u32).to_be_bytes()[..])
+            .unwrap();
+        let err = buffer.next_message().unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Message length is too big: {}", too_large_length)
+        );
+    }
+}
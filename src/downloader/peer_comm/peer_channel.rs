@@ -1,32 +1,80 @@
 use std::{
     io,
     net::{SocketAddr, TcpStream},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::types::PeerId;
+use crate::{downloader::RateLimits, types::PeerId};
 
-use super::PeerMessage;
+use super::{ConnectionState, PeerMessage, message_buffer::MessageBuffer};
 
 pub struct PeerChannel {
     peer_addr: SocketAddr,
     remote_id: PeerId,
-    pub stream: TcpStream,
+    stream: TcpStream,
+    state: ConnectionState,
+    read_buffer: MessageBuffer,
+    last_received: Instant,
+    last_sent: Instant,
+    message_timeout: Duration,
+    keep_alive_interval: Duration,
+    rate_limits: RateLimits,
 }
 
 impl PeerChannel {
     const MESSAGE_READ_TIMEOUT: Duration = Duration::from_secs(60);
+    const MESSAGE_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+    const READ_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
     pub fn from_stream(stream: TcpStream, remote_id: PeerId) -> io::Result<PeerChannel> {
         let peer_addr = stream.peer_addr()?;
-        stream.set_read_timeout(Some(Self::MESSAGE_READ_TIMEOUT))?;
+        stream.set_read_timeout(Some(Self::READ_POLL_INTERVAL))?;
+        stream.set_write_timeout(Some(Self::MESSAGE_WRITE_TIMEOUT))?;
         Ok(PeerChannel {
             stream,
             remote_id,
             peer_addr,
+            state: ConnectionState::default(),
+            read_buffer: MessageBuffer::new(),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            message_timeout: Self::MESSAGE_READ_TIMEOUT,
+            keep_alive_interval: Self::KEEP_ALIVE_INTERVAL,
+            rate_limits: RateLimits::default(),
         })
     }
 
+    pub fn with_state(mut self, state: ConnectionState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn with_piece_count(mut self, piece_count: usize) -> Self {
+        self.state = self.state.with_piece_count(piece_count);
+        self
+    }
+
+    pub fn without_keep_alives(mut self) -> Self {
+        self.keep_alive_interval = Duration::MAX;
+        self
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_timeouts(mut self, message_timeout: Duration, keep_alive_interval: Duration) -> Self {
+        self.stream
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        self.message_timeout = message_timeout;
+        self.keep_alive_interval = keep_alive_interval;
+        self
+    }
+
     pub fn peer_addr(&self) -> SocketAddr {
         self.peer_addr
     }
@@ -35,11 +83,126 @@ impl PeerChannel {
         self.remote_id
     }
 
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    pub fn try_clone_stream(&self) -> io::Result<TcpStream> {
+        self.stream.try_clone()
+    }
+
     pub fn receive(&mut self) -> io::Result<PeerMessage> {
-        PeerMessage::receive(&mut self.stream)
+        loop {
+            if let Some(msg) = self.read_buffer.next_message()? {
+                self.state.message_received(&msg)?;
+                if msg != PeerMessage::KeepAlive {
+                    return Ok(msg);
+                }
+                continue;
+            }
+
+            self.send_keep_alive_if_idle()?;
+            self.fill_read_buffer()?;
+        }
     }
 
     pub fn send(&mut self, msg: &PeerMessage) -> io::Result<()> {
-        msg.send(&mut self.stream)
+        if let PeerMessage::Piece { block, .. } = msg {
+            self.rate_limits.upload.acquire(block.len());
+        }
+        msg.send(&mut self.stream)?;
+        self.state.message_sent(msg);
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn send_keep_alive_if_idle(&mut self) -> io::Result<()> {
+        if self.last_sent.elapsed() >= self.keep_alive_interval {
+            self.send(&PeerMessage::KeepAlive)?;
+        }
+        Ok(())
+    }
+
+    fn fill_read_buffer(&mut self) -> io::Result<()> {
+        match self.read_buffer.fill(&mut self.stream) {
+            Ok(bytes_read) => {
+                self.rate_limits.download.acquire(bytes_read);
+                self.last_received = Instant::now();
+                Ok(())
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                if self.last_received.elapsed() >= self.message_timeout {
+                    Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "No messages received from peer",
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn receive_skips_keep_alives_and_tracks_state() {
+        let (mut remote, mut channel) = connected_pair();
+
+        PeerMessage::KeepAlive.send(&mut remote).unwrap();
+        PeerMessage::Have(3).send(&mut remote).unwrap();
+        PeerMessage::Unchoke.send(&mut remote).unwrap();
+
+        assert_eq!(channel.receive().unwrap(), PeerMessage::Have(3));
+        assert_eq!(channel.receive().unwrap(), PeerMessage::Unchoke);
+        assert!(channel.state().peer_pieces.has_piece(3));
+        assert!(!channel.state().peer_choking);
+    }
+
+    #[test]
+    fn send_keep_alive_while_waiting_for_messages() {
+        let (mut remote, channel) = connected_pair();
+        let mut channel = channel.with_timeouts(Duration::from_secs(10), Duration::from_millis(50));
+
+        let remote_thread = thread::spawn(move || {
+            let mut buffer = [0xff; 4];
+            remote.read_exact(&mut buffer).unwrap();
+            PeerMessage::Choke.send(&mut remote).unwrap();
+            buffer
+        });
+
+        assert_eq!(channel.receive().unwrap(), PeerMessage::Choke);
+        assert_eq!(remote_thread.join().unwrap(), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn error_when_peer_is_silent_for_too_long() {
+        let (_remote, channel) = connected_pair();
+        let mut channel =
+            channel.with_timeouts(Duration::from_millis(100), Duration::from_secs(10));
+
+        let err = channel.receive().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    fn connected_pair() -> (TcpStream, PeerChannel) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (remote, _) = listener.accept().unwrap();
+        let channel = PeerChannel::from_stream(local, PeerId::default()).unwrap();
+        (remote, channel)
     }
 }
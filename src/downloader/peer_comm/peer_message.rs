@@ -4,9 +4,13 @@ use crate::downloader::peer_comm::AsyncReadExact;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PeerMessage {
-    Bitfield(Vec<u8>),
-    Interested,
+    KeepAlive,
+    Choke,
     Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
     Request {
         piece_index: u32,
         offset: u32,
@@ -17,6 +21,15 @@ pub enum PeerMessage {
         offset: u32,
         block: Vec<u8>,
     },
+    Cancel {
+        piece_index: u32,
+        offset: u32,
+        length: u32,
+    },
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
     Unknown {
         id: u8,
         payload: Vec<u8>,
@@ -28,13 +41,28 @@ impl PeerMessage {
     pub const MAX_MESSAGE_LENGTH: usize = 128 * 1024; // 128KB
 
     pub fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.is_empty() {
+            return Self::KeepAlive;
+        }
+
         let id = bytes[0];
         let payload = &bytes[1..];
         match id {
+            0 => Self::Choke,
             1 => Self::Unchoke,
             2 => Self::Interested,
+            3 => Self::NotInterested,
+            4 if payload.len() == 4 => Self::Have(u32::from_be_bytes(payload.try_into().unwrap())),
             5 => Self::Bitfield(payload.to_vec()),
-            7 => {
+            6 if payload.len() == 12 => {
+                let (piece_index, offset, length) = Self::parse_block_request(payload);
+                Self::Request {
+                    piece_index,
+                    offset,
+                    length,
+                }
+            }
+            7 if payload.len() >= 8 => {
                 let piece_index = u32::from_be_bytes(payload[0..4].try_into().unwrap());
                 let offset = u32::from_be_bytes(payload[4..8].try_into().unwrap());
                 let block = payload[8..].to_vec();
@@ -44,6 +72,18 @@ impl PeerMessage {
                     block,
                 }
             }
+            8 if payload.len() == 12 => {
+                let (piece_index, offset, length) = Self::parse_block_request(payload);
+                Self::Cancel {
+                    piece_index,
+                    offset,
+                    length,
+                }
+            }
+            20 if !payload.is_empty() => Self::Extended {
+                id: payload[0],
+                payload: payload[1..].to_vec(),
+            },
             _ => Self::Unknown {
                 id,
                 payload: payload.to_vec(),
@@ -51,8 +91,32 @@ impl PeerMessage {
         }
     }
 
+    fn parse_block_request(payload: &[u8]) -> (u32, u32, u32) {
+        let piece_index = u32::from_be_bytes(payload[0..4].try_into().unwrap());
+        let offset = u32::from_be_bytes(payload[4..8].try_into().unwrap());
+        let length = u32::from_be_bytes(payload[8..12].try_into().unwrap());
+        (piece_index, offset, length)
+    }
+
     pub fn send(&self, dst: &mut impl io::Write) -> io::Result<()> {
         match self {
+            Self::KeepAlive => {
+                let msg = vec![0, 0, 0, 0];
+                dst.write_all(&msg)
+            }
+            Self::Choke => {
+                let msg = vec![0, 0, 0, 1, 0];
+                dst.write_all(&msg)
+            }
+            Self::NotInterested => {
+                let msg = vec![0, 0, 0, 1, 3];
+                dst.write_all(&msg)
+            }
+            Self::Have(piece_index) => {
+                let mut msg = vec![0, 0, 0, 5, 4];
+                msg.extend_from_slice(&piece_index.to_be_bytes());
+                dst.write_all(&msg)
+            }
             Self::Bitfield(bitfield) => {
                 let mut msg = vec![];
                 msg.extend_from_slice(&(bitfield.len() as u32 + 1).to_be_bytes());
@@ -79,6 +143,38 @@ impl PeerMessage {
                 msg.extend_from_slice(&length.to_be_bytes());
                 dst.write_all(&msg)
             }
+            Self::Piece {
+                piece_index,
+                offset,
+                block,
+            } => {
+                let mut msg = vec![];
+                msg.extend_from_slice(&(block.len() as u32 + 9).to_be_bytes());
+                msg.push(7);
+                msg.extend_from_slice(&piece_index.to_be_bytes());
+                msg.extend_from_slice(&offset.to_be_bytes());
+                msg.extend_from_slice(block);
+                dst.write_all(&msg)
+            }
+            Self::Cancel {
+                piece_index,
+                offset,
+                length,
+            } => {
+                let mut msg = vec![0, 0, 0, 13, 8];
+                msg.extend_from_slice(&piece_index.to_be_bytes());
+                msg.extend_from_slice(&offset.to_be_bytes());
+                msg.extend_from_slice(&length.to_be_bytes());
+                dst.write_all(&msg)
+            }
+            Self::Extended { id, payload } => {
+                let mut msg = vec![];
+                msg.extend_from_slice(&(payload.len() as u32 + 2).to_be_bytes());
+                msg.push(20);
+                msg.push(*id);
+                msg.extend_from_slice(payload);
+                dst.write_all(&msg)
+            }
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Does not support sending message of type: {:?}", self),
@@ -255,6 +351,142 @@ mod tests {
         );
     }
 
+    #[test]
+    fn send_piece_message() {
+        let mut buffer = Vec::new();
+
+        PeerMessage::Piece {
+            piece_index: 1,
+            offset: 10,
+            block: vec![1, 2, 3, 4],
+        }
+        .send(&mut buffer)
+        .unwrap();
+        assert_eq!(
+            buffer,
+            vec![
+                0, 0, 0, 13, // Message length
+                7,  // Message id
+                0, 0, 0, 1, // Piece index
+                0, 0, 0, 10, // Offset
+                1, 2, 3, 4, // Block
+            ]
+        );
+    }
+
+    #[test]
+    fn receive_choke_message() {
+        let buffer = vec![
+            0, 0, 0, 1, // Message length
+            0, // Message id
+        ];
+
+        let message = PeerMessage::receive(&mut buffer.as_slice()).unwrap();
+        assert_eq!(PeerMessage::Choke, message);
+    }
+
+    #[test]
+    fn receive_have_message() {
+        let buffer = vec![
+            0, 0, 0, 5, // Message length
+            4, // Message id
+            0, 0, 1, 2, // Piece index
+        ];
+
+        let message = PeerMessage::receive(&mut buffer.as_slice()).unwrap();
+        assert_eq!(PeerMessage::Have(258), message);
+    }
+
+    #[test]
+    fn send_have_message() {
+        let mut buffer = Vec::new();
+
+        PeerMessage::Have(258).send(&mut buffer).unwrap();
+        assert_eq!(
+            buffer,
+            vec![
+                0, 0, 0, 5, // Message length
+                4, // Message id
+                0, 0, 1, 2, // Piece index
+            ]
+        );
+    }
+
+    #[test]
+    fn send_keep_alive_message() {
+        let mut buffer = Vec::new();
+
+        PeerMessage::KeepAlive.send(&mut buffer).unwrap();
+        assert_eq!(buffer, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn keep_alive_message_from_empty_payload() {
+        assert_eq!(PeerMessage::KeepAlive, PeerMessage::from_bytes(&[]));
+    }
+
+    #[test]
+    fn send_and_receive_cancel_message() {
+        let message = PeerMessage::Cancel {
+            piece_index: 1,
+            offset: 10,
+            length: 128,
+        };
+        let mut buffer = Vec::new();
+        message.send(&mut buffer).unwrap();
+
+        assert_eq!(
+            buffer,
+            vec![
+                0, 0, 0, 13, // Message length
+                8,  // Message id
+                0, 0, 0, 1, // Piece index
+                0, 0, 0, 10, // Offset
+                0, 0, 0, 128, // Length
+            ]
+        );
+        assert_eq!(
+            message,
+            PeerMessage::receive(&mut buffer.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn send_and_receive_extended_message() {
+        let message = PeerMessage::Extended {
+            id: 0,
+            payload: b"de".to_vec(),
+        };
+        let mut buffer = Vec::new();
+        message.send(&mut buffer).unwrap();
+
+        assert_eq!(
+            buffer,
+            vec![
+                0, 0, 0, 4,  // Message length
+                20, // Message id
+                0,  // Extended message id
+                b'd', b'e', // Payload
+            ]
+        );
+        assert_eq!(
+            message,
+            PeerMessage::receive(&mut buffer.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_message_is_unknown() {
+        let message = PeerMessage::from_bytes(&[4, 0, 1]);
+        assert_eq!(
+            PeerMessage::Unknown {
+                id: 4,
+                payload: vec![0, 1]
+            },
+            message
+        );
+    }
+
     #[test]
     fn skip_keep_alive_messages() {
         let buffer = vec![
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info, instrument};
+
+use super::{
+    PeerChannel, PeerJoiner,
+    async_peer_connector::{ProbeError, ProbeResult, TaskWaker},
+    peer_comm::{ExtensionHandshake, HandshakeMessage},
+};
+use crate::{
+    async_tcp::{self, AsyncTcpListener, AsyncTcpStream},
+    types::{PeerId, Sha1},
+};
+
+type ActiveTorrents = Arc<Mutex<HashMap<Sha1, PeerJoiner>>>;
+
+pub struct PeerListener {
+    local_addr: SocketAddr,
+    torrents: ActiveTorrents,
+    shutdown: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl PeerListener {
+    pub fn bind(addr: impl ToSocketAddrs, peer_id: PeerId) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let torrents = ActiveTorrents::default();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let active_torrents = torrents.clone();
+        let shutdown_flag = shutdown.clone();
+        let accept_thread = thread::spawn(move || {
+            let accept_loop = AcceptLoop::new(active_torrents, peer_id);
+            if let Err(err) = accept_loop.run(listener, &shutdown_flag) {
+                error!(%err, "Peer listener stopped");
+            }
+        });
+        info!(%local_addr, "Listening for incoming peers");
+
+        Ok(Self {
+            local_addr,
+            torrents,
+            shutdown,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn add_torrent(&self, info_hash: Sha1, joiner: PeerJoiner) {
+        self.torrents.lock().unwrap().insert(info_hash, joiner);
+    }
+
+    pub fn remove_torrent(&self, info_hash: &Sha1) {
+        self.torrents.lock().unwrap().remove(info_hash);
+    }
+}
+
+impl Drop for PeerListener {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+    }
+}
+
+enum TaskOutput {
+    Accepted(io::Result<(AsyncTcpStream, SocketAddr)>),
+    Handshaken(Box<ProbeResult<(PeerJoiner, PeerChannel)>>),
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = TaskOutput>>>,
+    started: Instant,
+}
+
+struct AcceptLoop {
+    torrents: ActiveTorrents,
+    peer_id: PeerId,
+    ready_queue: Arc<Mutex<Vec<usize>>>,
+    tasks: HashMap<usize, Task>,
+    next_task_id: usize,
+}
+
+impl AcceptLoop {
+    const ACCEPT_TASK_ID: usize = 0;
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn new(torrents: ActiveTorrents, peer_id: PeerId) -> Self {
+        Self {
+            torrents,
+            peer_id,
+            ready_queue: Arc::new(Mutex::new(vec![])),
+            tasks: HashMap::new(),
+            next_task_id: Self::ACCEPT_TASK_ID + 1,
+        }
+    }
+
+    fn run(mut self, listener: TcpListener, shutdown: &AtomicBool) -> io::Result<()> {
+        let listener = Rc::new(AsyncTcpListener::from_std(listener)?);
+        self.spawn_accept(&listener);
+
+        while !shutdown.load(Ordering::Relaxed) {
+            for (id, output) in self.poll_ready_tasks() {
+                match output {
+                    TaskOutput::Accepted(result) => {
+                        self.spawn_accept(&listener);
+                        match result {
+                            Ok((stream, addr)) => self.spawn_handshake(stream, addr),
+                            Err(err) => error!(%err, "Failed to accept incoming connection"),
+                        }
+                    }
+                    TaskOutput::Handshaken(result) => {
+                        if let Ok((joiner, channel)) = *result {
+                            joiner.join_incoming(channel);
+                        }
+                    }
+                }
+                self.tasks.remove(&id);
+            }
+            self.drop_stale_handshakes();
+            async_tcp::poll_reactor(Some(Self::POLL_INTERVAL))?;
+        }
+        Ok(())
+    }
+
+    fn spawn_accept(&mut self, listener: &Rc<AsyncTcpListener>) {
+        let listener = listener.clone();
+        let future = async move { TaskOutput::Accepted(listener.accept().await) };
+        self.spawn(Self::ACCEPT_TASK_ID, Box::pin(future));
+    }
+
+    fn spawn_handshake(&mut self, stream: AsyncTcpStream, addr: SocketAddr) {
+        let future = accept_peer(stream, addr, self.torrents.clone(), self.peer_id);
+        let id = self.next_task_id;
+        self.next_task_id += 1;
+        self.spawn(
+            id,
+            Box::pin(async { TaskOutput::Handshaken(Box::new(future.await)) }),
+        );
+    }
+
+    fn spawn(&mut self, id: usize, future: Pin<Box<dyn Future<Output = TaskOutput>>>) {
+        self.tasks.insert(
+            id,
+            Task {
+                future,
+                started: Instant::now(),
+            },
+        );
+        self.ready_queue.lock().unwrap().push(id);
+    }
+
+    fn poll_ready_tasks(&mut self) -> Vec<(usize, TaskOutput)> {
+        let mut completed = vec![];
+        let ready_ids = std::mem::take(&mut *self.ready_queue.lock().unwrap());
+        for id in ready_ids {
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+            let waker = Waker::from(Arc::new(TaskWaker::new(id, self.ready_queue.clone())));
+            let mut context = Context::from_waker(&waker);
+            if let Poll::Ready(output) = task.future.as_mut().poll(&mut context) {
+                completed.push((id, output));
+            }
+        }
+        completed
+    }
+
+    fn drop_stale_handshakes(&mut self) {
+        let stale_ids = self
+            .tasks
+            .iter()
+            .filter(|(id, task)| {
+                **id != Self::ACCEPT_TASK_ID && task.started.elapsed() >= Self::HANDSHAKE_TIMEOUT
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in stale_ids {
+            // Dropping the pending future deregisters its stream from the reactor
+            // before the stream itself is closed.
+            drop(self.tasks.remove(&id));
+            self.ready_queue
+                .lock()
+                .unwrap()
+                .retain(|ready_id| *ready_id != id);
+        }
+    }
+}
+
+#[instrument(skip(stream, torrents, peer_id), err)]
+async fn accept_peer(
+    mut stream: AsyncTcpStream,
+    addr: SocketAddr,
+    torrents: ActiveTorrents,
+    peer_id: PeerId,
+) -> ProbeResult<(PeerJoiner, PeerChannel)> {
+    let their_handshake = HandshakeMessage::receive_async(&mut stream).await?;
+    let info_hash = their_handshake.info_hash;
+    let joiner = torrents
+        .lock()
+        .unwrap()
+        .get(&info_hash)
+        .cloned()
+        .ok_or(ProbeError::InfoHashMismatch)?;
+
+    let mut reply = vec![];
+    HandshakeMessage::new(info_hash, peer_id).send(&mut reply)?;
+    if their_handshake.supports_extensions() {
+        ExtensionHandshake::ours().to_message().send(&mut reply)?;
+    }
+    stream.write_all(&reply).await?;
+    let std_stream: TcpStream = stream.try_into()?;
+    let channel = PeerChannel::from_stream(std_stream, their_handshake.peer_id)?;
+    Ok((joiner, channel))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{
+        async_tcp::test_helpers::poll_future,
+        downloader::{DownloadChannel, DownloadEvent, PeerSet},
+    };
+
+    use super::*;
+
+    #[test]
+    fn accept_peer_of_active_torrent() {
+        let info_hash = Sha1::random();
+        let our_id = PeerId::random();
+        let their_id = PeerId::random();
+        let mut peer_set = PeerSet::new();
+        let listener = PeerListener::bind("127.0.0.1:0", our_id).unwrap();
+        listener.add_torrent(info_hash, peer_set.joiner());
+
+        let mut stream = TcpStream::connect(listener.local_addr()).unwrap();
+        HandshakeMessage::new(info_hash, their_id)
+            .send(&mut stream)
+            .unwrap();
+        let reply = HandshakeMessage::receive(&mut stream).unwrap();
+        assert_eq!(reply, HandshakeMessage::new(info_hash, our_id));
+
+        let event = peer_set.receive(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            event,
+            Some(DownloadEvent::PeerConnected { incoming: true, .. })
+        ));
+    }
+
+    #[test]
+    fn deregister_stale_handshakes_from_reactor() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let listener = AsyncTcpListener::from_std(listener).unwrap();
+        let (stream, addr) = poll_future(listener.accept()).unwrap();
+
+        let mut accept_loop = AcceptLoop::new(ActiveTorrents::default(), PeerId::random());
+        let wakers_before = async_tcp::registered_wakers();
+        accept_loop.spawn_handshake(stream, addr);
+        assert!(accept_loop.poll_ready_tasks().is_empty());
+        assert_eq!(async_tcp::registered_wakers(), wakers_before + 1);
+
+        for task in accept_loop.tasks.values_mut() {
+            task.started -= AcceptLoop::HANDSHAKE_TIMEOUT;
+        }
+        accept_loop.drop_stale_handshakes();
+        assert!(accept_loop.tasks.is_empty());
+        assert_eq!(async_tcp::registered_wakers(), wakers_before);
+    }
+
+    #[test]
+    fn reject_peer_of_unknown_torrent() {
+        let listener = PeerListener::bind("127.0.0.1:0", PeerId::random()).unwrap();
+        let peer_set = PeerSet::new();
+        listener.add_torrent(Sha1::random(), peer_set.joiner());
+
+        let mut stream = TcpStream::connect(listener.local_addr()).unwrap();
+        HandshakeMessage::new(Sha1::random(), PeerId::random())
+            .send(&mut stream)
+            .unwrap();
+
+        let mut buffer = [0; 1];
+        assert!(matches!(stream.read(&mut buffer), Ok(0) | Err(_)));
+    }
+}
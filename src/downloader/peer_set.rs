@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info, warn};
+
+use super::{
+    DownloadChannel, DownloadEvent, PeerChannel, PeerKey, RateLimits, peer_comm::PeerMessage,
+};
+
+enum PeerSetEvent {
+    Joined {
+        channel: PeerChannel,
+        incoming: bool,
+    },
+    Message(PeerKey, PeerMessage),
+    Closed(PeerKey, io::Error),
+}
+
+#[derive(Clone)]
+pub struct PeerJoiner {
+    sender: Sender<PeerSetEvent>,
+    _token: Option<Arc<()>>,
+}
+
+impl PeerJoiner {
+    pub fn join_outgoing(&self, channel: PeerChannel) -> bool {
+        self.join(channel, false)
+    }
+
+    pub fn join_incoming(&self, channel: PeerChannel) -> bool {
+        self.join(channel, true)
+    }
+
+    fn join(&self, channel: PeerChannel, incoming: bool) -> bool {
+        self.sender
+            .send(PeerSetEvent::Joined { channel, incoming })
+            .is_ok()
+    }
+}
+
+struct Peer {
+    addr: SocketAddr,
+    stream: TcpStream,
+    last_sent: Instant,
+}
+
+pub struct PeerSet {
+    peers: HashMap<PeerKey, Peer>,
+    next_key: PeerKey,
+    event_sender: Sender<PeerSetEvent>,
+    event_receiver: Receiver<PeerSetEvent>,
+    joiners: Arc<()>,
+    last_keep_alive_check: Instant,
+    rate_limits: RateLimits,
+    piece_count: Option<usize>,
+}
+
+#[allow(clippy::new_without_default)]
+impl PeerSet {
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+    const KEEP_ALIVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        let (event_sender, event_receiver) = mpsc::channel();
+        Self {
+            peers: HashMap::new(),
+            next_key: 0,
+            event_sender,
+            event_receiver,
+            joiners: Arc::new(()),
+            last_keep_alive_check: Instant::now(),
+            rate_limits: RateLimits::default(),
+            piece_count: None,
+        }
+    }
+
+    pub fn with_piece_count(mut self, piece_count: usize) -> Self {
+        self.piece_count = Some(piece_count);
+        self
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn joiner(&self) -> PeerJoiner {
+        PeerJoiner {
+            sender: self.event_sender.clone(),
+            _token: Some(self.joiners.clone()),
+        }
+    }
+
+    // Peers may still join through a passive joiner, but the peer set
+    // doesn't wait for them once it has no peers and no other joiners left.
+    pub fn passive_joiner(&self) -> PeerJoiner {
+        PeerJoiner {
+            sender: self.event_sender.clone(),
+            _token: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.peers.is_empty() && Arc::strong_count(&self.joiners) == 1
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<PeerSetEvent>> {
+        match self.event_receiver.try_recv() {
+            Ok(event) => return Ok(Some(event)),
+            Err(TryRecvError::Empty) if self.is_exhausted() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "No peers left to download from",
+                ));
+            }
+            Err(_) => (),
+        }
+
+        match self.event_receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the peer set holds a sender"),
+        }
+    }
+
+    fn add_peer(&mut self, mut channel: PeerChannel, incoming: bool) -> io::Result<DownloadEvent> {
+        if let Some(piece_count) = self.piece_count {
+            channel = channel.with_piece_count(piece_count);
+        }
+        let peer = self.next_key;
+        self.next_key += 1;
+
+        let addr = channel.peer_addr();
+        let stream = channel.try_clone_stream()?;
+        let state = channel.state().clone();
+        info!(peer_address = %addr, remote_id = %channel.remote_id(), incoming, "Peer joined");
+
+        self.peers.insert(
+            peer,
+            Peer {
+                addr,
+                stream,
+                last_sent: Instant::now(),
+            },
+        );
+        let event_sender = self.event_sender.clone();
+        let channel = channel
+            .without_keep_alives()
+            .with_rate_limits(self.rate_limits.clone());
+        thread::spawn(move || read_messages(peer, channel, event_sender));
+
+        Ok(DownloadEvent::PeerConnected {
+            peer,
+            state,
+            incoming,
+        })
+    }
+
+    fn close_connection(&mut self, peer: PeerKey, err: io::Error) {
+        if let Some(peer) = self.peers.get(&peer) {
+            debug!(peer_address = %peer.addr, %err, "Disconnecting peer");
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn send_keep_alives(&mut self) {
+        if self.last_keep_alive_check.elapsed() < Self::KEEP_ALIVE_CHECK_INTERVAL {
+            return;
+        }
+        self.last_keep_alive_check = Instant::now();
+
+        let idle_peers = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.last_sent.elapsed() >= Self::KEEP_ALIVE_INTERVAL)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for peer in idle_peers {
+            let _ = self.send(peer, &PeerMessage::KeepAlive);
+        }
+    }
+}
+
+impl DownloadChannel for PeerSet {
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<DownloadEvent>> {
+        loop {
+            self.send_keep_alives();
+            let Some(event) = self.next_event(timeout)? else {
+                return Ok(None);
+            };
+
+            match event {
+                PeerSetEvent::Joined { channel, incoming } => {
+                    match self.add_peer(channel, incoming) {
+                        Ok(event) => return Ok(Some(event)),
+                        Err(err) => warn!(%err, "Failed to add peer"),
+                    }
+                }
+                PeerSetEvent::Message(peer, msg) if self.peers.contains_key(&peer) => {
+                    return Ok(Some(DownloadEvent::Message(peer, msg)));
+                }
+                PeerSetEvent::Closed(peer, err) => {
+                    if let Some(removed) = self.peers.remove(&peer) {
+                        info!(peer_address = %removed.addr, %err, "Peer disconnected");
+                        let _ = removed.stream.shutdown(Shutdown::Both);
+                        return Ok(Some(DownloadEvent::PeerDisconnected(peer)));
+                    }
+                }
+                PeerSetEvent::Message(..) => (),
+            }
+        }
+    }
+
+    fn send(&mut self, peer: PeerKey, msg: &PeerMessage) -> io::Result<()> {
+        let Some(connected_peer) = self.peers.get_mut(&peer) else {
+            return Ok(());
+        };
+        match msg.send(&mut connected_peer.stream) {
+            Ok(()) => connected_peer.last_sent = Instant::now(),
+            Err(err) => self.close_connection(peer, err),
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, peer: PeerKey) {
+        self.close_connection(
+            peer,
+            io::Error::new(io::ErrorKind::TimedOut, "Peer stopped sending blocks"),
+        );
+    }
+}
+
+impl Drop for PeerSet {
+    fn drop(&mut self) {
+        for peer in self.peers.values() {
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn read_messages(peer: PeerKey, mut channel: PeerChannel, event_sender: Sender<PeerSetEvent>) {
+    loop {
+        let event = match channel.receive() {
+            Ok(msg) => PeerSetEvent::Message(peer, msg),
+            Err(err) => {
+                let _ = event_sender.send(PeerSetEvent::Closed(peer, err));
+                return;
+            }
+        };
+        if event_sender.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::{downloader::peer_comm::ConnectionState, types::PeerId};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn report_joined_peers_and_their_messages() {
+        let mut peer_set = PeerSet::new();
+        let (mut remote, channel) = connected_pair();
+        peer_set.joiner().join_incoming(channel);
+
+        let event = peer_set.receive(TIMEOUT).unwrap();
+        assert!(matches!(
+            event,
+            Some(DownloadEvent::PeerConnected {
+                peer: 0,
+                incoming: true,
+                ..
+            })
+        ));
+
+        PeerMessage::Have(7).send(&mut remote).unwrap();
+        let event = peer_set.receive(TIMEOUT).unwrap();
+        assert!(matches!(
+            event,
+            Some(DownloadEvent::Message(0, PeerMessage::Have(7)))
+        ));
+    }
+
+    #[test]
+    fn send_messages_to_peer() {
+        let mut peer_set = PeerSet::new();
+        let (mut remote, channel) = connected_pair();
+        peer_set.joiner().join_outgoing(channel);
+        peer_set.receive(TIMEOUT).unwrap();
+
+        peer_set.send(0, &PeerMessage::Interested).unwrap();
+        assert_eq!(
+            PeerMessage::receive(&mut remote).unwrap(),
+            PeerMessage::Interested
+        );
+    }
+
+    #[test]
+    fn report_disconnected_peers() {
+        let mut peer_set = PeerSet::new();
+        let (remote, channel) = connected_pair();
+        peer_set.joiner().join_outgoing(channel);
+        peer_set.receive(TIMEOUT).unwrap();
+
+        drop(remote);
+        let event = peer_set.receive(TIMEOUT).unwrap();
+        assert!(matches!(event, Some(DownloadEvent::PeerDisconnected(0))));
+        assert!(peer_set.is_empty());
+    }
+
+    #[test]
+    fn disconnect_peer_when_send_times_out() {
+        let mut peer_set = PeerSet::new();
+        let (_remote, channel) = connected_pair();
+        channel
+            .try_clone_stream()
+            .unwrap()
+            .set_write_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        peer_set.joiner().join_outgoing(channel);
+        peer_set.receive(TIMEOUT).unwrap();
+
+        let msg = PeerMessage::Piece {
+            piece_index: 0,
+            offset: 0,
+            block: vec![0; 1024 * 1024],
+        };
+        for _ in 0..64 {
+            peer_set.send(0, &msg).unwrap();
+        }
+        let event = peer_set.receive(TIMEOUT).unwrap();
+        assert!(matches!(event, Some(DownloadEvent::PeerDisconnected(0))));
+    }
+
+    #[test]
+    fn error_when_no_peers_can_join_anymore() {
+        let mut peer_set = PeerSet::new();
+        let joiner = peer_set.joiner();
+        assert!(
+            peer_set
+                .receive(Duration::from_millis(10))
+                .unwrap()
+                .is_none()
+        );
+
+        drop(joiner);
+        let err = peer_set.receive(TIMEOUT).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn passive_joiner_does_not_keep_peer_set_waiting() {
+        let mut peer_set = PeerSet::new();
+        let joiner = peer_set.joiner();
+        let passive_joiner = peer_set.passive_joiner();
+        let (_remote, channel) = connected_pair();
+        assert!(passive_joiner.join_incoming(channel));
+        assert!(matches!(
+            peer_set.receive(TIMEOUT).unwrap(),
+            Some(DownloadEvent::PeerConnected { peer: 0, .. })
+        ));
+
+        drop(joiner);
+        peer_set.disconnect(0);
+        assert!(matches!(
+            peer_set.receive(TIMEOUT).unwrap(),
+            Some(DownloadEvent::PeerDisconnected(0))
+        ));
+        let err = peer_set.receive(TIMEOUT).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    fn connected_pair() -> (TcpStream, PeerChannel) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (remote, _) = listener.accept().unwrap();
+        let channel = PeerChannel::from_stream(local, PeerId::default())
+            .unwrap()
+            .with_state(ConnectionState::default());
+        (remote, channel)
+    }
+}
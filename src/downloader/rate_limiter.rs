@@ -0,0 +1,214 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate,
+            tokens: rate.unwrap_or(0) as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(rate) = self.rate {
+            let elapsed = now.saturating_duration_since(self.last_refill);
+            self.tokens = (self.tokens + elapsed.as_secs_f64() * rate as f64).min(rate as f64);
+        }
+        self.last_refill = now;
+    }
+
+    fn set_rate(&mut self, rate: Option<u64>, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = match rate {
+            Some(rate) => self.tokens.min(rate as f64),
+            None => 0.0,
+        };
+    }
+
+    fn consume(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= bytes as f64;
+        self.delay()
+    }
+
+    fn delay(&self) -> Duration {
+        match self.rate {
+            Some(rate) if self.tokens < 0.0 => {
+                Duration::from_secs_f64(-self.tokens / rate.max(1) as f64)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+    parent: Option<Box<RateLimiter>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: Option<u64>) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(bytes_per_second))),
+            parent: None,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None)
+    }
+
+    pub fn child(&self, bytes_per_second: Option<u64>) -> Self {
+        Self {
+            parent: Some(Box::new(self.clone())),
+            ..Self::new(bytes_per_second)
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    pub fn set_rate(&self, bytes_per_second: Option<u64>) {
+        self.bucket
+            .lock()
+            .unwrap()
+            .set_rate(bytes_per_second, Instant::now());
+    }
+
+    pub fn consume(&self, bytes: usize) -> Duration {
+        let delay = self.bucket.lock().unwrap().consume(bytes, Instant::now());
+        match &self.parent {
+            Some(parent) => delay.max(parent.consume(bytes)),
+            None => delay,
+        }
+    }
+
+    pub fn acquire(&self, bytes: usize) {
+        let delay = self.consume(bytes);
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        let delay = {
+            let mut bucket = self.bucket.lock().unwrap();
+            bucket.refill(Instant::now());
+            bucket.delay()
+        };
+        match &self.parent {
+            Some(parent) => delay.max(parent.delay()),
+            None => delay,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        !self.delay().is_zero()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    pub download: RateLimiter,
+    pub upload: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(download: Option<u64>, upload: Option<u64>) -> Self {
+        Self {
+            download: RateLimiter::new(download),
+            upload: RateLimiter::new(upload),
+        }
+    }
+
+    pub fn child(&self, download: Option<u64>, upload: Option<u64>) -> Self {
+        Self {
+            download: self.download.child(download),
+            upload: self.upload.child(upload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_rate_never_delays() {
+        let limiter = RateLimiter::unlimited();
+        assert_eq!(limiter.consume(1 << 30), Duration::ZERO);
+        assert!(!limiter.is_exhausted());
+    }
+
+    #[test]
+    fn delay_once_burst_is_used_up() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(Some(1000));
+        bucket.last_refill = now;
+
+        assert_eq!(bucket.consume(1000, now), Duration::ZERO);
+        assert_delay(bucket.consume(500, now), 500);
+        assert_delay(bucket.consume(0, now + Duration::from_millis(200)), 300);
+    }
+
+    #[test]
+    fn refill_no_more_than_one_second_of_tokens() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(Some(1000));
+        bucket.last_refill = now;
+
+        bucket.refill(now + Duration::from_secs(10));
+        assert_delay(bucket.consume(2000, now + Duration::from_secs(10)), 1000);
+    }
+
+    #[test]
+    fn change_rate_at_runtime() {
+        let limiter = RateLimiter::new(Some(100));
+        limiter.consume(300);
+        assert!(limiter.is_exhausted());
+
+        limiter.set_rate(None);
+        assert_eq!(limiter.rate(), None);
+        assert!(!limiter.is_exhausted());
+    }
+
+    #[test]
+    fn child_is_limited_by_its_parent() {
+        let global = RateLimiter::new(Some(100));
+        let torrent = global.child(None);
+
+        assert!(!torrent.consume(300).is_zero());
+        assert!(global.is_exhausted());
+        assert!(torrent.is_exhausted());
+    }
+
+    fn assert_delay(actual: Duration, expected_millis: u64) {
+        let expected = Duration::from_millis(expected_millis);
+        assert!(
+            actual.abs_diff(expected) < Duration::from_millis(1),
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::types::Sha1;
+
+struct HashJob {
+    piece_index: u32,
+    data: Vec<u8>,
+    expected: Sha1,
+}
+
+#[derive(Debug)]
+pub struct HashResult {
+    pub piece_index: u32,
+    pub data: Vec<u8>,
+    pub is_valid: bool,
+}
+
+pub struct HashPool {
+    job_sender: Option<Sender<HashJob>>,
+    result_receiver: Receiver<HashResult>,
+    workers: Vec<JoinHandle<()>>,
+    pending: usize,
+}
+
+impl Default for HashPool {
+    fn default() -> Self {
+        let worker_count = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self::new(worker_count)
+    }
+}
+
+impl HashPool {
+    pub fn new(worker_count: usize) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<HashJob>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..worker_count.max(1))
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                thread::spawn(move || hash_pieces(&job_receiver, &result_sender))
+            })
+            .collect();
+
+        Self {
+            job_sender: Some(job_sender),
+            result_receiver,
+            workers,
+            pending: 0,
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn submit(&mut self, piece_index: u32, data: Vec<u8>, expected: Sha1) {
+        let job = HashJob {
+            piece_index,
+            data,
+            expected,
+        };
+        self.job_sender
+            .as_ref()
+            .expect("job sender lives as long as the pool")
+            .send(job)
+            .expect("hash workers live as long as the pool");
+        self.pending += 1;
+    }
+
+    pub fn try_result(&mut self) -> Option<HashResult> {
+        let result = self.result_receiver.try_recv().ok()?;
+        self.pending -= 1;
+        Some(result)
+    }
+
+    pub fn wait_result(&mut self) -> Option<HashResult> {
+        if self.pending == 0 {
+            return None;
+        }
+        let result = self
+            .result_receiver
+            .recv()
+            .expect("hash workers live as long as the pool");
+        self.pending -= 1;
+        Some(result)
+    }
+
+    pub fn verify_all<I>(&mut self, pieces: I, mut on_result: impl FnMut(HashResult))
+    where
+        I: IntoIterator<Item = (u32, Vec<u8>, Sha1)>,
+    {
+        let max_pending = self.worker_count() * 2;
+        for (piece_index, data, expected) in pieces {
+            if self.pending >= max_pending
+                && let Some(result) = self.wait_result()
+            {
+                on_result(result);
+            }
+            self.submit(piece_index, data, expected);
+        }
+        while let Some(result) = self.wait_result() {
+            on_result(result);
+        }
+    }
+}
+
+impl Drop for HashPool {
+    fn drop(&mut self) {
+        self.job_sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn hash_pieces(job_receiver: &Mutex<Receiver<HashJob>>, result_sender: &Sender<HashResult>) {
+    loop {
+        let Ok(job) = job_receiver.lock().unwrap().recv() else {
+            return;
+        };
+        let result = HashResult {
+            piece_index: job.piece_index,
+            is_valid: job.expected.verify(&job.data),
+            data: job.data,
+        };
+        if result_sender.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_submitted_pieces_on_workers() {
+        let mut pool = HashPool::new(2);
+        pool.submit(0, vec![1, 2, 3], Sha1::calculate(&[1, 2, 3]));
+        pool.submit(1, vec![4, 5, 6], Sha1::calculate(&[0, 0, 0]));
+        assert_eq!(pool.pending(), 2);
+
+        let mut results = [pool.wait_result().unwrap(), pool.wait_result().unwrap()];
+        results.sort_by_key(|result| result.piece_index);
+        assert!(results[0].is_valid);
+        assert_eq!(results[0].data, vec![1, 2, 3]);
+        assert!(!results[1].is_valid);
+        assert_eq!(pool.pending(), 0);
+        assert!(pool.wait_result().is_none());
+    }
+
+    #[test]
+    fn verify_all_pieces_with_bounded_queue() {
+        let mut pool = HashPool::new(2);
+        let pieces = (0..20_u8).map(|index| {
+            let data = vec![index; 100];
+            let expected = if index % 3 == 0 {
+                Sha1::default()
+            } else {
+                Sha1::calculate(&data)
+            };
+            (index as u32, data, expected)
+        });
+
+        let mut invalid = vec![];
+        pool.verify_all(pieces, |result| {
+            if !result.is_valid {
+                invalid.push(result.piece_index);
+            }
+        });
+        invalid.sort_unstable();
+        assert_eq!(invalid, vec![0, 3, 6, 9, 12, 15, 18]);
+        assert_eq!(pool.pending(), 0);
+    }
+}
@@ -1,117 +1,478 @@
 mod async_tcp;
 pub mod downloader;
+pub mod hash_pool;
+pub mod options;
 pub mod ratatui_ui;
 pub mod result;
+pub mod resume;
+pub mod storage;
+pub mod streaming;
 pub mod torrent;
 mod tracker;
 pub mod types;
 mod util;
+pub mod verify;
 use tracing::{error, info};
 
 use crate::{
-    downloader::{PeerChannel, async_peer_connector::PeerConnector},
+    downloader::{
+        FilePriority, PeerJoiner, PeerSet, TransferStats, async_peer_connector::PeerConnector,
+        peer_listener::PeerListener,
+    },
+    hash_pool::HashPool,
     ratatui_ui::AppEvent,
-    tracker::AnnounceRequest,
-    types::PeerId,
+    resume::ResumeFile,
+    storage::{DiskIo, Storage, StorageLayout, WriterSink},
+    streaming::{StreamServer, StreamingHandle},
+    tracker::{AnnounceEvent, AnnounceRequest},
+    types::{Bitfield, PeerId},
 };
 
+pub use options::DownloadOptions;
 use result::Result;
-use std::{net::SocketAddr, sync::mpsc::Sender, time::Duration};
+use std::{
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
 pub use torrent::Torrent;
 
+#[derive(Default)]
+struct TransferContext<'a> {
+    resume_file: Option<&'a ResumeFile>,
+    listener: Option<&'a PeerListener>,
+    streaming: Option<&'a StreamingHandle>,
+}
+
 #[derive(Debug)]
 pub struct DownloadedFile {
-    pub content: Vec<u8>,
     pub download_duration: Duration,
+    pub stats: TransferStats,
 }
 
 impl Torrent {
-    pub fn fetch_peer_addresses(&self, peer_id: PeerId) -> Result<Vec<SocketAddr>> {
+    const LISTEN_PORT: u16 = 6881;
+
+    pub fn fetch_peer_addresses(
+        &self,
+        peer_id: PeerId,
+        port: Option<u16>,
+    ) -> Result<Vec<SocketAddr>> {
         let announce_request = AnnounceRequest {
             tracker_url: self.announce.clone(),
             info_hash: self.info.sha1,
             peer_id,
+            port,
+            uploaded: 0,
+            downloaded: 0,
+            left: self.info.length as u64,
+            event: Some(AnnounceEvent::Started),
         };
         announce_request.fetch_peer_addresses()
     }
 
-    fn request_file(
+    fn announce_transfer(
+        &self,
+        peer_id: PeerId,
+        port: u16,
+        stats: &TransferStats,
+        left: u64,
+        event: Option<AnnounceEvent>,
+    ) -> Result<Duration> {
+        let announce_request = AnnounceRequest {
+            tracker_url: self.announce.clone(),
+            info_hash: self.info.sha1,
+            peer_id,
+            port: Some(port),
+            uploaded: stats.uploaded_bytes,
+            downloaded: stats.downloaded_bytes,
+            left,
+            event,
+        };
+        announce_request.announce()
+    }
+
+    fn announce_stopped(&self, peer_id: PeerId, port: u16, stats: &TransferStats) -> Result<()> {
+        let left = (self.info.length as u64).saturating_sub(stats.downloaded_bytes);
+        self.announce_transfer(peer_id, port, stats, left, Some(AnnounceEvent::Stopped))?;
+        Ok(())
+    }
+
+    fn announce_completed(&self, peer_id: PeerId, port: u16, stats: &TransferStats) {
+        let event = Some(AnnounceEvent::Completed);
+        if let Err(e) = self.announce_transfer(peer_id, port, stats, 0, event) {
+            error!(%e, "Failed to announce completed download");
+        }
+    }
+
+    fn reannounce_while_seeding(
+        &self,
+        options: &DownloadOptions,
+        peer_id: PeerId,
+        port: u16,
+        mut interval: Duration,
+        stopped: Receiver<()>,
+    ) {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let stats = TransferStats {
+                uploaded_bytes: options.handle.stats().uploaded_bytes,
+                ..TransferStats::default()
+            };
+            match self.announce_transfer(peer_id, port, &stats, 0, None) {
+                Ok(next_interval) => interval = next_interval,
+                Err(e) => error!(%e, "Failed to re-announce seeding"),
+            }
+        }
+    }
+
+    fn bind_listener(peer_id: PeerId) -> Result<PeerListener> {
+        let listener = PeerListener::bind(("0.0.0.0", Self::LISTEN_PORT), peer_id)
+            .or_else(|_| PeerListener::bind(("0.0.0.0", 0), peer_id))?;
+        Ok(listener)
+    }
+
+    fn connect_to_peers(
         &self,
         peer_addrs: Vec<SocketAddr>,
         peer_id: PeerId,
+        joiner: PeerJoiner,
         event_sender: &Sender<AppEvent>,
-    ) -> Option<PeerChannel> {
-        let total_peers = peer_addrs.len();
-        let connector = PeerConnector::new(self.info.sha1, peer_id, self.info.pieces.len())
-            .with_progress_callback(|addr, total_probed| {
-                let _ = event_sender
-                    .send(AppEvent::Probing {
-                        address: addr,
-                        current_index: total_probed,
-                        total_count: total_peers,
-                    })
-                    .inspect_err(|e| error!(%e, "Failed to send AppEvent to the UI thread"));
-            });
-        connector.connect(peer_addrs).next()
+    ) {
+        let info_hash = self.info.sha1;
+        let piece_count = self.info.pieces.len();
+        let event_sender = event_sender.clone();
+        thread::spawn(move || {
+            let total_peers = peer_addrs.len();
+            let connector = PeerConnector::new(info_hash, peer_id, piece_count)
+                .with_progress_callback(|addr, total_probed| {
+                    let _ = event_sender
+                        .send(AppEvent::Probing {
+                            address: addr,
+                            current_index: total_probed,
+                            total_count: total_peers,
+                        })
+                        .inspect_err(|e| error!(%e, "Failed to send AppEvent to the UI thread"));
+                });
+            for channel in connector.connect(peer_addrs) {
+                if !joiner.join_outgoing(channel) {
+                    break;
+                }
+            }
+        });
     }
 
-    pub fn download(self, event_sender: &Sender<AppEvent>) -> Result<()> {
+    pub fn download(
+        &self,
+        options: &DownloadOptions,
+        target_dir: &Path,
+        event_sender: &Sender<AppEvent>,
+    ) -> Result<()> {
+        let mut resume_file = ResumeFile::new(target_dir, &self.info);
+        if let Some(incomplete_dir) = &options.incomplete_dir {
+            resume_file = resume_file.with_incomplete_dir(incomplete_dir);
+        }
+        if options.file_priorities.contains(&FilePriority::Skip) {
+            resume_file = resume_file
+                .with_skipped_files(FilePriority::skipped_files(&options.file_priorities));
+        }
+        if !resume_file.part_files().has_existing_files() {
+            resume_file = resume_file.with_new_files();
+        }
+        let part_files = resume_file.part_files();
+        let data_dir = part_files.incomplete_dir();
+        let layout = part_files.storage_layout();
+        storage::check_free_space(data_dir, &layout)?;
+        storage::preallocate(data_dir, &layout, options.preallocation)?;
+
         let peer_id = PeerId::default();
-        let peer_addrs = self.fetch_peer_addresses(peer_id)?;
+        let listener = Self::bind_listener(peer_id)?;
+        let peer_addrs = self.fetch_peer_addresses(peer_id, Some(listener.local_addr().port()))?;
         info!(peer_count = peer_addrs.len(), "Received peer addresses");
 
-        let downloaded = self.download_from(peer_addrs, peer_id, event_sender)?;
+        let mut storage = DiskIo::new(
+            options.storage_backend.open(data_dir, layout.clone())?,
+            layout,
+        );
+        let streaming = options
+            .stream_address
+            .map(|_| StreamingHandle::new(&StorageLayout::from_info(&self.info)));
+        let server = match (options.stream_address, &streaming) {
+            (Some(address), Some(streaming)) => {
+                let server = StreamServer::bind(
+                    address,
+                    StorageLayout::from_info(&self.info),
+                    streaming.clone(),
+                    storage.reader(),
+                )?
+                .spawn()?;
+                info!(
+                    url = format!("http://{}/", server.local_addr()),
+                    "Streaming files"
+                );
+                Some(server)
+            }
+            _ => None,
+        };
+        let context = TransferContext {
+            resume_file: Some(&resume_file),
+            listener: Some(&listener),
+            streaming: streaming.as_ref(),
+        };
+        let downloaded = self.transfer(
+            options,
+            peer_addrs,
+            peer_id,
+            &mut storage,
+            context,
+            event_sender,
+        );
+        if let Some(server) = server {
+            server.stop();
+        }
+        let downloaded = downloaded?;
+        if options.handle.is_cancelled() {
+            info!(
+                downloaded_bytes = downloaded.stats.downloaded_bytes,
+                "Download cancelled"
+            );
+            return self.announce_stopped(peer_id, listener.local_addr().port(), &downloaded.stats);
+        }
         info!(
-            file_bytes = hex::encode(&downloaded.content[..128]),
-            file_size = downloaded.content.len(),
+            file_size = self.info.length,
+            target_dir = %target_dir.display(),
             download_duration = format!("{:.2?}", downloaded.download_duration),
+            uploaded_bytes = downloaded.stats.uploaded_bytes,
             "Downloaded file"
         );
+        self.announce_completed(peer_id, listener.local_addr().port(), &downloaded.stats);
 
         Ok(())
     }
 
+    pub fn download_to(
+        &self,
+        options: &DownloadOptions,
+        writer: impl Write,
+        event_sender: &Sender<AppEvent>,
+    ) -> Result<()> {
+        let peer_id = PeerId::default();
+        let listener = Self::bind_listener(peer_id)?;
+        let peer_addrs = self.fetch_peer_addresses(peer_id, Some(listener.local_addr().port()))?;
+        info!(peer_count = peer_addrs.len(), "Received peer addresses");
+
+        let mut sink = WriterSink::new(writer, StorageLayout::from_info(&self.info));
+        let context = TransferContext {
+            listener: Some(&listener),
+            ..TransferContext::default()
+        };
+        let downloaded = self.transfer(
+            options,
+            peer_addrs,
+            peer_id,
+            &mut sink,
+            context,
+            event_sender,
+        )?;
+        sink.flush()?;
+        if options.handle.is_cancelled() {
+            return self.announce_stopped(peer_id, listener.local_addr().port(), &downloaded.stats);
+        }
+        info!(
+            file_size = self.info.length,
+            download_duration = format!("{:.2?}", downloaded.download_duration),
+            "Downloaded file to output"
+        );
+        self.announce_completed(peer_id, listener.local_addr().port(), &downloaded.stats);
+        Ok(())
+    }
+
+    pub fn seed(&self, options: &DownloadOptions, data_dir: &Path, super_seed: bool) -> Result<()> {
+        let info = &self.info;
+        info!(data_dir = %data_dir.display(), "Verifying data before seeding");
+        let report = verify::verify(info, data_dir, &mut HashPool::default())?;
+        if !report.is_complete() {
+            return Err("Data does not match the torrent".into());
+        }
+
+        let peer_id = PeerId::default();
+        let listener = Self::bind_listener(peer_id)?;
+        let port = listener.local_addr().port();
+        let event = Some(AnnounceEvent::Completed);
+        let interval =
+            self.announce_transfer(peer_id, port, &TransferStats::default(), 0, event)?;
+
+        let layout = StorageLayout::from_info(info);
+        let mut storage = DiskIo::new(
+            options.storage_backend.open(data_dir, layout.clone())?,
+            layout,
+        );
+        let mut peer_set = PeerSet::new()
+            .with_rate_limits(options.handle.rate_limits().clone())
+            .with_piece_count(info.pieces.len());
+        listener.add_torrent(info.sha1, peer_set.joiner());
+        info!(
+            file_size = info.length,
+            piece_count = info.pieces.len(),
+            super_seed,
+            "Seeding file"
+        );
+        let mut seeder = downloader::FileDownloader::new(
+            &mut peer_set,
+            &mut storage,
+            info.pieces.clone(),
+            info.piece_length,
+            info.length,
+        )
+        .with_downloaded_pieces(&report.verified_pieces())
+        .with_rate_limits(options.handle.rate_limits().clone())
+        .with_handle(options.handle.clone());
+        if super_seed {
+            seeder = seeder.with_super_seeding();
+        }
+        let (stop_announcing, stopped) = mpsc::channel();
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| self.reannounce_while_seeding(options, peer_id, port, interval, stopped));
+            let result = seeder.seed();
+            drop(stop_announcing);
+            result
+        });
+        listener.remove_torrent(&info.sha1);
+        let stats = result?;
+        if options.handle.is_cancelled() {
+            self.announce_stopped(peer_id, port, &stats)?;
+        }
+        Ok(())
+    }
+
     pub fn download_from(
-        self,
+        &self,
+        options: &DownloadOptions,
         peer_addrs: Vec<SocketAddr>,
         peer_id: PeerId,
+        storage: &mut dyn Storage,
         event_sender: &Sender<AppEvent>,
     ) -> Result<DownloadedFile> {
+        let context = TransferContext::default();
+        self.transfer(options, peer_addrs, peer_id, storage, context, event_sender)
+    }
+
+    fn transfer(
+        &self,
+        options: &DownloadOptions,
+        peer_addrs: Vec<SocketAddr>,
+        peer_id: PeerId,
+        storage: &mut dyn Storage,
+        context: TransferContext,
+        event_sender: &Sender<AppEvent>,
+    ) -> Result<DownloadedFile> {
+        let TransferContext {
+            resume_file,
+            listener,
+            streaming,
+        } = context;
         let info = &self.info;
+        let mut hash_pool = HashPool::default();
+        let downloaded_pieces = match resume_file {
+            Some(resume_file) => {
+                resume_file.verified_pieces(storage, &info.pieces, &mut hash_pool)?
+            }
+            None => Bitfield::new(info.pieces.len()),
+        };
+        let piece_priorities = FilePriority::piece_priorities(
+            &StorageLayout::from_info(info),
+            &options.file_priorities,
+        );
+        if piece_priorities
+            .iter()
+            .enumerate()
+            .all(|(index, priority)| {
+                *priority == FilePriority::Skip || downloaded_pieces.has_piece(index as u32)
+            })
+        {
+            info!("All pieces are already downloaded");
+            if let Some(resume_file) = resume_file {
+                resume_file.finalize_completed(&downloaded_pieces)?;
+            }
+            event_sender.send(AppEvent::Completed)?;
+            return Ok(DownloadedFile {
+                download_duration: Duration::ZERO,
+                stats: TransferStats::default(),
+            });
+        }
 
+        let mut peer_set = PeerSet::new()
+            .with_rate_limits(options.handle.rate_limits().clone())
+            .with_piece_count(info.pieces.len());
+        if let Some(listener) = listener {
+            listener.add_torrent(info.sha1, peer_set.passive_joiner());
+        }
         info!("Probing peers");
-        let result = if let Some(mut channel) = self.request_file(peer_addrs, peer_id, event_sender)
-        {
-            info!(
-                file_size = info.length,
-                piece_count = info.pieces.len(),
-                peer_address = %channel.peer_addr(),
-                remote_id = %channel.remote_id(),
-                "Downloading file"
-            );
-            let (file_content, download_duration) = util::elapsed(|| {
-                downloader::FileDownloader::new(
-                    &mut channel,
-                    info.pieces.clone(),
-                    info.piece_length,
-                    info.length,
-                )
-                .with_progress_callback(|current, total| {
-                    let _ = event_sender
-                        .send(AppEvent::Downloading(current, total))
-                        .inspect_err(|e| error!(%e, "Failed to send downloading event"));
-                })
-                .download()
-            })?;
-            Ok(DownloadedFile {
-                content: file_content,
-                download_duration,
+        self.connect_to_peers(peer_addrs, peer_id, peer_set.joiner(), event_sender);
+
+        info!(
+            file_size = info.length,
+            piece_count = info.pieces.len(),
+            "Downloading file"
+        );
+        let mut finalize_error = None;
+        let result = util::elapsed(|| {
+            let mut downloader = downloader::FileDownloader::new(
+                &mut peer_set,
+                storage,
+                info.pieces.clone(),
+                info.piece_length,
+                info.length,
+            )
+            .with_downloaded_pieces(&downloaded_pieces)
+            .with_hash_pool(hash_pool)
+            .with_rate_limits(options.handle.rate_limits().clone())
+            .with_handle(options.handle.clone())
+            .with_progress_callback(|current, total| {
+                let _ = event_sender
+                    .send(AppEvent::Downloading(current, total))
+                    .inspect_err(|e| error!(%e, "Failed to send downloading event"));
             })
-        } else {
-            Err("No peer responded".into())
-        };
+            .with_stats_callback(|stats| {
+                let _ = event_sender
+                    .send(AppEvent::Stats(stats.clone()))
+                    .inspect_err(|e| error!(%e, "Failed to send stats event"));
+            })
+            .with_checkpoint_callback(|pieces| {
+                let Some(resume_file) = resume_file else {
+                    return;
+                };
+                let _ = resume_file
+                    .save(pieces)
+                    .inspect_err(|e| error!(%e, "Failed to save resume file"));
+                if let Err(e) = resume_file.finalize_completed(pieces) {
+                    error!(%e, "Failed to finalize downloaded files");
+                    finalize_error.get_or_insert(e);
+                }
+            });
+            if !options.file_priorities.is_empty() {
+                downloader = downloader.with_piece_priorities(piece_priorities);
+            }
+            if let Some(streaming) = streaming {
+                downloader = downloader.with_streaming(streaming.clone());
+            }
+            downloader.download()
+        });
+        if let Some(listener) = listener {
+            listener.remove_torrent(&info.sha1);
+        }
 
         event_sender.send(AppEvent::Completed)?;
-        result
+        let (stats, download_duration) = result?;
+        if let Some(e) = finalize_error {
+            return Err(e.into());
+        }
+        Ok(DownloadedFile {
+            download_duration,
+            stats,
+        })
     }
 }
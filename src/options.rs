@@ -0,0 +1,51 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    downloader::{DownloadHandle, FilePriority},
+    storage::{Preallocation, StorageBackend},
+};
+
+#[derive(Default)]
+pub struct DownloadOptions {
+    pub handle: DownloadHandle,
+    pub storage_backend: StorageBackend,
+    pub preallocation: Preallocation,
+    pub incomplete_dir: Option<PathBuf>,
+    pub stream_address: Option<SocketAddr>,
+    pub file_priorities: Vec<FilePriority>,
+}
+
+impl DownloadOptions {
+    pub fn with_handle(mut self, handle: DownloadHandle) -> Self {
+        self.handle = handle;
+        self
+    }
+
+    pub fn with_storage_backend(mut self, storage_backend: StorageBackend) -> Self {
+        self.storage_backend = storage_backend;
+        self
+    }
+
+    pub fn with_preallocation(mut self, preallocation: Preallocation) -> Self {
+        self.preallocation = preallocation;
+        self
+    }
+
+    pub fn with_streaming(mut self, address: SocketAddr) -> Self {
+        self.stream_address = Some(address);
+        self
+    }
+
+    pub fn with_incomplete_dir(mut self, incomplete_dir: impl AsRef<Path>) -> Self {
+        self.incomplete_dir = Some(incomplete_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_file_priorities(mut self, file_priorities: Vec<FilePriority>) -> Self {
+        self.file_priorities = file_priorities;
+        self
+    }
+}
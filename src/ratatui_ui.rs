@@ -2,6 +2,7 @@ use std::{
     net::SocketAddr,
     sync::mpsc::{self, Receiver, Sender},
     thread,
+    time::Duration,
 };
 
 use humansize::FormatSizeOptions;
@@ -9,17 +10,22 @@ use ratatui::{
     Frame,
     buffer::Buffer,
     crossterm::event::{self, Event},
-    layout::Rect,
+    layout::{Constraint, Layout, Rect},
     style::Stylize,
     symbols,
     text::{Line, ToLine},
     widgets::{Block, LineGauge, Padding, Paragraph, Widget},
 };
 
-use crate::result::{GenericError, Result};
+use crate::{
+    downloader::{DownloadHandle, DownloadStats},
+    result::{GenericError, Result},
+};
 
 pub enum AppEvent {
     Exit,
+    TogglePause,
+    Cancel,
     Error(GenericError),
     Resize,
     Probing {
@@ -28,11 +34,14 @@ pub enum AppEvent {
         total_count: usize,
     },
     Downloading(usize, usize),
+    Stats(DownloadStats),
     Completed,
 }
 
 pub struct App {
     app_state: DownloadState,
+    download_handle: Option<DownloadHandle>,
+    stats: DownloadStats,
     event_sender: Sender<AppEvent>,
     event_receiver: Receiver<AppEvent>,
 }
@@ -55,11 +64,18 @@ impl App {
         let (event_sender, event_receiver) = mpsc::channel::<AppEvent>();
         Self {
             app_state: DownloadState::default(),
+            download_handle: None,
+            stats: DownloadStats::default(),
             event_sender,
             event_receiver,
         }
     }
 
+    pub fn with_download_handle(mut self, handle: DownloadHandle) -> Self {
+        self.download_handle = Some(handle);
+        self
+    }
+
     pub fn start_background_task<F>(&self, task: F) -> thread::JoinHandle<()>
     where
         F: FnOnce(&Sender<AppEvent>) -> Result<()>,
@@ -88,6 +104,11 @@ impl App {
 
     fn process_app_event(&mut self) -> Result<bool> {
         match self.event_receiver.recv()? {
+            AppEvent::Probing { .. }
+                if matches!(self.app_state, DownloadState::Downloading(..)) =>
+            {
+                Ok(true)
+            }
             AppEvent::Probing {
                 address,
                 current_index,
@@ -104,6 +125,26 @@ impl App {
                 self.app_state = DownloadState::Downloading(current, total);
                 Ok(true)
             }
+            AppEvent::Stats(stats) => {
+                self.stats = stats;
+                Ok(true)
+            }
+            AppEvent::TogglePause => {
+                if let Some(handle) = &self.download_handle {
+                    if handle.is_paused() {
+                        handle.resume();
+                    } else {
+                        handle.pause();
+                    }
+                }
+                Ok(true)
+            }
+            AppEvent::Cancel => {
+                if let Some(handle) = &self.download_handle {
+                    handle.cancel();
+                }
+                Ok(true)
+            }
             AppEvent::Resize => Ok(true),
             AppEvent::Completed => Ok(false),
             AppEvent::Exit => Ok(false),
@@ -112,11 +153,20 @@ impl App {
     }
 
     fn render_ui(&mut self, f: &mut Frame) {
+        let mut help = vec![" Press ".into(), "<ESC>".bold(), " to exit".into()];
+        if self.download_handle.is_some() {
+            help.extend([
+                ", ".into(),
+                "<P>".bold(),
+                " to pause/resume, ".into(),
+                "<C>".bold(),
+                " to cancel".into(),
+            ]);
+        }
+        help.push(" ".into());
         let app_block = Block::bordered()
             .title(" BitTorrent Client ".to_line().bold().centered())
-            .title_bottom(
-                Line::from(vec![" Press ".into(), "<ESC>".bold(), " to exit ".into()]).centered(),
-            )
+            .title_bottom(Line::from(help).centered())
             .padding(Padding::horizontal(1));
         let content_area = app_block.inner(f.area());
         f.render_widget(app_block, f.area());
@@ -136,10 +186,18 @@ impl App {
                 );
             }
             DownloadState::Downloading(downloaded, total) => {
+                let paused = self
+                    .download_handle
+                    .as_ref()
+                    .is_some_and(DownloadHandle::is_paused);
+                let [gauge_area, stats_area] =
+                    Layout::vertical([Constraint::Length(1), Constraint::Length(1)])
+                        .areas(content_area);
                 f.render_widget(
-                    DownloadingStatusWidget::new(downloaded, total),
-                    content_area,
+                    DownloadingStatusWidget::new(downloaded, total, paused),
+                    gauge_area,
                 );
+                f.render_widget(TransferStatsWidget::new(&self.stats), stats_area);
             }
         };
     }
@@ -148,11 +206,12 @@ impl App {
 fn listen_for_keyboard_events(sender: &Sender<AppEvent>) -> Result<()> {
     loop {
         match event::read()? {
-            Event::Key(key) => {
-                if let event::KeyCode::Esc = key.code {
-                    sender.send(AppEvent::Exit)?;
-                }
-            }
+            Event::Key(key) => match key.code {
+                event::KeyCode::Esc => sender.send(AppEvent::Exit)?,
+                event::KeyCode::Char('p' | 'P') => sender.send(AppEvent::TogglePause)?,
+                event::KeyCode::Char('c' | 'C') => sender.send(AppEvent::Cancel)?,
+                _ => (),
+            },
             Event::Resize(_, _) => {
                 sender.send(AppEvent::Resize)?;
             }
@@ -194,14 +253,16 @@ impl Widget for ProbingStatusWidget {
 struct DownloadingStatusWidget {
     downloaded: usize,
     total: usize,
+    paused: bool,
     format: FormatSizeOptions,
 }
 
 impl DownloadingStatusWidget {
-    pub fn new(downloaded: usize, total: usize) -> Self {
+    pub fn new(downloaded: usize, total: usize, paused: bool) -> Self {
         Self {
             downloaded,
             total,
+            paused,
             format: humansize::BINARY.decimal_zeroes(2),
         }
     }
@@ -211,14 +272,57 @@ impl Widget for DownloadingStatusWidget {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let downloaded = humansize::format_size(self.downloaded, self.format);
         let total = humansize::format_size(self.total, self.format);
-        let label = format!("Downloading {downloaded} / {total}");
+        let status = if self.paused { "Paused" } else { "Downloading" };
+        let label = format!("{status} {downloaded} / {total}");
         let ratio = self.downloaded as f64 / self.total as f64;
 
-        LineGauge::default()
+        let gauge = LineGauge::default()
             .filled_symbol(symbols::line::THICK_HORIZONTAL)
             .label(label)
-            .ratio(ratio)
-            .yellow()
-            .render(area, buf);
+            .ratio(ratio);
+        if self.paused {
+            gauge.dark_gray().render(area, buf);
+        } else {
+            gauge.yellow().render(area, buf);
+        }
+    }
+}
+
+struct TransferStatsWidget<'s> {
+    stats: &'s DownloadStats,
+    format: FormatSizeOptions,
+}
+
+impl<'s> TransferStatsWidget<'s> {
+    pub fn new(stats: &'s DownloadStats) -> Self {
+        Self {
+            stats,
+            format: humansize::BINARY.decimal_zeroes(2),
+        }
     }
 }
+
+impl Widget for TransferStatsWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let download_rate = humansize::format_size(self.stats.download_rate, self.format);
+        let upload_rate = humansize::format_size(self.stats.upload_rate, self.format);
+        let eta = self.stats.eta.map_or("--".to_string(), format_duration);
+        let wasted = humansize::format_size(self.stats.wasted_bytes, self.format);
+        let line = format!(
+            "Down {download_rate}/s  Up {upload_rate}/s  ETA {eta}  Peers {}  Wasted {wasted}  Hash failures {}",
+            self.stats.peers.len(),
+            self.stats.hash_failures,
+        );
+        Paragraph::new(Line::from(line).dark_gray()).render(area, buf);
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
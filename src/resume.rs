@@ -0,0 +1,338 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    hash_pool::HashPool,
+    result::Result,
+    storage::{PartFiles, Storage, StorageLayout},
+    torrent::Info,
+    types::{Bitfield, Sha1},
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ResumeData {
+    info_hash: String,
+    #[serde(with = "serde_bytes")]
+    pieces: Vec<u8>,
+    files: Vec<FileState>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct FileState {
+    path: String,
+    length: u64,
+    mtime: u64,
+}
+
+pub struct ResumeFile {
+    path: PathBuf,
+    part_files: PartFiles,
+    layout: StorageLayout,
+    info_hash: Sha1,
+    new_files: bool,
+}
+
+impl ResumeFile {
+    pub fn new(base_dir: impl AsRef<Path>, info: &Info) -> Self {
+        let base_dir = base_dir.as_ref();
+        let layout = StorageLayout::from_info(info);
+        Self {
+            path: base_dir.join(format!("{}.resume", info.name)),
+            part_files: PartFiles::new(base_dir, layout.clone()),
+            layout,
+            info_hash: info.sha1,
+            new_files: false,
+        }
+    }
+
+    pub fn with_incomplete_dir(mut self, incomplete_dir: impl AsRef<Path>) -> Self {
+        self.part_files = self.part_files.with_incomplete_dir(incomplete_dir);
+        self
+    }
+
+    pub fn with_skipped_files(mut self, skipped: Vec<bool>) -> Self {
+        self.part_files = self
+            .part_files
+            .with_skipped_files(skipped, self.path.with_extension("parts"));
+        self
+    }
+
+    pub fn with_new_files(mut self) -> Self {
+        self.new_files = true;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn part_files(&self) -> &PartFiles {
+        &self.part_files
+    }
+
+    pub fn finalize_completed(&self, pieces: &Bitfield) -> io::Result<usize> {
+        self.part_files.finalize_completed(pieces)
+    }
+
+    pub fn verified_pieces(
+        &self,
+        storage: &mut dyn Storage,
+        piece_hashes: &[Sha1],
+        hash_pool: &mut HashPool,
+    ) -> io::Result<Bitfield> {
+        if self.new_files {
+            info!("Skipping recheck of newly created files");
+            return Ok(Bitfield::new(piece_hashes.len()));
+        }
+        match self.load_trusted_pieces() {
+            Ok(Some(pieces)) => {
+                info!(path = %self.path.display(), "Resuming download from resume file");
+                Ok(pieces)
+            }
+            Ok(None) => Ok(recheck_pieces(
+                storage,
+                &self.layout,
+                piece_hashes,
+                hash_pool,
+            )),
+            Err(err) => {
+                warn!(%err, path = %self.path.display(), "Failed to read resume file");
+                Ok(recheck_pieces(
+                    storage,
+                    &self.layout,
+                    piece_hashes,
+                    hash_pool,
+                ))
+            }
+        }
+    }
+
+    pub fn save(&self, pieces: &Bitfield) -> Result<()> {
+        let data = ResumeData {
+            info_hash: self.info_hash.to_string(),
+            pieces: pieces.as_bytes().to_vec(),
+            files: self.current_file_states()?,
+        };
+        let temp_path = self.path.with_extension("resume.tmp");
+        fs::write(&temp_path, serde_bencode::to_bytes(&data)?)?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn load_trusted_pieces(&self) -> Result<Option<Bitfield>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let data: ResumeData = serde_bencode::from_bytes(&fs::read(&self.path)?)?;
+        if data.info_hash != self.info_hash.to_string() {
+            return Err("Resume file belongs to a different torrent".into());
+        }
+        if data.files != self.current_file_states()? {
+            info!(path = %self.path.display(), "Files changed since the resume file was written");
+            return Ok(None);
+        }
+        Ok(Some(Bitfield::from_bytes(&data.pieces)))
+    }
+
+    fn current_file_states(&self) -> io::Result<Vec<FileState>> {
+        self.layout
+            .files()
+            .iter()
+            .enumerate()
+            .filter(|(file_index, _)| self.part_files.is_stored(*file_index))
+            .map(|(file_index, file)| {
+                let metadata = fs::metadata(self.part_files.location(file_index))?;
+                let mtime = metadata
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(io::Error::other)?;
+                Ok(FileState {
+                    path: file.path.to_string_lossy().into_owned(),
+                    length: metadata.len(),
+                    mtime: mtime.as_nanos() as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn recheck_pieces(
+    storage: &mut dyn Storage,
+    layout: &StorageLayout,
+    piece_hashes: &[Sha1],
+    hash_pool: &mut HashPool,
+) -> Bitfield {
+    let mut pieces = Bitfield::new(piece_hashes.len());
+    let stored_pieces = piece_hashes.iter().enumerate().filter_map(|(index, hash)| {
+        let index = index as u32;
+        let data = storage
+            .read_block(index, 0, layout.piece_length(index))
+            .ok()?;
+        Some((index, data, *hash))
+    });
+    hash_pool.verify_all(stored_pieces, |result| {
+        if result.is_valid {
+            pieces.set_piece(result.piece_index);
+        }
+    });
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        storage::{FileStorage, MemoryStorage},
+        torrent::FileEntry,
+    };
+
+    use super::*;
+
+    fn make_info(data: &[u8], piece_length: u32) -> Info {
+        Info {
+            sha1: Sha1::calculate(data),
+            name: "data.bin".to_string(),
+            piece_length,
+            length: data.len(),
+            pieces: data
+                .chunks(piece_length as usize)
+                .map(Sha1::calculate)
+                .collect(),
+            files: vec![FileEntry {
+                path: PathBuf::from("data.bin"),
+                length: data.len(),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn recheck_pieces_of_existing_data() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let mut corrupted = data.clone();
+        corrupted[12] = 0xff;
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), &corrupted).unwrap();
+        let layout = StorageLayout::from_info(&info);
+        let mut storage = FileStorage::create(dir.path(), layout.clone()).unwrap();
+
+        let pieces = recheck_pieces(&mut storage, &layout, &info.pieces, &mut HashPool::new(2));
+        assert_eq!(pieces, Bitfield::from_bytes(&[0b1010_0000]));
+    }
+
+    #[test]
+    fn recheck_pieces_when_data_is_missing() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), &data[..12]).unwrap();
+        let layout = StorageLayout::from_info(&info);
+        let mut storage = FileStorage::create(dir.path(), layout.clone()).unwrap();
+
+        let pieces = recheck_pieces(&mut storage, &layout, &info.pieces, &mut HashPool::new(2));
+        assert_eq!(pieces, Bitfield::from_bytes(&[0b1000_0000]));
+    }
+
+    #[test]
+    fn trust_saved_pieces_when_files_are_unchanged() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileStorage::create(dir.path(), StorageLayout::from_info(&info)).unwrap();
+        let saved_pieces = Bitfield::from_bytes(&[0b0100_0000]);
+        let resume_file = ResumeFile::new(dir.path(), &info);
+        resume_file.save(&saved_pieces).unwrap();
+
+        let pieces = resume_file
+            .verified_pieces(&mut storage, &info.pieces, &mut HashPool::new(2))
+            .unwrap();
+        assert_eq!(pieces, saved_pieces);
+    }
+
+    #[test]
+    fn recheck_pieces_when_files_changed_since_save() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileStorage::create(dir.path(), StorageLayout::from_info(&info)).unwrap();
+        let resume_file = ResumeFile::new(dir.path(), &info);
+        resume_file
+            .save(&Bitfield::from_bytes(&[0b0100_0000]))
+            .unwrap();
+        fs::write(dir.path().join("data.bin"), &data).unwrap();
+
+        let pieces = resume_file
+            .verified_pieces(&mut storage, &info.pieces, &mut HashPool::new(2))
+            .unwrap();
+        assert_eq!(pieces, Bitfield::from_bytes(&[0b1110_0000]));
+    }
+
+    #[test]
+    fn recheck_pieces_when_resume_file_is_corrupted() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), &data[..10]).unwrap();
+        let mut storage = FileStorage::create(dir.path(), StorageLayout::from_info(&info)).unwrap();
+        let resume_file = ResumeFile::new(dir.path(), &info);
+        fs::write(resume_file.path(), b"garbage").unwrap();
+
+        let pieces = resume_file
+            .verified_pieces(&mut storage, &info.pieces, &mut HashPool::new(2))
+            .unwrap();
+        assert_eq!(pieces, Bitfield::from_bytes(&[0b1000_0000]));
+    }
+
+    #[test]
+    fn skip_recheck_of_newly_created_files() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        let resume_file = ResumeFile::new(dir.path(), &info);
+        assert!(!resume_file.part_files().has_existing_files());
+        fs::write(resume_file.part_files().part_path(0), []).unwrap();
+        assert!(resume_file.part_files().has_existing_files());
+
+        let mut storage = MemoryStorage::new(10, data.len());
+        storage.write_piece(0, &data[..10]).unwrap();
+        let pieces = resume_file
+            .with_new_files()
+            .verified_pieces(&mut storage, &info.pieces, &mut HashPool::new(2))
+            .unwrap();
+        assert_eq!(pieces, Bitfield::new(3));
+    }
+
+    #[test]
+    fn trust_saved_pieces_after_part_file_is_finalized() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&data, 10);
+
+        let dir = tempfile::tempdir().unwrap();
+        let part_files = PartFiles::new(dir.path(), StorageLayout::from_info(&info));
+        fs::write(part_files.part_path(0), &data).unwrap();
+        let saved_pieces = Bitfield::from_bytes(&[0b1110_0000]);
+        let resume_file = ResumeFile::new(dir.path(), &info);
+        resume_file.save(&saved_pieces).unwrap();
+        part_files.finalize_completed(&saved_pieces).unwrap();
+
+        let mut storage = FileStorage::create(dir.path(), part_files.storage_layout()).unwrap();
+        let pieces = resume_file
+            .verified_pieces(&mut storage, &info.pieces, &mut HashPool::new(2))
+            .unwrap();
+        assert_eq!(pieces, saved_pieces);
+        assert_eq!(fs::read(dir.path().join("data.bin")).unwrap(), data);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{io, path::Path};
+
+mod disk_io;
+mod file_storage;
+mod layout;
+mod memory_storage;
+mod mmap_storage;
+mod part_files;
+mod parts_file;
+mod preallocation;
+mod writer_sink;
+
+pub use disk_io::{DiskIo, DiskReader};
+pub use file_storage::FileStorage;
+pub use layout::{FileSegment, StorageLayout};
+pub use memory_storage::MemoryStorage;
+pub use mmap_storage::MmapStorage;
+pub use part_files::PartFiles;
+pub use preallocation::{Preallocation, check_free_space, preallocate};
+pub use writer_sink::WriterSink;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    #[default]
+    File,
+    Mmap,
+}
+
+impl StorageBackend {
+    pub fn open(
+        self,
+        base_dir: impl AsRef<Path>,
+        layout: StorageLayout,
+    ) -> io::Result<Box<dyn Storage + Send>> {
+        Ok(match self {
+            StorageBackend::File => Box::new(FileStorage::create(base_dir, layout)?),
+            StorageBackend::Mmap => Box::new(MmapStorage::create(base_dir, layout)?),
+        })
+    }
+}
+
+pub trait Storage {
+    // `data` may span several consecutive pieces starting at `piece_index`,
+    // since DiskIo coalesces adjacent pieces into a single write.
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()>;
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    fn is_backlogged(&self) -> bool {
+        false
+    }
+}
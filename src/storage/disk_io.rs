@@ -0,0 +1,603 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::warn;
+
+use super::{Storage, StorageLayout};
+
+enum DiskCommand {
+    Write,
+    Read {
+        piece_index: u32,
+        offset: u32,
+        length: u32,
+        reply: Sender<io::Result<Vec<u8>>>,
+    },
+    Flush {
+        reply: Sender<io::Result<()>>,
+    },
+    Shutdown,
+}
+
+#[derive(Default)]
+struct WriteBacklog {
+    pieces: BTreeMap<u32, Arc<[u8]>>,
+    bytes: usize,
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl WriteBacklog {
+    fn check_error(&self) -> io::Result<()> {
+        match &self.error {
+            Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+type PendingPiece = (u32, Arc<[u8]>);
+
+struct SharedBacklog {
+    backlog: Mutex<WriteBacklog>,
+    written: Condvar,
+}
+
+struct ReadCache {
+    pieces: VecDeque<(u32, Vec<u8>)>,
+    bytes: usize,
+    capacity: usize,
+}
+
+impl ReadCache {
+    fn get(&mut self, piece_index: u32) -> Option<&[u8]> {
+        let position = self
+            .pieces
+            .iter()
+            .position(|(index, _)| *index == piece_index)?;
+        let entry = self.pieces.remove(position)?;
+        self.pieces.push_back(entry);
+        self.pieces.back().map(|(_, data)| data.as_slice())
+    }
+
+    fn insert(&mut self, piece_index: u32, data: Vec<u8>) {
+        self.remove(piece_index);
+        self.bytes += data.len();
+        self.pieces.push_back((piece_index, data));
+        while self.bytes > self.capacity
+            && self.pieces.len() > 1
+            && let Some((_, evicted)) = self.pieces.pop_front()
+        {
+            self.bytes -= evicted.len();
+        }
+    }
+
+    fn remove(&mut self, piece_index: u32) {
+        if let Some(position) = self
+            .pieces
+            .iter()
+            .position(|(index, _)| *index == piece_index)
+        {
+            let (_, data) = self.pieces.remove(position).unwrap();
+            self.bytes -= data.len();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DiskReader {
+    layout: StorageLayout,
+    shared: Arc<SharedBacklog>,
+    command_sender: Sender<DiskCommand>,
+}
+
+impl DiskReader {
+    pub fn read_block(&self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        match self.pending_piece(piece_index) {
+            Some(piece) => slice_block(&piece, offset, length),
+            None => self.request(|reply| DiskCommand::Read {
+                piece_index,
+                offset,
+                length,
+                reply,
+            }),
+        }
+    }
+
+    fn pending_piece(&self, piece_index: u32) -> Option<Arc<[u8]>> {
+        self.shared
+            .backlog
+            .lock()
+            .unwrap()
+            .pieces
+            .get(&piece_index)
+            .cloned()
+    }
+
+    fn send(&self, command: DiskCommand) -> io::Result<()> {
+        self.command_sender
+            .send(command)
+            .map_err(|_| disk_thread_stopped())
+    }
+
+    fn request<R>(
+        &self,
+        command: impl FnOnce(Sender<io::Result<R>>) -> DiskCommand,
+    ) -> io::Result<R> {
+        let (reply, response) = mpsc::channel();
+        self.send(command(reply))?;
+        response.recv().map_err(|_| disk_thread_stopped())?
+    }
+}
+
+fn disk_thread_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Disk I/O thread has stopped")
+}
+
+fn slice_block(piece: &[u8], offset: u32, length: u32) -> io::Result<Vec<u8>> {
+    let (start, end) = (offset as usize, offset as usize + length as usize);
+    if end > piece.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Block is out of piece bounds: {}..{}", start, end),
+        ));
+    }
+    Ok(piece[start..end].to_vec())
+}
+
+pub struct DiskIo {
+    reader: DiskReader,
+    worker: Option<JoinHandle<()>>,
+    write_capacity: usize,
+    read_cache: ReadCache,
+}
+
+impl DiskIo {
+    pub const DEFAULT_WRITE_CACHE: usize = 64 * 1024 * 1024;
+    pub const DEFAULT_READ_CACHE: usize = 16 * 1024 * 1024;
+    const MAX_COALESCED_WRITE: usize = 4 * 1024 * 1024;
+
+    pub fn new(storage: Box<dyn Storage + Send>, layout: StorageLayout) -> Self {
+        let shared = Arc::new(SharedBacklog {
+            backlog: Mutex::new(WriteBacklog::default()),
+            written: Condvar::new(),
+        });
+        let (command_sender, command_receiver) = mpsc::channel();
+        let worker_shared = shared.clone();
+        let worker_layout = layout.clone();
+        let worker = thread::spawn(move || {
+            DiskWorker {
+                storage,
+                layout: worker_layout,
+                shared: worker_shared,
+            }
+            .run(&command_receiver)
+        });
+
+        Self {
+            reader: DiskReader {
+                layout,
+                shared,
+                command_sender,
+            },
+            worker: Some(worker),
+            write_capacity: Self::DEFAULT_WRITE_CACHE,
+            read_cache: ReadCache {
+                pieces: VecDeque::new(),
+                bytes: 0,
+                capacity: Self::DEFAULT_READ_CACHE,
+            },
+        }
+    }
+
+    pub fn with_write_cache(mut self, capacity: usize) -> Self {
+        self.write_capacity = capacity;
+        self
+    }
+
+    pub fn with_read_cache(mut self, capacity: usize) -> Self {
+        self.read_cache.capacity = capacity;
+        self
+    }
+
+    pub fn reader(&self) -> DiskReader {
+        self.reader.clone()
+    }
+
+    pub fn backlog_bytes(&self) -> usize {
+        self.reader.shared.backlog.lock().unwrap().bytes
+    }
+
+    fn read_piece(&mut self, piece_index: u32) -> io::Result<&[u8]> {
+        if self.read_cache.get(piece_index).is_none() {
+            let length = self.reader.layout.piece_length(piece_index);
+            let data = self.reader.read_block(piece_index, 0, length)?;
+            self.read_cache.insert(piece_index, data);
+        }
+        Ok(self.read_cache.get(piece_index).unwrap())
+    }
+}
+
+impl Storage for DiskIo {
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        self.read_cache.remove(piece_index);
+        {
+            let shared = &self.reader.shared;
+            let mut backlog = shared.backlog.lock().unwrap();
+            while backlog.error.is_none()
+                && backlog.bytes > 0
+                && backlog.bytes + data.len() > self.write_capacity
+            {
+                backlog = shared.written.wait(backlog).unwrap();
+            }
+            backlog.check_error()?;
+            if let Some(replaced) = backlog.pieces.insert(piece_index, Arc::from(data)) {
+                backlog.bytes -= replaced.len();
+            }
+            backlog.bytes += data.len();
+        }
+        self.reader.send(DiskCommand::Write)
+    }
+
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        match self.reader.pending_piece(piece_index) {
+            Some(piece) => slice_block(&piece, offset, length),
+            None => slice_block(self.read_piece(piece_index)?, offset, length),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.request(|reply| DiskCommand::Flush { reply })
+    }
+
+    fn is_backlogged(&self) -> bool {
+        self.backlog_bytes() > self.write_capacity / 2
+    }
+}
+
+impl Drop for DiskIo {
+    fn drop(&mut self) {
+        let _ = self.reader.send(DiskCommand::Shutdown);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct DiskWorker {
+    storage: Box<dyn Storage + Send>,
+    layout: StorageLayout,
+    shared: Arc<SharedBacklog>,
+}
+
+impl DiskWorker {
+    fn run(&mut self, commands: &Receiver<DiskCommand>) {
+        while let Ok(command) = commands.recv() {
+            let mut batch = vec![command];
+            batch.extend(commands.try_iter());
+
+            let (reads, rest): (Vec<_>, Vec<_>) = batch
+                .into_iter()
+                .partition(|command| matches!(command, DiskCommand::Read { .. }));
+            for command in reads {
+                self.execute(command);
+            }
+            if rest
+                .iter()
+                .any(|command| matches!(command, DiskCommand::Write))
+            {
+                self.write_backlog();
+            }
+            let shutdown = rest
+                .iter()
+                .any(|command| matches!(command, DiskCommand::Shutdown));
+            for command in rest {
+                self.execute(command);
+            }
+            if shutdown {
+                break;
+            }
+        }
+        self.write_backlog();
+    }
+
+    fn execute(&mut self, command: DiskCommand) {
+        match command {
+            DiskCommand::Write | DiskCommand::Shutdown => {}
+            DiskCommand::Read {
+                piece_index,
+                offset,
+                length,
+                reply,
+            } => {
+                let _ = reply.send(self.storage.read_block(piece_index, offset, length));
+            }
+            DiskCommand::Flush { reply } => {
+                let result = self
+                    .shared
+                    .backlog
+                    .lock()
+                    .unwrap()
+                    .check_error()
+                    .and_then(|_| self.storage.flush());
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    fn write_backlog(&mut self) {
+        let pieces = self
+            .shared
+            .backlog
+            .lock()
+            .unwrap()
+            .pieces
+            .iter()
+            .map(|(index, data)| (*index, data.clone()))
+            .collect::<Vec<_>>();
+
+        for run in self.coalesce(&pieces) {
+            let result = match run {
+                [(piece_index, data)] => self.storage.write_piece(*piece_index, data),
+                _ => {
+                    let data = run.iter().flat_map(|(_, data)| data.iter().copied());
+                    self.storage
+                        .write_piece(run[0].0, &data.collect::<Vec<_>>())
+                }
+            };
+
+            let mut backlog = self.shared.backlog.lock().unwrap();
+            for (piece_index, data) in run {
+                if backlog
+                    .pieces
+                    .get(piece_index)
+                    .is_some_and(|pending| Arc::ptr_eq(pending, data))
+                {
+                    backlog.pieces.remove(piece_index);
+                    backlog.bytes -= data.len();
+                }
+            }
+            if let Err(e) = result {
+                warn!(%e, "Failed to write pieces to disk");
+                backlog.error.get_or_insert((e.kind(), e.to_string()));
+            }
+            self.shared.written.notify_all();
+        }
+    }
+
+    fn coalesce<'p>(&self, pieces: &'p [PendingPiece]) -> Vec<&'p [PendingPiece]> {
+        let mut runs = vec![];
+        let mut run_start = 0;
+        let mut run_bytes = 0;
+        for (position, (piece_index, data)) in pieces.iter().enumerate() {
+            let continues_run = position > run_start && {
+                let (previous_index, previous_data) = &pieces[position - 1];
+                previous_index + 1 == *piece_index
+                    && previous_data.len() == self.layout.piece_length(*previous_index) as usize
+                    && run_bytes + data.len() <= DiskIo::MAX_COALESCED_WRITE
+            };
+            if position > run_start && !continues_run {
+                runs.push(&pieces[run_start..position]);
+                run_start = position;
+                run_bytes = 0;
+            }
+            run_bytes += data.len();
+        }
+        if run_start < pieces.len() {
+            runs.push(&pieces[run_start..]);
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use crate::{storage::MemoryStorage, torrent::FileEntry};
+
+    use super::*;
+
+    fn make_layout(piece_length: u32, total_length: usize) -> StorageLayout {
+        StorageLayout::new(
+            piece_length,
+            vec![FileEntry {
+                path: PathBuf::from("file.bin"),
+                length: total_length,
+                ..Default::default()
+            }],
+        )
+    }
+
+    type WriteLog = Arc<Mutex<Vec<(u32, usize)>>>;
+
+    struct WriteGate {
+        started: Receiver<u32>,
+        release: Sender<()>,
+    }
+
+    impl WriteGate {
+        fn wait_for_write(&self) -> u32 {
+            self.started.recv_timeout(Duration::from_secs(5)).unwrap()
+        }
+    }
+
+    struct RecordingStorage {
+        inner: MemoryStorage,
+        writes: WriteLog,
+        gate: Option<(Sender<u32>, Receiver<()>)>,
+    }
+
+    impl Storage for RecordingStorage {
+        fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+            if let Some((started, release)) = &self.gate {
+                let _ = started.send(piece_index);
+                let _ = release.recv();
+            }
+            self.writes.lock().unwrap().push((piece_index, data.len()));
+            self.inner.write_piece(piece_index, data)
+        }
+
+        fn read_block(
+            &mut self,
+            piece_index: u32,
+            offset: u32,
+            length: u32,
+        ) -> io::Result<Vec<u8>> {
+            self.inner.read_block(piece_index, offset, length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    struct FailingStorage;
+
+    impl Storage for FailingStorage {
+        fn write_piece(&mut self, _piece_index: u32, _data: &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "Disk is full"))
+        }
+
+        fn read_block(&mut self, _: u32, _: u32, _: u32) -> io::Result<Vec<u8>> {
+            Err(io::Error::other("Unreadable"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn recording_disk_io(piece_length: u32, total_length: usize) -> DiskIo {
+        let storage = RecordingStorage {
+            inner: MemoryStorage::new(piece_length, total_length),
+            writes: Arc::new(Mutex::new(vec![])),
+            gate: None,
+        };
+        DiskIo::new(Box::new(storage), make_layout(piece_length, total_length))
+    }
+
+    // Every write blocks until the gate is released or dropped, so the
+    // tests control which pieces are still in the backlog.
+    fn gated_disk_io(piece_length: u32, total_length: usize) -> (DiskIo, WriteLog, WriteGate) {
+        let writes = Arc::new(Mutex::new(vec![]));
+        let (started_sender, started) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let storage = RecordingStorage {
+            inner: MemoryStorage::new(piece_length, total_length),
+            writes: writes.clone(),
+            gate: Some((started_sender, release_receiver)),
+        };
+        let disk_io = DiskIo::new(Box::new(storage), make_layout(piece_length, total_length));
+        (disk_io, writes, WriteGate { started, release })
+    }
+
+    #[test]
+    fn write_pieces_in_background_and_read_them_back() {
+        let mut disk_io = recording_disk_io(4, 10);
+
+        disk_io.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        disk_io.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        disk_io.write_piece(2, &[9, 10]).unwrap();
+        disk_io.flush().unwrap();
+
+        assert_eq!(disk_io.backlog_bytes(), 0);
+        assert_eq!(disk_io.read_block(0, 1, 2).unwrap(), vec![2, 3]);
+        assert_eq!(disk_io.read_block(2, 0, 2).unwrap(), vec![9, 10]);
+    }
+
+    #[test]
+    fn serve_reads_from_write_backlog() {
+        let (mut disk_io, _, gate) = gated_disk_io(4, 8);
+
+        disk_io.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(gate.wait_for_write(), 0);
+        disk_io.write_piece(1, &[5, 6, 7, 8]).unwrap();
+
+        assert_eq!(disk_io.backlog_bytes(), 8);
+        assert_eq!(disk_io.read_block(1, 2, 2).unwrap(), vec![7, 8]);
+        assert_eq!(disk_io.read_block(0, 0, 2).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn coalesce_adjacent_pieces_into_one_write() {
+        let (mut disk_io, writes, gate) = gated_disk_io(4, 16);
+
+        disk_io.write_piece(3, &[0; 4]).unwrap();
+        assert_eq!(gate.wait_for_write(), 3);
+        disk_io.write_piece(0, &[1; 4]).unwrap();
+        disk_io.write_piece(1, &[2; 4]).unwrap();
+        disk_io.write_piece(2, &[3; 4]).unwrap();
+        drop(gate);
+        disk_io.flush().unwrap();
+
+        assert_eq!(*writes.lock().unwrap(), vec![(3, 4), (0, 12)]);
+        assert_eq!(disk_io.read_block(1, 0, 4).unwrap(), vec![2; 4]);
+    }
+
+    #[test]
+    fn report_backlog_when_disk_falls_behind() {
+        let (disk_io, _, gate) = gated_disk_io(4, 16);
+        let mut disk_io = disk_io.with_write_cache(8);
+
+        disk_io.write_piece(0, &[1; 4]).unwrap();
+        assert_eq!(gate.wait_for_write(), 0);
+        disk_io.write_piece(2, &[1; 4]).unwrap();
+        assert!(disk_io.is_backlogged());
+
+        gate.release.send(()).unwrap();
+        assert_eq!(gate.wait_for_write(), 2);
+        drop(gate);
+        disk_io.write_piece(3, &[1; 4]).unwrap();
+        assert!(disk_io.backlog_bytes() <= 8);
+        disk_io.flush().unwrap();
+        assert!(!disk_io.is_backlogged());
+    }
+
+    #[test]
+    fn cache_pieces_read_for_uploads() {
+        let mut disk_io = recording_disk_io(4, 8);
+        disk_io.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        disk_io.flush().unwrap();
+
+        assert_eq!(disk_io.read_block(0, 0, 2).unwrap(), vec![1, 2]);
+        assert_eq!(disk_io.read_cache.pieces.len(), 1);
+        assert_eq!(disk_io.read_block(0, 2, 2).unwrap(), vec![3, 4]);
+
+        disk_io.write_piece(0, &[4, 3, 2, 1]).unwrap();
+        assert!(disk_io.read_cache.pieces.is_empty());
+        assert_eq!(disk_io.read_block(0, 0, 2).unwrap(), vec![4, 3]);
+    }
+
+    #[test]
+    fn surface_write_errors_on_flush() {
+        let mut disk_io = DiskIo::new(Box::new(FailingStorage), make_layout(4, 8));
+
+        disk_io.write_piece(0, &[1; 4]).unwrap();
+        let error = disk_io.flush().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+        assert!(disk_io.write_piece(1, &[1; 4]).is_err());
+    }
+
+    #[test]
+    fn share_reader_with_other_threads() {
+        let mut disk_io = recording_disk_io(4, 8);
+        let reader = disk_io.reader();
+        disk_io.write_piece(1, &[5, 6, 7, 8]).unwrap();
+
+        let block = thread::spawn({
+            let reader = reader.clone();
+            move || reader.read_block(1, 1, 2)
+        });
+        assert_eq!(block.join().unwrap().unwrap(), vec![6, 7]);
+
+        drop(disk_io);
+        assert!(reader.read_block(1, 0, 4).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use super::{Storage, StorageLayout, parts_file::PartsFile};
+
+pub struct FileStorage {
+    layout: StorageLayout,
+    files: Vec<Option<File>>,
+    parts: Option<PartsFile>,
+}
+
+impl FileStorage {
+    pub fn create(base_dir: impl AsRef<Path>, layout: StorageLayout) -> io::Result<Self> {
+        let base_dir = base_dir.as_ref();
+        let files = layout
+            .files()
+            .iter()
+            .enumerate()
+            .map(|(file_index, file)| {
+                if !layout.is_stored(file_index) {
+                    return Ok(None);
+                }
+                Self::open_file(&base_dir.join(&file.path)).map(Some)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            parts: PartsFile::for_layout(base_dir, &layout),
+            layout,
+            files,
+        })
+    }
+
+    pub(super) fn open_file(path: &Path) -> io::Result<File> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    }
+}
+
+impl Storage for FileStorage {
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        let piece_start = self.layout.piece_start(piece_index);
+        let mut data_offset = 0;
+        for segment in self.layout.segments(piece_start, data.len()) {
+            if let Some(file) = &mut self.files[segment.file_index] {
+                file.seek(SeekFrom::Start(segment.file_offset))?;
+                file.write_all(&data[data_offset..data_offset + segment.length])?;
+            }
+            data_offset += segment.length;
+        }
+        match &mut self.parts {
+            Some(parts) => parts.write_skipped(&self.layout, piece_start, data),
+            None => Ok(()),
+        }
+    }
+
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        let block_start = self.layout.piece_start(piece_index) + offset as usize;
+        let mut block = vec![0; length as usize];
+        let mut block_offset = 0;
+        for segment in self.layout.segments(block_start, length as usize) {
+            if let Some(file) = &mut self.files[segment.file_index] {
+                file.seek(SeekFrom::Start(segment.file_offset))?;
+                file.read_exact(&mut block[block_offset..block_offset + segment.length])?;
+            }
+            block_offset += segment.length;
+        }
+        if let Some(parts) = &mut self.parts {
+            parts.read_skipped(&self.layout, block_start, &mut block)?;
+        }
+        Ok(block)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.iter_mut().flatten() {
+            file.sync_data()?;
+        }
+        match &mut self.parts {
+            Some(parts) => parts.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::torrent::FileEntry;
+
+    use super::*;
+
+    fn make_layout() -> StorageLayout {
+        StorageLayout::new(
+            4,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("first.bin"),
+                    length: 6,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("nested/second.bin"),
+                    length: 4,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn write_pieces_to_their_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileStorage::create(dir.path(), make_layout()).unwrap();
+
+        storage.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        storage.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        storage.write_piece(2, &[9, 10]).unwrap();
+        storage.flush().unwrap();
+
+        assert_eq!(
+            fs::read(dir.path().join("first.bin")).unwrap(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            fs::read(dir.path().join("nested/second.bin")).unwrap(),
+            vec![7, 8, 9, 10]
+        );
+    }
+
+    #[test]
+    fn read_block_spanning_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = FileStorage::create(dir.path(), make_layout()).unwrap();
+
+        storage.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        storage.write_piece(1, &[5, 6, 7, 8]).unwrap();
+
+        assert_eq!(storage.read_block(1, 1, 2).unwrap(), vec![6, 7]);
+    }
+
+    #[test]
+    fn keep_boundary_data_of_skipped_files_in_parts_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = make_layout().with_skipped_files(vec![true, false], "data.parts");
+        let mut storage = FileStorage::create(dir.path(), layout).unwrap();
+
+        storage.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        storage.write_piece(2, &[9, 10]).unwrap();
+        storage.flush().unwrap();
+
+        assert!(!dir.path().join("first.bin").exists());
+        assert_eq!(
+            fs::read(dir.path().join("nested/second.bin")).unwrap(),
+            vec![7, 8, 9, 10]
+        );
+        assert_eq!(storage.read_block(1, 0, 4).unwrap(), vec![5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn never_write_padding_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = make_layout().files().to_vec();
+        files[0].attributes.padding = true;
+        let mut storage = FileStorage::create(dir.path(), StorageLayout::new(4, files)).unwrap();
+
+        storage.write_piece(1, &[0, 0, 7, 8]).unwrap();
+
+        assert!(!dir.path().join("first.bin").exists());
+        assert_eq!(storage.read_block(1, 0, 4).unwrap(), vec![0, 0, 7, 8]);
+    }
+
+    #[test]
+    fn keep_existing_file_content() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("first.bin"), [1, 2, 3, 4, 5, 6]).unwrap();
+
+        let mut storage = FileStorage::create(dir.path(), make_layout()).unwrap();
+        assert_eq!(storage.read_block(0, 0, 4).unwrap(), vec![1, 2, 3, 4]);
+    }
+}
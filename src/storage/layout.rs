@@ -0,0 +1,244 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::torrent::{FileEntry, Info};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSegment {
+    pub file_index: usize,
+    pub file_offset: u64,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageLayout {
+    piece_length: u32,
+    files: Vec<FileEntry>,
+    total_length: usize,
+    skipped: Vec<bool>,
+    parts_path: Option<PathBuf>,
+}
+
+impl StorageLayout {
+    pub fn new(piece_length: u32, files: Vec<FileEntry>) -> Self {
+        let total_length = files.iter().map(|file| file.length).sum();
+        Self {
+            piece_length,
+            files,
+            total_length,
+            skipped: vec![],
+            parts_path: None,
+        }
+    }
+
+    pub fn with_skipped_files(mut self, skipped: Vec<bool>, parts_path: impl AsRef<Path>) -> Self {
+        self.skipped = skipped;
+        self.parts_path = Some(parts_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn from_info(info: &Info) -> Self {
+        Self::new(info.piece_length, info.files.clone())
+    }
+
+    pub fn files(&self) -> &[FileEntry] {
+        &self.files
+    }
+
+    pub fn relocate(&self, mut location: impl FnMut(usize, &FileEntry) -> PathBuf) -> Self {
+        let files = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| FileEntry {
+                path: location(index, file),
+                ..file.clone()
+            })
+            .collect();
+        Self {
+            files,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_skipped(&self, file_index: usize) -> bool {
+        self.skipped.get(file_index).copied().unwrap_or(false)
+    }
+
+    pub fn is_stored(&self, file_index: usize) -> bool {
+        self.files[file_index].has_data() && !self.is_skipped(file_index)
+    }
+
+    pub fn parts_path(&self) -> Option<&Path> {
+        self.parts_path
+            .as_deref()
+            .filter(|_| self.skipped.contains(&true))
+    }
+
+    pub fn file_start(&self, file_index: usize) -> usize {
+        self.files[..file_index]
+            .iter()
+            .map(|file| file.length)
+            .sum()
+    }
+
+    pub fn file_pieces(&self, file_index: usize) -> Range<u32> {
+        let file_start = self.file_start(file_index);
+        let file_end = file_start + self.files[file_index].length;
+        if file_start == file_end {
+            return 0..0;
+        }
+        let piece_length = self.piece_length as usize;
+        (file_start / piece_length) as u32..file_end.div_ceil(piece_length) as u32
+    }
+
+    pub fn total_length(&self) -> usize {
+        self.total_length
+    }
+
+    pub fn piece_count(&self) -> u32 {
+        self.total_length.div_ceil(self.piece_length as usize) as u32
+    }
+
+    pub fn piece_at(&self, offset: usize) -> u32 {
+        (offset / self.piece_length as usize) as u32
+    }
+
+    pub fn piece_start(&self, piece_index: u32) -> usize {
+        piece_index as usize * self.piece_length as usize
+    }
+
+    pub fn piece_length(&self, piece_index: u32) -> u32 {
+        let piece_start = self.piece_start(piece_index);
+        let piece_end = (piece_start + self.piece_length as usize).min(self.total_length);
+        (piece_end - piece_start) as u32
+    }
+
+    pub fn segments(&self, start: usize, length: usize) -> Vec<FileSegment> {
+        let end = start + length;
+        let mut segments = vec![];
+        let mut file_start = 0;
+
+        for (file_index, file) in self.files.iter().enumerate() {
+            let file_end = file_start + file.length;
+            let segment_start = start.max(file_start);
+            let segment_end = end.min(file_end);
+            if segment_start < segment_end {
+                segments.push(FileSegment {
+                    file_index,
+                    file_offset: (segment_start - file_start) as u64,
+                    length: segment_end - segment_start,
+                });
+            }
+            if file_end >= end {
+                break;
+            }
+            file_start = file_end;
+        }
+
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn make_layout(lengths: &[usize]) -> StorageLayout {
+        let files = lengths
+            .iter()
+            .enumerate()
+            .map(|(index, length)| FileEntry {
+                path: PathBuf::from(format!("file-{index}")),
+                length: *length,
+                ..Default::default()
+            })
+            .collect();
+        StorageLayout::new(10, files)
+    }
+
+    #[test]
+    fn segment_inside_single_file() {
+        let layout = make_layout(&[100]);
+        assert_eq!(
+            layout.segments(20, 10),
+            vec![FileSegment {
+                file_index: 0,
+                file_offset: 20,
+                length: 10
+            }]
+        );
+    }
+
+    #[test]
+    fn segment_spanning_multiple_files() {
+        let layout = make_layout(&[5, 3, 20]);
+        assert_eq!(
+            layout.segments(4, 10),
+            vec![
+                FileSegment {
+                    file_index: 0,
+                    file_offset: 4,
+                    length: 1
+                },
+                FileSegment {
+                    file_index: 1,
+                    file_offset: 0,
+                    length: 3
+                },
+                FileSegment {
+                    file_index: 2,
+                    file_offset: 0,
+                    length: 6
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_empty_files() {
+        let layout = make_layout(&[5, 0, 5]);
+        assert_eq!(
+            layout.segments(0, 10),
+            vec![
+                FileSegment {
+                    file_index: 0,
+                    file_offset: 0,
+                    length: 5
+                },
+                FileSegment {
+                    file_index: 2,
+                    file_offset: 0,
+                    length: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn total_length_is_sum_of_file_lengths() {
+        let layout = make_layout(&[5, 3, 20]);
+        assert_eq!(layout.total_length(), 28);
+    }
+
+    #[test]
+    fn pieces_overlapping_each_file() {
+        let layout = make_layout(&[5, 0, 20, 5]);
+        assert_eq!(layout.file_pieces(0), 0..1);
+        assert_eq!(layout.file_pieces(1), 0..0);
+        assert_eq!(layout.file_pieces(2), 0..3);
+        assert_eq!(layout.file_pieces(3), 2..3);
+    }
+
+    #[test]
+    fn last_piece_is_shorter() {
+        let layout = make_layout(&[5, 3, 20]);
+        assert_eq!(layout.piece_count(), 3);
+        assert_eq!(layout.piece_length(1), 10);
+        assert_eq!(layout.piece_length(2), 8);
+    }
+}
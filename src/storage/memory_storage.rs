@@ -0,0 +1,81 @@
+use std::io;
+
+use super::Storage;
+
+pub struct MemoryStorage {
+    piece_length: u32,
+    buffer: Vec<u8>,
+}
+
+impl MemoryStorage {
+    pub fn new(piece_length: u32, total_length: usize) -> Self {
+        Self {
+            piece_length,
+            buffer: vec![0; total_length],
+        }
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn into_content(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    fn byte_range(
+        &self,
+        piece_index: u32,
+        offset: u32,
+        length: usize,
+    ) -> io::Result<(usize, usize)> {
+        let start = piece_index as usize * self.piece_length as usize + offset as usize;
+        let end = start + length;
+        if end > self.buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Block is out of storage bounds: {}..{}", start, end),
+            ));
+        }
+        Ok((start, end))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        let (start, end) = self.byte_range(piece_index, 0, data.len())?;
+        self.buffer[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        let (start, end) = self.byte_range(piece_index, offset, length as usize)?;
+        Ok(self.buffer[start..end].to_vec())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_pieces_and_read_blocks() {
+        let mut storage = MemoryStorage::new(4, 10);
+        storage.write_piece(2, &[9, 10]).unwrap();
+        storage.write_piece(0, &[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(storage.read_block(0, 1, 2).unwrap(), vec![2, 3]);
+        assert_eq!(storage.content(), &[1, 2, 3, 4, 0, 0, 0, 0, 9, 10]);
+    }
+
+    #[test]
+    fn error_when_writing_past_end() {
+        let mut storage = MemoryStorage::new(4, 10);
+        let err = storage.write_piece(2, &[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
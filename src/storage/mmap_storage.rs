@@ -0,0 +1,294 @@
+use std::{fs::File, io, ops::Range, path::Path};
+
+use memmap2::{MmapMut, MmapOptions};
+
+use super::{FileStorage, Storage, StorageLayout, parts_file::PartsFile};
+
+struct MappedWindow {
+    file_index: usize,
+    start: u64,
+    map: MmapMut,
+}
+
+impl MappedWindow {
+    fn contains(&self, file_index: usize, offset: u64) -> bool {
+        self.file_index == file_index
+            && offset >= self.start
+            && offset < self.start + self.map.len() as u64
+    }
+}
+
+pub struct MmapStorage {
+    layout: StorageLayout,
+    files: Vec<Option<File>>,
+    parts: Option<PartsFile>,
+    windows: Vec<MappedWindow>,
+    window_size: u64,
+}
+
+impl MmapStorage {
+    pub const DEFAULT_WINDOW_SIZE: u64 = 64 * 1024 * 1024;
+    const WINDOW_ALIGNMENT: u64 = 64 * 1024;
+    const MAX_WINDOWS: usize = 8;
+
+    pub fn create(base_dir: impl AsRef<Path>, layout: StorageLayout) -> io::Result<Self> {
+        let base_dir = base_dir.as_ref();
+        let files = layout
+            .files()
+            .iter()
+            .enumerate()
+            .map(|(file_index, entry)| {
+                if !layout.is_stored(file_index) {
+                    return Ok(None);
+                }
+                let file = FileStorage::open_file(&base_dir.join(&entry.path))?;
+                if file.metadata()?.len() < entry.length as u64 {
+                    file.set_len(entry.length as u64)?;
+                }
+                Ok(Some(file))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            parts: PartsFile::for_layout(base_dir, &layout),
+            layout,
+            files,
+            windows: vec![],
+            window_size: Self::DEFAULT_WINDOW_SIZE,
+        })
+    }
+
+    pub fn with_window_size(mut self, window_size: u64) -> Self {
+        self.windows.clear();
+        self.window_size = window_size.max(1).next_multiple_of(Self::WINDOW_ALIGNMENT);
+        self
+    }
+
+    fn window(&mut self, file_index: usize, offset: u64) -> io::Result<&mut MappedWindow> {
+        let position = match self
+            .windows
+            .iter()
+            .position(|window| window.contains(file_index, offset))
+        {
+            Some(position) => position,
+            None => {
+                if self.windows.len() >= Self::MAX_WINDOWS {
+                    self.windows.remove(0).map.flush()?;
+                }
+                let window = self.map_window(file_index, offset)?;
+                self.windows.push(window);
+                self.windows.len() - 1
+            }
+        };
+        let window = self.windows.remove(position);
+        self.windows.push(window);
+        Ok(self.windows.last_mut().unwrap())
+    }
+
+    fn map_window(&self, file_index: usize, offset: u64) -> io::Result<MappedWindow> {
+        let Some(file) = &self.files[file_index] else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("File {} is skipped and cannot be mapped", file_index),
+            ));
+        };
+        let file_length = self.layout.files()[file_index].length as u64;
+        let start = offset - offset % self.window_size;
+        let length = self.window_size.min(file_length - start);
+        // SAFETY: the storage owns the open file and never shrinks it while mapped;
+        // concurrent modification by other processes is outside our control, as with
+        // any plain file write.
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(start)
+                .len(length as usize)
+                .map_mut(file)?
+        };
+        Ok(MappedWindow {
+            file_index,
+            start,
+            map,
+        })
+    }
+
+    fn for_each_chunk(
+        &mut self,
+        start: usize,
+        length: usize,
+        mut on_chunk: impl FnMut(&mut MappedWindow, Range<usize>, Range<usize>),
+    ) -> io::Result<()> {
+        let mut data_offset = 0;
+        for segment in self.layout.segments(start, length) {
+            if self.files[segment.file_index].is_none() {
+                data_offset += segment.length;
+                continue;
+            }
+            let mut file_offset = segment.file_offset;
+            let segment_end = segment.file_offset + segment.length as u64;
+            while file_offset < segment_end {
+                let window = self.window(segment.file_index, file_offset)?;
+                let window_offset = (file_offset - window.start) as usize;
+                let chunk_length =
+                    (segment_end - file_offset).min((window.map.len() - window_offset) as u64);
+                let chunk_length = chunk_length as usize;
+                on_chunk(
+                    window,
+                    window_offset..window_offset + chunk_length,
+                    data_offset..data_offset + chunk_length,
+                );
+                file_offset += chunk_length as u64;
+                data_offset += chunk_length;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for MmapStorage {
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        let piece_start = self.layout.piece_start(piece_index);
+        let mut flush_result = Ok(());
+        self.for_each_chunk(
+            piece_start,
+            data.len(),
+            |window, window_range, data_range| {
+                window.map[window_range.clone()].copy_from_slice(&data[data_range]);
+                if flush_result.is_ok() {
+                    flush_result = window
+                        .map
+                        .flush_range(window_range.start, window_range.len());
+                }
+            },
+        )?;
+        flush_result?;
+        match &mut self.parts {
+            Some(parts) => parts.write_skipped(&self.layout, piece_start, data),
+            None => Ok(()),
+        }
+    }
+
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        let block_start = self.layout.piece_start(piece_index) + offset as usize;
+        let mut block = vec![0; length as usize];
+        self.for_each_chunk(
+            block_start,
+            length as usize,
+            |window, window_range, block_range| {
+                block[block_range].copy_from_slice(&window.map[window_range]);
+            },
+        )?;
+        if let Some(parts) = &mut self.parts {
+            parts.read_skipped(&self.layout, block_start, &mut block)?;
+        }
+        Ok(block)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for window in &self.windows {
+            window.map.flush()?;
+        }
+        match &mut self.parts {
+            Some(parts) => parts.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::torrent::FileEntry;
+
+    use super::*;
+
+    fn make_layout() -> StorageLayout {
+        StorageLayout::new(
+            4,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("first.bin"),
+                    length: 6,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("nested/second.bin"),
+                    length: 4,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn write_pieces_into_mapped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = MmapStorage::create(dir.path(), make_layout()).unwrap();
+
+        storage.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        storage.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        storage.write_piece(2, &[9, 10]).unwrap();
+
+        assert_eq!(
+            fs::read(dir.path().join("first.bin")).unwrap(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            fs::read(dir.path().join("nested/second.bin")).unwrap(),
+            vec![7, 8, 9, 10]
+        );
+    }
+
+    #[test]
+    fn read_block_spanning_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = MmapStorage::create(dir.path(), make_layout()).unwrap();
+
+        storage.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        storage.write_piece(1, &[5, 6, 7, 8]).unwrap();
+
+        assert_eq!(storage.read_block(1, 1, 2).unwrap(), vec![6, 7]);
+    }
+
+    #[test]
+    fn keep_existing_file_content() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("first.bin"), [1, 2, 3, 4, 5, 6]).unwrap();
+
+        let mut storage = MmapStorage::create(dir.path(), make_layout()).unwrap();
+        assert_eq!(storage.read_block(0, 0, 4).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn map_large_file_through_sliding_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_length = 20 * MmapStorage::WINDOW_ALIGNMENT as usize + 100;
+        let piece_length = 48 * 1024;
+        let layout = StorageLayout::new(
+            piece_length,
+            vec![FileEntry {
+                path: PathBuf::from("large.bin"),
+                length: file_length,
+                ..Default::default()
+            }],
+        );
+        let mut storage = MmapStorage::create(dir.path(), layout.clone())
+            .unwrap()
+            .with_window_size(MmapStorage::WINDOW_ALIGNMENT);
+
+        let piece = |index: u32| vec![index as u8 + 1; layout.piece_length(index) as usize];
+        for index in (0..layout.piece_count()).rev() {
+            storage.write_piece(index, &piece(index)).unwrap();
+        }
+        storage.flush().unwrap();
+        assert!(storage.windows.len() <= MmapStorage::MAX_WINDOWS);
+
+        let content = fs::read(dir.path().join("large.bin")).unwrap();
+        assert_eq!(content.len(), file_length);
+        for index in 0..layout.piece_count() {
+            let start = layout.piece_start(index);
+            let length = layout.piece_length(index);
+            assert_eq!(&content[start..start + length as usize], piece(index));
+            assert_eq!(storage.read_block(index, 0, length).unwrap(), piece(index));
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{self, Path, PathBuf},
+};
+
+use tracing::info;
+
+use super::StorageLayout;
+use crate::types::Bitfield;
+
+pub struct PartFiles {
+    final_dir: PathBuf,
+    incomplete_dir: PathBuf,
+    layout: StorageLayout,
+}
+
+impl PartFiles {
+    pub const SUFFIX: &str = ".part";
+
+    pub fn new(final_dir: impl AsRef<Path>, layout: StorageLayout) -> Self {
+        let final_dir = absolute_path(final_dir.as_ref());
+        Self {
+            incomplete_dir: final_dir.clone(),
+            final_dir,
+            layout,
+        }
+    }
+
+    pub fn with_incomplete_dir(mut self, incomplete_dir: impl AsRef<Path>) -> Self {
+        self.incomplete_dir = absolute_path(incomplete_dir.as_ref());
+        self
+    }
+
+    pub fn with_skipped_files(mut self, skipped: Vec<bool>, parts_path: impl AsRef<Path>) -> Self {
+        self.layout = self
+            .layout
+            .with_skipped_files(skipped, absolute_path(parts_path.as_ref()));
+        self
+    }
+
+    pub fn incomplete_dir(&self) -> &Path {
+        &self.incomplete_dir
+    }
+
+    pub fn part_path(&self, file_index: usize) -> PathBuf {
+        let mut path = OsString::from(
+            self.incomplete_dir
+                .join(&self.layout.files()[file_index].path),
+        );
+        path.push(Self::SUFFIX);
+        PathBuf::from(path)
+    }
+
+    pub fn final_path(&self, file_index: usize) -> PathBuf {
+        self.final_dir.join(&self.layout.files()[file_index].path)
+    }
+
+    pub fn is_stored(&self, file_index: usize) -> bool {
+        self.layout.is_stored(file_index)
+    }
+
+    pub fn is_finalized(&self, file_index: usize) -> bool {
+        !self.part_path(file_index).exists()
+            && fs::symlink_metadata(self.final_path(file_index)).is_ok()
+    }
+
+    pub fn has_existing_files(&self) -> bool {
+        (0..self.layout.files().len())
+            .filter(|file_index| self.is_stored(*file_index))
+            .any(|file_index| fs::symlink_metadata(self.location(file_index)).is_ok())
+            || self.layout.parts_path().is_some_and(Path::exists)
+    }
+
+    pub fn location(&self, file_index: usize) -> PathBuf {
+        if self.is_finalized(file_index) {
+            self.final_path(file_index)
+        } else {
+            self.part_path(file_index)
+        }
+    }
+
+    pub fn storage_layout(&self) -> StorageLayout {
+        self.layout
+            .relocate(|file_index, _| self.location(file_index))
+    }
+
+    pub fn finalize_completed(&self, pieces: &Bitfield) -> io::Result<usize> {
+        let is_complete = |file_index| {
+            self.layout
+                .file_pieces(file_index)
+                .all(|piece_index| pieces.has_piece(piece_index))
+        };
+        let all_complete = (0..self.layout.files().len())
+            .filter(|file_index| self.layout.is_stored(*file_index))
+            .all(is_complete);
+
+        let mut finalized = 0;
+        for (file_index, file) in self.layout.files().iter().enumerate() {
+            if file.attributes.padding
+                || self.layout.is_skipped(file_index)
+                || self.is_finalized(file_index)
+                || !is_complete(file_index)
+                || (self.layout.file_pieces(file_index).is_empty() && !all_complete)
+            {
+                continue;
+            }
+            let final_path = self.final_path(file_index);
+            match &file.attributes.symlink {
+                Some(target) => create_symlink(&relative_target(&file.path, target), &final_path)?,
+                None => {
+                    move_file(&self.part_path(file_index), &final_path)?;
+                    if file.attributes.executable {
+                        set_executable(&final_path)?;
+                    }
+                }
+            }
+            info!(path = %final_path.display(), "Finalized downloaded file");
+            finalized += 1;
+        }
+        Ok(finalized)
+    }
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_across_devices(from, to),
+        result => result,
+    }
+}
+
+fn relative_target(link: &Path, target: &Path) -> PathBuf {
+    let link_dir = link.parent().unwrap_or(Path::new(""));
+    let common = link_dir
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in link_dir.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(target.components().skip(common));
+    relative
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    tracing::warn!(link = %link.display(), target = %target.display(), "Symlinks are not supported");
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | (mode & 0o444) >> 2);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn copy_across_devices(from: &Path, to: &Path) -> io::Result<()> {
+    let mut staging = OsString::from(to);
+    staging.push(PartFiles::SUFFIX);
+    let staging = PathBuf::from(staging);
+
+    let mut source = File::open(from)?;
+    let mut target = File::create(&staging)?;
+    io::copy(&mut source, &mut target)?;
+    target.set_modified(source.metadata()?.modified()?)?;
+    target.sync_all()?;
+    fs::rename(&staging, to)?;
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::torrent::{FileAttributes, FileEntry};
+
+    use super::*;
+
+    fn make_layout() -> StorageLayout {
+        StorageLayout::new(
+            4,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("first.bin"),
+                    length: 6,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("nested/second.bin"),
+                    length: 4,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    fn pieces(indices: &[u32]) -> Bitfield {
+        let mut pieces = Bitfield::new(3);
+        for index in indices {
+            pieces.set_piece(*index);
+        }
+        pieces
+    }
+
+    #[test]
+    fn place_incomplete_files_under_part_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let part_files = PartFiles::new(dir.path(), make_layout());
+
+        let layout = part_files.storage_layout();
+        assert_eq!(layout.files()[0].path, dir.path().join("first.bin.part"));
+        assert_eq!(
+            layout.files()[1].path,
+            dir.path().join("nested/second.bin.part")
+        );
+    }
+
+    #[test]
+    fn finalize_files_once_all_their_pieces_are_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let part_files = PartFiles::new(dir.path(), make_layout());
+        fs::write(part_files.part_path(0), [1, 2, 3, 4, 5, 6]).unwrap();
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+        fs::write(part_files.part_path(1), [7, 8, 9, 10]).unwrap();
+
+        assert_eq!(part_files.finalize_completed(&pieces(&[0, 2])).unwrap(), 0);
+        assert_eq!(part_files.finalize_completed(&pieces(&[0, 1])).unwrap(), 1);
+        assert_eq!(
+            fs::read(dir.path().join("first.bin")).unwrap(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert!(!part_files.part_path(0).exists());
+        assert!(!dir.path().join("nested/second.bin").exists());
+
+        assert_eq!(
+            part_files.finalize_completed(&pieces(&[0, 1, 2])).unwrap(),
+            1
+        );
+        assert!(part_files.is_finalized(1));
+        assert_eq!(
+            part_files.storage_layout().files()[0].path,
+            dir.path().join("first.bin")
+        );
+    }
+
+    #[test]
+    fn move_finished_files_out_of_incomplete_dir() {
+        let final_dir = tempfile::tempdir().unwrap();
+        let incomplete_dir = tempfile::tempdir().unwrap();
+        let part_files = PartFiles::new(final_dir.path(), make_layout())
+            .with_incomplete_dir(incomplete_dir.path());
+        fs::write(part_files.part_path(0), [1, 2, 3, 4, 5, 6]).unwrap();
+
+        part_files.finalize_completed(&pieces(&[0, 1])).unwrap();
+        assert_eq!(
+            fs::read(final_dir.path().join("first.bin")).unwrap(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert!(!incomplete_dir.path().join("first.bin.part").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_executable_bit_and_symlinks_on_completion() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = make_layout().files().to_vec();
+        files[1].attributes.executable = true;
+        files.push(FileEntry {
+            path: PathBuf::from("nested/link"),
+            attributes: FileAttributes {
+                symlink: Some(PathBuf::from("first.bin")),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let part_files = PartFiles::new(dir.path(), StorageLayout::new(4, files));
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+        fs::write(part_files.part_path(0), [1, 2, 3, 4, 5, 6]).unwrap();
+        fs::write(part_files.part_path(1), [7, 8, 9, 10]).unwrap();
+
+        assert_eq!(
+            part_files.finalize_completed(&pieces(&[0, 1, 2])).unwrap(),
+            3
+        );
+        let mode = fs::metadata(dir.path().join("nested/second.bin"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o100, 0o100);
+        assert_eq!(
+            fs::read_link(dir.path().join("nested/link")).unwrap(),
+            PathBuf::from("../first.bin")
+        );
+        assert!(part_files.is_finalized(2));
+    }
+
+    #[test]
+    fn defer_files_without_data_until_all_pieces_are_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = make_layout().files().to_vec();
+        files.push(FileEntry {
+            path: PathBuf::from("empty.bin"),
+            length: 0,
+            ..Default::default()
+        });
+        let part_files = PartFiles::new(dir.path(), StorageLayout::new(4, files));
+        fs::write(part_files.part_path(0), [1, 2, 3, 4, 5, 6]).unwrap();
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+        fs::write(part_files.part_path(1), [7, 8, 9, 10]).unwrap();
+        fs::write(part_files.part_path(2), []).unwrap();
+
+        assert_eq!(part_files.finalize_completed(&pieces(&[0, 1])).unwrap(), 1);
+        assert!(!part_files.is_finalized(2));
+
+        assert_eq!(
+            part_files.finalize_completed(&pieces(&[0, 1, 2])).unwrap(),
+            2
+        );
+        assert!(part_files.is_finalized(2));
+    }
+
+    #[test]
+    fn copy_file_across_devices_keeping_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("incomplete.bin.part");
+        let to = dir.path().join("data.bin");
+        fs::write(&from, [1, 2, 3]).unwrap();
+        let mtime = fs::metadata(&from).unwrap().modified().unwrap();
+
+        copy_across_devices(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), vec![1, 2, 3]);
+        assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), mtime);
+        assert!(!from.exists());
+        assert!(!dir.path().join("data.bin.part").exists());
+    }
+}
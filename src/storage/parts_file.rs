@@ -0,0 +1,75 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{FileStorage, StorageLayout};
+
+pub(super) struct PartsFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl PartsFile {
+    pub(super) fn for_layout(base_dir: &Path, layout: &StorageLayout) -> Option<Self> {
+        layout.parts_path().map(|path| Self {
+            path: base_dir.join(path),
+            file: None,
+        })
+    }
+
+    pub(super) fn write_skipped(
+        &mut self,
+        layout: &StorageLayout,
+        start: usize,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut data_offset = 0;
+        for segment in layout.segments(start, data.len()) {
+            if is_parted(layout, segment.file_index) {
+                let file = self.open()?;
+                file.seek(SeekFrom::Start((start + data_offset) as u64))?;
+                file.write_all(&data[data_offset..data_offset + segment.length])?;
+            }
+            data_offset += segment.length;
+        }
+        Ok(())
+    }
+
+    pub(super) fn read_skipped(
+        &mut self,
+        layout: &StorageLayout,
+        start: usize,
+        buffer: &mut [u8],
+    ) -> io::Result<()> {
+        let mut buffer_offset = 0;
+        for segment in layout.segments(start, buffer.len()) {
+            if is_parted(layout, segment.file_index) {
+                let file = self.open()?;
+                file.seek(SeekFrom::Start((start + buffer_offset) as u64))?;
+                file.read_exact(&mut buffer[buffer_offset..buffer_offset + segment.length])?;
+            }
+            buffer_offset += segment.length;
+        }
+        Ok(())
+    }
+
+    pub(super) fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            self.file = Some(FileStorage::open_file(&self.path)?);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+fn is_parted(layout: &StorageLayout, file_index: usize) -> bool {
+    layout.is_skipped(file_index) && layout.files()[file_index].has_data()
+}
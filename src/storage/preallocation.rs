@@ -0,0 +1,207 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use super::{FileStorage, StorageLayout};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Preallocation {
+    #[default]
+    Sparse,
+    Full,
+}
+
+pub fn check_free_space(base_dir: &Path, layout: &StorageLayout) -> io::Result<()> {
+    let mut required = 0;
+    for (file_index, file) in layout.files().iter().enumerate() {
+        if !layout.is_stored(file_index) {
+            continue;
+        }
+        let allocated = fs::metadata(base_dir.join(&file.path)).map_or(0, |m| allocated_bytes(&m));
+        required += (file.length as u64).saturating_sub(allocated);
+    }
+    let Some(available) = available_space(base_dir)? else {
+        return Ok(());
+    };
+    if required > available {
+        let format = humansize::BINARY.decimal_zeroes(2);
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Not enough disk space in {}: {} required, {} available",
+                base_dir.display(),
+                humansize::format_size(required, format),
+                humansize::format_size(available, format)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn preallocate(
+    base_dir: &Path,
+    layout: &StorageLayout,
+    preallocation: Preallocation,
+) -> io::Result<()> {
+    for (file_index, file) in layout.files().iter().enumerate() {
+        if !layout.is_stored(file_index) {
+            continue;
+        }
+        let handle = FileStorage::open_file(&base_dir.join(&file.path))?;
+        let length = file.length as u64;
+        let metadata = handle.metadata()?;
+        match preallocation {
+            Preallocation::Sparse if metadata.len() < length => handle.set_len(length)?,
+            Preallocation::Full if allocated_bytes(&metadata) < length => {
+                allocate(&handle, length)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.blocks() * 512).min(metadata.len())
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+#[cfg(unix)]
+fn allocate(file: &File, length: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let length_off = libc::off_t::try_from(length).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("File length {} is too large to allocate", length),
+        )
+    })?;
+    // SAFETY: the descriptor is owned by `file`, which stays open for the duration
+    // of the call, and the length was checked to fit in `off_t`.
+    let result = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, length_off) };
+    match result {
+        0 => Ok(()),
+        libc::EOPNOTSUPP | libc::EINVAL => file.set_len(length),
+        error => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+#[cfg(not(unix))]
+fn allocate(file: &File, length: u64) -> io::Result<()> {
+    file.set_len(length)
+}
+
+#[cfg(unix)]
+fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stats` points to
+    // writable memory large enough for a `statvfs` struct.
+    if unsafe { libc::statvfs(c_path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `statvfs` returned success, so it has initialized the struct.
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::torrent::FileEntry;
+
+    use super::*;
+
+    fn make_layout(length: usize) -> StorageLayout {
+        StorageLayout::new(
+            4,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("first.bin"),
+                    length: 6,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("nested/second.bin"),
+                    length,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn preallocate_sparse_files_to_their_length() {
+        let dir = tempfile::tempdir().unwrap();
+        preallocate(dir.path(), &make_layout(1 << 20), Preallocation::Sparse).unwrap();
+
+        let metadata = fs::metadata(dir.path().join("nested/second.bin")).unwrap();
+        assert_eq!(metadata.len(), 1 << 20);
+        assert_eq!(fs::metadata(dir.path().join("first.bin")).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn preallocate_full_files_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        preallocate(dir.path(), &make_layout(1 << 20), Preallocation::Full).unwrap();
+
+        let metadata = fs::metadata(dir.path().join("nested/second.bin")).unwrap();
+        assert_eq!(metadata.len(), 1 << 20);
+        assert_eq!(allocated_bytes(&metadata), 1 << 20);
+    }
+
+    #[test]
+    fn keep_existing_file_content_when_preallocating() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("first.bin"), [1, 2, 3]).unwrap();
+
+        preallocate(dir.path(), &make_layout(4), Preallocation::Full).unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("first.bin")).unwrap(),
+            vec![1, 2, 3, 0, 0, 0]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reject_allocation_beyond_maximum_file_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = FileStorage::open_file(&dir.path().join("huge.bin")).unwrap();
+        let error = allocate(&file, u64::MAX).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn accept_torrent_that_fits_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        check_free_space(&dir.path().join("not/created/yet"), &make_layout(4)).unwrap();
+    }
+
+    #[test]
+    fn reject_torrent_larger_than_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = check_free_space(dir.path(), &make_layout(usize::MAX / 2)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+        assert!(error.to_string().contains("Not enough disk space"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use super::{Storage, StorageLayout};
+
+pub struct WriterSink<W: Write> {
+    writer: W,
+    layout: StorageLayout,
+    next_piece: u32,
+    buffered: BTreeMap<u32, Vec<u8>>,
+    buffered_bytes: usize,
+    buffer_capacity: usize,
+    spill: Option<SpillFile>,
+    spill_capacity: u64,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024 * 1024;
+    pub const DEFAULT_SPILL_CAPACITY: u64 = 4 * 1024 * 1024 * 1024;
+
+    pub fn new(writer: W, layout: StorageLayout) -> Self {
+        Self {
+            writer,
+            layout,
+            next_piece: 0,
+            buffered: BTreeMap::new(),
+            buffered_bytes: 0,
+            buffer_capacity: Self::DEFAULT_BUFFER_CAPACITY,
+            spill: None,
+            spill_capacity: Self::DEFAULT_SPILL_CAPACITY,
+        }
+    }
+
+    pub fn with_buffer_capacity(mut self, buffer_capacity: usize) -> Self {
+        self.buffer_capacity = buffer_capacity;
+        self
+    }
+
+    pub fn with_spill_capacity(mut self, spill_capacity: u64) -> Self {
+        self.spill_capacity = spill_capacity;
+        self
+    }
+
+    pub fn emitted_pieces(&self) -> u32 {
+        self.next_piece
+    }
+
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn is_held(&self, piece_index: u32) -> bool {
+        self.buffered.contains_key(&piece_index)
+            || self
+                .spill
+                .as_ref()
+                .is_some_and(|spill| spill.pieces.contains(&piece_index))
+    }
+
+    fn hold(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        if self.buffered_bytes + data.len() <= self.buffer_capacity {
+            self.buffered.insert(piece_index, data.to_vec());
+            self.buffered_bytes += data.len();
+            return Ok(());
+        }
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::create()?),
+        };
+        if spill.bytes + data.len() as u64 > self.spill_capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "Out-of-order pieces exceed the spill limit of {} bytes",
+                    self.spill_capacity
+                ),
+            ));
+        }
+        spill.write(self.layout.piece_start(piece_index) as u64, data)?;
+        spill.pieces.insert(piece_index);
+        spill.bytes += data.len() as u64;
+        Ok(())
+    }
+
+    fn take_held(&mut self, piece_index: u32) -> io::Result<Option<Vec<u8>>> {
+        if let Some(data) = self.buffered.remove(&piece_index) {
+            self.buffered_bytes -= data.len();
+            return Ok(Some(data));
+        }
+        let Some(spill) = &mut self.spill else {
+            return Ok(None);
+        };
+        if !spill.pieces.remove(&piece_index) {
+            return Ok(None);
+        }
+        let mut data = vec![0; self.layout.piece_length(piece_index) as usize];
+        spill.read(self.layout.piece_start(piece_index) as u64, &mut data)?;
+        spill.bytes -= data.len() as u64;
+        Ok(Some(data))
+    }
+
+    fn emit(&mut self, data: &[u8]) -> io::Result<()> {
+        let piece_start = self.layout.piece_start(self.next_piece);
+        let mut position = 0;
+        for segment in self.layout.segments(piece_start, data.len()) {
+            if !self.layout.files()[segment.file_index].attributes.padding {
+                self.writer
+                    .write_all(&data[position..position + segment.length])?;
+            }
+            position += segment.length;
+        }
+        self.next_piece += 1;
+        Ok(())
+    }
+
+    fn emit_contiguous(&mut self) -> io::Result<()> {
+        while let Some(data) = self.take_held(self.next_piece)? {
+            self.emit(&data)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Storage for WriterSink<W> {
+    fn write_piece(&mut self, piece_index: u32, data: &[u8]) -> io::Result<()> {
+        if piece_index < self.next_piece || self.is_held(piece_index) {
+            return Ok(());
+        }
+        if piece_index != self.next_piece {
+            return self.hold(piece_index, data);
+        }
+        self.emit(data)?;
+        self.emit_contiguous()
+    }
+
+    fn read_block(&mut self, piece_index: u32, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        let start = offset as usize;
+        let end = start + length as usize;
+        if let Some(data) = self.buffered.get(&piece_index) {
+            return data.get(start..end).map(<[u8]>::to_vec).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Block is out of piece bounds: {}..{}", start, end),
+                )
+            });
+        }
+        match &mut self.spill {
+            Some(spill) if spill.pieces.contains(&piece_index) => {
+                let mut block = vec![0; length as usize];
+                spill.read(
+                    (self.layout.piece_start(piece_index) + start) as u64,
+                    &mut block,
+                )?;
+                Ok(block)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Piece {} is no longer held by the output sink", piece_index),
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+struct SpillFile {
+    file: File,
+    pieces: BTreeSet<u32>,
+    bytes: u64,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        Ok(Self {
+            file: tempfile::tempfile()?,
+            pieces: BTreeSet::new(),
+            bytes: 0,
+        })
+    }
+
+    fn write(&mut self, position: u64, data: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.write_all(data)
+    }
+
+    fn read(&mut self, position: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.read_exact(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::torrent::{FileAttributes, FileEntry};
+
+    use super::*;
+
+    fn make_sink(buffer_capacity: usize) -> WriterSink<Vec<u8>> {
+        let files = vec![FileEntry {
+            path: PathBuf::from("file"),
+            length: 10,
+            ..Default::default()
+        }];
+        WriterSink::new(vec![], StorageLayout::new(4, files)).with_buffer_capacity(buffer_capacity)
+    }
+
+    #[test]
+    fn write_pieces_in_order_as_prefix_becomes_contiguous() {
+        let mut sink = make_sink(1024);
+        sink.write_piece(2, &[9, 10]).unwrap();
+        sink.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        assert_eq!(sink.emitted_pieces(), 0);
+        assert_eq!(sink.buffered_bytes(), 6);
+
+        sink.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(sink.emitted_pieces(), 3);
+        assert_eq!(sink.buffered_bytes(), 0);
+        assert_eq!(sink.into_inner(), (1..=10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn spill_pieces_to_temp_file_when_buffer_is_full() {
+        let mut sink = make_sink(2);
+        sink.write_piece(2, &[9, 10]).unwrap();
+        sink.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        assert_eq!(sink.buffered_bytes(), 2);
+        assert_eq!(sink.read_block(1, 1, 2).unwrap(), vec![6, 7]);
+
+        sink.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(sink.into_inner(), (1..=10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn fail_when_spilled_pieces_exceed_spill_capacity() {
+        let mut sink = make_sink(0).with_spill_capacity(3);
+        sink.write_piece(2, &[9, 10]).unwrap();
+        let err = sink.write_piece(1, &[5, 6, 7, 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+    }
+
+    #[test]
+    fn skip_padding_files_in_output() {
+        let file = |name: &str, length: usize, padding: bool| FileEntry {
+            path: PathBuf::from(name),
+            length,
+            attributes: FileAttributes {
+                padding,
+                ..Default::default()
+            },
+        };
+        let files = vec![
+            file("a", 3, false),
+            file("pad", 1, true),
+            file("b", 6, false),
+        ];
+        let mut sink = WriterSink::new(vec![], StorageLayout::new(4, files));
+        sink.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        sink.write_piece(0, &[1, 2, 3, 0]).unwrap();
+        sink.write_piece(2, &[9, 10]).unwrap();
+        assert_eq!(sink.into_inner(), vec![1, 2, 3, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn ignore_pieces_that_were_already_written() {
+        let mut sink = make_sink(1024);
+        sink.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        sink.write_piece(0, &[0, 0, 0, 0]).unwrap();
+        sink.write_piece(2, &[9, 10]).unwrap();
+        sink.write_piece(2, &[0, 0]).unwrap();
+        sink.write_piece(1, &[5, 6, 7, 8]).unwrap();
+        assert_eq!(sink.into_inner(), (1..=10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn cannot_read_pieces_already_written_to_output() {
+        let mut sink = make_sink(1024);
+        sink.write_piece(0, &[1, 2, 3, 4]).unwrap();
+        let err = sink.read_block(0, 0, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
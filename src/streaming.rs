@@ -0,0 +1,175 @@
+use std::{
+    io,
+    ops::Range,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::{storage::StorageLayout, types::Bitfield};
+
+mod http_server;
+
+pub use http_server::{RunningStreamServer, StreamServer};
+
+struct StreamState {
+    verified: Bitfield,
+    focus: Option<u32>,
+    closed: bool,
+}
+
+#[derive(Clone)]
+pub struct StreamingHandle {
+    state: Arc<(Mutex<StreamState>, Condvar)>,
+    piece_count: u32,
+    edge_pieces: Arc<[u32]>,
+}
+
+impl StreamingHandle {
+    const READAHEAD_PIECES: u32 = 8;
+    const FOCUS_REFRESH: Duration = Duration::from_secs(1);
+
+    pub fn new(layout: &StorageLayout) -> Self {
+        let mut edge_pieces = vec![];
+        for file_index in 0..layout.files().len() {
+            let pieces = layout.file_pieces(file_index);
+            if layout.files()[file_index].has_data() && !pieces.is_empty() {
+                edge_pieces.push(pieces.start);
+                edge_pieces.push(pieces.end - 1);
+            }
+        }
+        edge_pieces.dedup();
+
+        Self {
+            state: Arc::new((
+                Mutex::new(StreamState {
+                    verified: Bitfield::new(layout.piece_count() as usize),
+                    focus: None,
+                    closed: false,
+                }),
+                Condvar::new(),
+            )),
+            piece_count: layout.piece_count(),
+            edge_pieces: edge_pieces.into(),
+        }
+    }
+
+    pub fn focus(&self) -> Option<u32> {
+        self.state.0.lock().unwrap().focus
+    }
+
+    pub fn is_verified(&self, piece_index: u32) -> bool {
+        self.state.0.lock().unwrap().verified.has_piece(piece_index)
+    }
+
+    pub fn wait_for_pieces(&self, pieces: Range<u32>) -> io::Result<()> {
+        let (state, verified) = &*self.state;
+        let mut state = state.lock().unwrap();
+        loop {
+            let Some(missing) = pieces
+                .clone()
+                .find(|index| !state.verified.has_piece(*index))
+            else {
+                return Ok(());
+            };
+            if state.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Download stopped before the requested pieces arrived",
+                ));
+            }
+            state.focus = Some(missing);
+            state = verified.wait_timeout(state, Self::FOCUS_REFRESH).unwrap().0;
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.0.lock().unwrap().closed
+    }
+
+    pub fn close(&self) {
+        let (state, verified) = &*self.state;
+        state.lock().unwrap().closed = true;
+        verified.notify_all();
+    }
+
+    pub(crate) fn pieces_verified(&self, pieces: &Bitfield) {
+        let (state, verified) = &*self.state;
+        let mut state = state.lock().unwrap();
+        for index in (0..self.piece_count).filter(|index| pieces.has_piece(*index)) {
+            state.verified.set_piece(index);
+        }
+        verified.notify_all();
+    }
+
+    pub(crate) fn piece_verified(&self, piece_index: u32) {
+        let (state, verified) = &*self.state;
+        state.lock().unwrap().verified.set_piece(piece_index);
+        verified.notify_all();
+    }
+
+    pub(crate) fn priority_pieces(&self, focus: Option<u32>) -> Vec<u32> {
+        let readahead = focus.map_or(0..0, |focus| {
+            focus..(focus + Self::READAHEAD_PIECES).min(self.piece_count)
+        });
+        readahead.chain(self.edge_pieces.iter().copied()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, thread};
+
+    use crate::torrent::FileEntry;
+
+    use super::*;
+
+    fn make_handle() -> StreamingHandle {
+        let files = [25, 40, 15]
+            .iter()
+            .enumerate()
+            .map(|(index, length)| FileEntry {
+                path: PathBuf::from(format!("file-{index}")),
+                length: *length,
+                ..Default::default()
+            })
+            .collect();
+        StreamingHandle::new(&StorageLayout::new(10, files))
+    }
+
+    #[test]
+    fn prioritise_readahead_then_first_and_last_pieces_of_files() {
+        let handle = make_handle();
+        assert_eq!(handle.priority_pieces(None), vec![0, 2, 6, 7]);
+        assert_eq!(
+            handle.priority_pieces(Some(3)),
+            vec![3, 4, 5, 6, 7, 0, 2, 6, 7]
+        );
+    }
+
+    #[test]
+    fn wait_until_pieces_are_verified() {
+        let handle = make_handle();
+        handle.piece_verified(1);
+
+        let waiter = handle.clone();
+        let reader = thread::spawn(move || waiter.wait_for_pieces(1..3));
+        while handle.focus().is_none() {
+            thread::yield_now();
+        }
+        assert_eq!(handle.focus(), Some(2));
+
+        handle.piece_verified(2);
+        reader.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn stop_waiting_when_stream_is_closed() {
+        let handle = make_handle();
+        let waiter = handle.clone();
+        let reader = thread::spawn(move || waiter.wait_for_pieces(0..1));
+
+        handle.close();
+        let error = reader.join().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
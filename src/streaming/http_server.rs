@@ -0,0 +1,552 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::{debug, warn};
+
+use super::StreamingHandle;
+use crate::storage::{DiskReader, StorageLayout};
+
+struct StreamContext {
+    layout: StorageLayout,
+    handle: StreamingHandle,
+    reader: DiskReader,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+pub struct StreamServer {
+    listener: TcpListener,
+    context: Arc<StreamContext>,
+}
+
+pub struct RunningStreamServer {
+    address: SocketAddr,
+    handle: StreamingHandle,
+    acceptor: JoinHandle<()>,
+}
+
+impl StreamServer {
+    const MAX_HEADER_LINES: usize = 64;
+    const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn bind(
+        address: SocketAddr,
+        layout: StorageLayout,
+        handle: StreamingHandle,
+        reader: DiskReader,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            context: Arc::new(StreamContext {
+                layout,
+                handle,
+                reader,
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn spawn(self) -> io::Result<RunningStreamServer> {
+        let address = self.local_addr()?;
+        let handle = self.context.handle.clone();
+        let acceptor = thread::spawn(move || self.accept_connections());
+        Ok(RunningStreamServer {
+            address,
+            handle,
+            acceptor,
+        })
+    }
+
+    fn accept_connections(self) {
+        let mut connections: Vec<JoinHandle<()>> = vec![];
+        for stream in self.listener.incoming() {
+            if self.context.handle.is_closed() {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(%e, "Failed to accept streaming connection");
+                    continue;
+                }
+            };
+            let context = self.context.clone();
+            connections.retain(|connection| !connection.is_finished());
+            connections.push(thread::spawn(move || {
+                if let Err(e) = serve_connection(stream, &context) {
+                    debug!(%e, "Streaming connection closed");
+                }
+            }));
+        }
+        for connection in connections {
+            let _ = connection.join();
+        }
+    }
+}
+
+impl RunningStreamServer {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn stop(self) {
+        self.handle.close();
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect(address);
+        let _ = self.acceptor.join();
+    }
+}
+
+fn serve_connection(stream: TcpStream, context: &StreamContext) -> io::Result<()> {
+    stream.set_read_timeout(Some(StreamServer::CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(StreamServer::CONNECTION_TIMEOUT))?;
+    let request = read_request(&mut BufReader::new(&stream))?;
+    let mut stream = stream;
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_status(&mut stream, "405 Method Not Allowed");
+    }
+    let with_body = request.method == "GET";
+
+    if request.path == "/" {
+        let index = file_index(&context.layout);
+        write_head(
+            &mut stream,
+            "200 OK",
+            &[
+                ("Content-Type", "text/html; charset=utf-8".to_string()),
+                ("Content-Length", index.len().to_string()),
+            ],
+        )?;
+        if with_body {
+            stream.write_all(index.as_bytes())?;
+        }
+        return Ok(());
+    }
+
+    let Some(file_index) = find_file(&context.layout, &request.path) else {
+        return write_status(&mut stream, "404 Not Found");
+    };
+    let file = &context.layout.files()[file_index];
+    let file_length = file.length as u64;
+    let content_type = ("Content-Type", content_type(&file.path).to_string());
+    let (status, range, mut headers) = match request
+        .range
+        .as_deref()
+        .map(|range| parse_range(range, file_length))
+    {
+        Some(Ok(range)) => (
+            "206 Partial Content",
+            range.clone(),
+            vec![(
+                "Content-Range",
+                format!("bytes {}-{}/{}", range.start, range.end - 1, file_length),
+            )],
+        ),
+        Some(Err(RangeError::Unsatisfiable)) => {
+            return write_head(
+                &mut stream,
+                "416 Range Not Satisfiable",
+                &[
+                    ("Content-Range", format!("bytes */{}", file_length)),
+                    ("Content-Length", "0".to_string()),
+                ],
+            );
+        }
+        Some(Err(RangeError::Malformed)) | None => ("200 OK", 0..file_length, vec![]),
+    };
+    headers.extend([
+        content_type,
+        ("Accept-Ranges", "bytes".to_string()),
+        ("Content-Length", (range.end - range.start).to_string()),
+    ]);
+    write_head(&mut stream, status, &headers)?;
+    if with_body {
+        let file_start = context.layout.file_start(file_index) as u64;
+        write_body(
+            &mut stream,
+            context,
+            file_start + range.start..file_start + range.end,
+        )?;
+    }
+    Ok(())
+}
+
+fn write_body(
+    stream: &mut TcpStream,
+    context: &StreamContext,
+    bytes: Range<u64>,
+) -> io::Result<()> {
+    let layout = &context.layout;
+    let mut position = bytes.start as usize;
+    while position < bytes.end as usize {
+        let piece_index = layout.piece_at(position);
+        let piece_offset = position - layout.piece_start(piece_index);
+        let length = (layout.piece_length(piece_index) as usize - piece_offset)
+            .min(bytes.end as usize - position);
+
+        context
+            .handle
+            .wait_for_pieces(piece_index..piece_index + 1)?;
+        let block = context
+            .reader
+            .read_block(piece_index, piece_offset as u32, length as u32)?;
+        stream.write_all(&block)?;
+        position += length;
+    }
+    Ok(())
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed request line: {}", request_line.trim_end()),
+        ));
+    };
+
+    let mut range = None;
+    for _ in 0..StreamServer::MAX_HEADER_LINES {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("range")
+        {
+            range = Some(value.trim().to_string());
+        }
+    }
+
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    Ok(Request {
+        method: method.to_string(),
+        path: percent_decode(path)?,
+        range,
+    })
+}
+
+fn write_head(stream: &mut TcpStream, status: &str, headers: &[(&str, String)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())
+}
+
+fn write_status(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    write_head(stream, status, &[("Content-Length", "0".to_string())])
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeError {
+    Malformed,
+    Unsatisfiable,
+}
+
+fn parse_range(header: &str, length: u64) -> Result<Range<u64>, RangeError> {
+    let spec = header
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .ok_or(RangeError::Malformed)?;
+    let (start, end) = spec.split_once('-').ok_or(RangeError::Malformed)?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| RangeError::Malformed)
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Err(RangeError::Malformed),
+        ("", suffix) => length.saturating_sub(parse(suffix)?)..length,
+        (start, "") => parse(start)?..length,
+        (start, end) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end < start {
+                return Err(RangeError::Malformed);
+            }
+            start..end.saturating_add(1).min(length)
+        }
+    };
+    if range.start >= length || range.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+    Ok(range)
+}
+
+fn find_file(layout: &StorageLayout, request_path: &str) -> Option<usize> {
+    let request_path = request_path.trim_start_matches('/');
+    layout
+        .files()
+        .iter()
+        .position(|file| file.has_data() && url_path(&file.path) == request_path)
+}
+
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn file_index(layout: &StorageLayout) -> String {
+    let mut index = String::from("<!DOCTYPE html>\n<html><body><ul>\n");
+    for file in layout.files().iter().filter(|file| file.has_data()) {
+        let path = url_path(&file.path);
+        index.push_str(&format!(
+            "<li><a href=\"/{}\">{}</a> ({} bytes)</li>\n",
+            percent_encode(&path),
+            html_escape(&path),
+            file.length
+        ));
+    }
+    index.push_str("</ul></body></html>\n");
+    index
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("mp4" | "m4v") => "video/mp4",
+        Some("mkv") => "video/x-matroska",
+        Some("webm") => "video/webm",
+        Some("avi") => "video/x-msvideo",
+        Some("mp3") => "audio/mpeg",
+        Some("flac") => "audio/flac",
+        Some("txt" | "log") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        Some("iso") => "application/x-iso9660-image",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(value: &str) -> io::Result<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'%'
+            && let Some(byte) = value
+                .get(position + 1..position + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            position += 3;
+        } else {
+            decoded.push(bytes[position]);
+            position += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, path::PathBuf};
+
+    use crate::{
+        storage::{DiskIo, MemoryStorage, Storage},
+        torrent::FileEntry,
+    };
+
+    use super::*;
+
+    fn make_layout() -> StorageLayout {
+        StorageLayout::new(
+            4,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("intro.txt"),
+                    length: 6,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("movies/big movie.mp4"),
+                    length: 10,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    fn start_server(disk_io: &DiskIo, handle: &StreamingHandle) -> SocketAddr {
+        spawn_server(disk_io, handle).local_addr()
+    }
+
+    fn spawn_server(disk_io: &DiskIo, handle: &StreamingHandle) -> RunningStreamServer {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server =
+            StreamServer::bind(address, make_layout(), handle.clone(), disk_io.reader()).unwrap();
+        server.spawn().unwrap()
+    }
+
+    fn get(address: SocketAddr, path: &str, headers: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            path, headers
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn downloaded_disk_io() -> (DiskIo, StreamingHandle) {
+        let layout = make_layout();
+        let mut disk_io = DiskIo::new(Box::new(MemoryStorage::new(4, 16)), layout.clone());
+        let handle = StreamingHandle::new(&layout);
+        for (index, piece) in b"abcdefghijklmnop".chunks(4).enumerate() {
+            disk_io.write_piece(index as u32, piece).unwrap();
+            handle.piece_verified(index as u32);
+        }
+        (disk_io, handle)
+    }
+
+    #[test]
+    fn serve_whole_file() {
+        let (disk_io, handle) = downloaded_disk_io();
+        let response = get(start_server(&disk_io, &handle), "/intro.txt", "");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.contains("Accept-Ranges: bytes\r\n"));
+        assert!(response.ends_with("\r\n\r\nabcdef"));
+    }
+
+    #[test]
+    fn serve_requested_byte_range() {
+        let (disk_io, handle) = downloaded_disk_io();
+        let response = get(
+            start_server(&disk_io, &handle),
+            "/movies/big%20movie.mp4",
+            "Range: bytes=1-8\r\n",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 1-8/10\r\n"));
+        assert!(response.contains("Content-Type: video/mp4\r\n"));
+        assert!(response.ends_with("\r\n\r\nhijklmno"));
+    }
+
+    #[test]
+    fn reject_unsatisfiable_range_and_unknown_files() {
+        let (disk_io, handle) = downloaded_disk_io();
+        let address = start_server(&disk_io, &handle);
+
+        let response = get(address, "/intro.txt", "Range: bytes=6-\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("Content-Range: bytes */6\r\n"));
+
+        let response = get(address, "/missing.txt", "");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn list_files_in_index() {
+        let (disk_io, handle) = downloaded_disk_io();
+        let response = get(start_server(&disk_io, &handle), "/", "");
+
+        assert!(response.contains("<a href=\"/intro.txt\">intro.txt</a>"));
+        assert!(response.contains("<a href=\"/movies/big%20movie.mp4\">movies/big movie.mp4</a>"));
+    }
+
+    #[test]
+    fn block_until_requested_pieces_are_verified() {
+        let layout = make_layout();
+        let mut disk_io = DiskIo::new(Box::new(MemoryStorage::new(4, 16)), layout.clone());
+        let handle = StreamingHandle::new(&layout);
+        let address = start_server(&disk_io, &handle);
+
+        let response = thread::spawn(move || get(address, "/intro.txt", "Range: bytes=4-5\r\n"));
+        while handle.focus().is_none() {
+            thread::yield_now();
+        }
+        assert_eq!(handle.focus(), Some(1));
+
+        disk_io.write_piece(1, b"efgh").unwrap();
+        handle.piece_verified(1);
+        assert!(response.join().unwrap().ends_with("\r\n\r\nef"));
+    }
+
+    #[test]
+    fn stop_serving_and_release_waiting_connections() {
+        let layout = make_layout();
+        let disk_io = DiskIo::new(Box::new(MemoryStorage::new(4, 16)), layout.clone());
+        let handle = StreamingHandle::new(&layout);
+        let server = spawn_server(&disk_io, &handle);
+        let address = server.local_addr();
+
+        let response = thread::spawn(move || get(address, "/intro.txt", ""));
+        while handle.focus().is_none() {
+            thread::yield_now();
+        }
+        server.stop();
+
+        assert!(response.join().unwrap().ends_with("\r\n\r\n"));
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-3", 10), Ok(0..4));
+        assert_eq!(parse_range("bytes=5-", 10), Ok(5..10));
+        assert_eq!(parse_range("bytes=-3", 10), Ok(7..10));
+        assert_eq!(parse_range("bytes=8-20", 10), Ok(8..10));
+        assert_eq!(parse_range("bytes=10-", 10), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), Err(RangeError::Malformed));
+        assert_eq!(parse_range("items=0-1", 10), Err(RangeError::Malformed));
+    }
+
+    #[test]
+    fn clamp_range_ending_at_maximum_offset() {
+        assert_eq!(parse_range("bytes=0-18446744073709551615", 10), Ok(0..10));
+    }
+}
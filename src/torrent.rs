@@ -1,17 +1,43 @@
 use crate::types::Sha1;
-use serde::{Deserialize, Serialize};
-use std::fs;
+use serde::Deserialize;
+use serde_bencode::value::Value;
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Deserialize)]
-#[serde(try_from = "InfoInternal")]
+#[serde(try_from = "Value")]
 pub struct Info {
     pub sha1: Sha1,
     pub name: String,
     pub piece_length: u32,
     pub length: usize,
     pub pieces: Vec<Sha1>,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub length: usize,
+    pub attributes: FileAttributes,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub padding: bool,
+    pub executable: bool,
+    pub hidden: bool,
+    pub symlink: Option<PathBuf>,
+}
+
+impl FileEntry {
+    pub fn has_data(&self) -> bool {
+        !self.attributes.padding && self.attributes.symlink.is_none()
+    }
 }
 
 #[derive(Deserialize)]
@@ -34,21 +60,98 @@ impl Torrent {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize)]
 struct InfoInternal {
     pub name: String,
     #[serde(rename = "piece length")]
     pub piece_length: u32,
-    pub length: usize,
+    #[serde(default)]
+    pub length: Option<usize>,
+    #[serde(default)]
+    pub files: Option<Vec<FileInternal>>,
+    #[serde(default)]
+    pub attr: Option<String>,
     #[serde(with = "serde_bytes")]
     pub pieces: Vec<u8>,
 }
 
-impl TryFrom<InfoInternal> for Info {
+#[derive(Deserialize)]
+struct FileInternal {
+    pub length: usize,
+    pub path: Vec<String>,
+    #[serde(default)]
+    pub attr: Option<String>,
+    #[serde(rename = "symlink path", default)]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl InfoInternal {
+    fn file_entries(&self) -> Result<Vec<FileEntry>, Error> {
+        match (&self.length, &self.files) {
+            (Some(length), None) => Ok(vec![FileEntry {
+                path: self.file_path(&[])?,
+                length: *length,
+                attributes: file_attributes(self.attr.as_deref(), None)?,
+            }]),
+            (None, Some(files)) => files
+                .iter()
+                .map(|file| {
+                    let symlink = file
+                        .symlink_path
+                        .as_ref()
+                        .map(|target| self.file_path(target))
+                        .transpose()?;
+                    Ok(FileEntry {
+                        path: self.file_path(&file.path)?,
+                        length: file.length,
+                        attributes: file_attributes(file.attr.as_deref(), symlink)?,
+                    })
+                })
+                .collect(),
+            _ => Err("Torrent info must contain either `length` or `files`".into()),
+        }
+    }
+
+    fn file_path(&self, components: &[String]) -> Result<PathBuf, Error> {
+        let mut path = PathBuf::new();
+        for component in std::iter::once(&self.name).chain(components) {
+            if !is_plain_component(component) {
+                return Err(format!("Invalid file path component: {:?}", component).into());
+            }
+            path.push(component);
+        }
+        Ok(path)
+    }
+}
+
+fn is_plain_component(component: &str) -> bool {
+    let mut components = Path::new(component).components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !component.contains(['/', '\\'])
+}
+
+fn file_attributes(attr: Option<&str>, symlink: Option<PathBuf>) -> Result<FileAttributes, Error> {
+    let attr = attr.unwrap_or_default();
+    if attr.contains('l') != symlink.is_some() {
+        return Err("Symlink attribute requires `symlink path` and vice versa".into());
+    }
+    Ok(FileAttributes {
+        padding: attr.contains('p'),
+        executable: attr.contains('x'),
+        hidden: attr.contains('h'),
+        symlink,
+    })
+}
+
+impl TryFrom<Value> for Info {
     type Error = Error;
 
-    fn try_from(info_internal: InfoInternal) -> Result<Info, Self::Error> {
-        let sha1 = Sha1::calculate(&serde_bencode::to_bytes(&info_internal)?);
+    fn try_from(value: Value) -> Result<Info, Self::Error> {
+        let info_bytes = serde_bencode::to_bytes(&value)?;
+        let sha1 = Sha1::calculate(&info_bytes);
+        let info_internal: InfoInternal = serde_bencode::from_bytes(&info_bytes)?;
+        let files = info_internal.file_entries()?;
         let pieces = info_internal
             .pieces
             .chunks_exact(20)
@@ -58,8 +161,9 @@ impl TryFrom<InfoInternal> for Info {
         Ok(Self {
             name: info_internal.name,
             piece_length: info_internal.piece_length,
-            length: info_internal.length,
+            length: files.iter().map(|file| file.length).sum(),
             pieces,
+            files,
             sha1,
         })
     }
@@ -89,9 +193,158 @@ mod tests {
         );
     }
 
+    #[test]
+    fn single_file_torrent_has_one_file_entry() {
+        let torrent = Torrent::read_default_file().unwrap();
+        assert_eq!(
+            torrent.info.files,
+            vec![FileEntry {
+                path: PathBuf::from("debian-12.11.0-amd64-netinst.iso"),
+                length: 702545920,
+                ..Default::default()
+            }]
+        );
+    }
+
     #[test]
     fn calculate_info_hash() {
         let torrent = Torrent::read_default_file().unwrap();
         assert_eq!(format!("{}", torrent.info.sha1), INFO_HASH);
     }
+
+    #[test]
+    fn deserialize_multi_file_torrent() {
+        let info = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl5:a.txteed6:lengthi7e4:pathl3:dir5:b.txteee\
+             4:name7:dataset12:piece lengthi4e6:pieces60:",
+            &[0; 60],
+        )
+        .unwrap();
+
+        assert_eq!(info.length, 12);
+        assert_eq!(
+            info.files,
+            vec![
+                FileEntry {
+                    path: PathBuf::from("dataset/a.txt"),
+                    length: 5,
+                    ..Default::default()
+                },
+                FileEntry {
+                    path: PathBuf::from("dataset/dir/b.txt"),
+                    length: 7,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_file_attributes() {
+        let info = deserialize_info(
+            "d5:filesld4:attr1:x6:lengthi3e4:pathl3:runeed4:attr1:p6:lengthi1e4:pathl4:.pad1:1ee\
+             d4:attr2:lh6:lengthi0e4:pathl4:linke12:symlink pathl3:runeee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        )
+        .unwrap();
+
+        assert_eq!(info.length, 4);
+        assert!(info.files[0].attributes.executable);
+        assert!(info.files[1].attributes.padding);
+        assert!(!info.files[1].has_data());
+        assert!(info.files[2].attributes.hidden);
+        assert_eq!(
+            info.files[2].attributes.symlink,
+            Some(PathBuf::from("dataset/run"))
+        );
+    }
+
+    #[test]
+    fn keep_attributes_in_info_hash() {
+        let with_attr = deserialize_info(
+            "d5:filesld4:attr1:x6:lengthi5e4:pathl5:a.txteee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        )
+        .unwrap();
+        let without_attr = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl5:a.txteee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        )
+        .unwrap();
+        assert_ne!(with_attr.sha1, without_attr.sha1);
+    }
+
+    #[test]
+    fn hash_info_keys_that_are_not_modelled() {
+        let prefix = "d6:lengthi5e6:md5sum32:00000000000000000000000000000000\
+                      4:name8:data.bin12:piece lengthi4e6:pieces20:";
+        let suffix = "7:privatei1e6:source4:teste";
+        let mut info_bytes = prefix.as_bytes().to_vec();
+        info_bytes.extend_from_slice(&[0; 20]);
+        info_bytes.extend_from_slice(suffix.as_bytes());
+
+        let info: Info = serde_bencode::from_bytes(&info_bytes).unwrap();
+        assert_eq!(info.sha1, Sha1::calculate(&info_bytes));
+    }
+
+    #[test]
+    fn reject_file_path_escaping_torrent_directory() {
+        let result = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl2:..5:a.txteee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_absolute_file_path_component() {
+        let result = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl11:/etc/passwdeee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_file_path_component_with_separator() {
+        let result = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl9:a/../../beee\
+             4:name7:dataset12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_torrent_name_escaping_target_directory() {
+        let single_file = deserialize_info(
+            "d6:lengthi5e4:name11:../evil.bin12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        );
+        assert!(single_file.is_err());
+        let multi_file = deserialize_info(
+            "d5:filesld6:lengthi5e4:pathl5:a.txteee\
+             4:name4:/tmp12:piece lengthi4e6:pieces20:",
+            &[0; 20],
+        );
+        assert!(multi_file.is_err());
+    }
+
+    #[test]
+    fn reject_info_without_length_or_files() {
+        let result = deserialize_info("d4:name7:dataset12:piece lengthi4e6:pieces20:", &[0; 20]);
+        assert!(result.is_err());
+    }
+
+    fn deserialize_info(prefix: &str, pieces: &[u8]) -> Result<Info, Error> {
+        let mut bytes = prefix.as_bytes().to_vec();
+        bytes.extend_from_slice(pieces);
+        bytes.push(b'e');
+        Ok(serde_bencode::from_bytes(&bytes)?)
+    }
 }
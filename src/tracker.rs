@@ -1,16 +1,48 @@
 use crate::result::{Result, StdResult};
 use crate::types::{PeerId, Sha1};
 use serde::Deserialize;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
 use url::{ParseError, Url};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnounceEvent {
+    Started,
+    Completed,
+    Stopped,
+}
+
+impl AnnounceEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AnnounceEvent::Started => "started",
+            AnnounceEvent::Completed => "completed",
+            AnnounceEvent::Stopped => "stopped",
+        }
+    }
+}
+
 pub struct AnnounceRequest {
     pub tracker_url: String,
     pub info_hash: Sha1,
     pub peer_id: PeerId,
+    pub port: Option<u16>,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub event: Option<AnnounceEvent>,
 }
 
 impl AnnounceRequest {
+    const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+    pub fn announce(&self) -> Result<Duration> {
+        let response = self.make_announce_request()?;
+        parse_announce_response(response.as_bytes())
+    }
+
     pub fn fetch_peer_addresses(&self) -> Result<Vec<SocketAddr>> {
         let response = self.make_announce_request()?;
         let peer_addrs = get_peer_list_from_response(response.as_bytes())?;
@@ -26,10 +58,27 @@ impl AnnounceRequest {
     fn make_announce_url(&self) -> StdResult<Url, ParseError> {
         let info_hash = unsafe { String::from_utf8_unchecked(self.info_hash.as_vec()) };
         let peer_id = unsafe { String::from_utf8_unchecked(self.peer_id.as_vec()) };
-        Url::parse_with_params(
-            &self.tracker_url,
-            &[("info_hash", &info_hash), ("peer_id", &peer_id)],
-        )
+        let mut params = vec![("info_hash", info_hash), ("peer_id", peer_id)];
+        if let Some(port) = self.port {
+            params.push(("port", port.to_string()));
+        }
+        params.push(("uploaded", self.uploaded.to_string()));
+        params.push(("downloaded", self.downloaded.to_string()));
+        params.push(("left", self.left.to_string()));
+        if let Some(event) = self.event {
+            params.push(("event", event.as_str().to_string()));
+        }
+        Url::parse_with_params(&self.tracker_url, &params)
+    }
+}
+
+fn parse_announce_response(response: &[u8]) -> Result<Duration> {
+    let decoded_response: AnnounceResponse = serde_bencode::from_bytes(response)?;
+    match decoded_response.failure_reason {
+        Some(reason) => Err(format!("Tracker rejected announce: {}", reason).into()),
+        None => Ok(decoded_response
+            .interval
+            .map_or(AnnounceRequest::DEFAULT_INTERVAL, Duration::from_secs)),
     }
 }
 
@@ -58,6 +107,13 @@ struct TrackerResponse {
     peers: Vec<Peer>,
 }
 
+#[derive(Deserialize)]
+struct AnnounceResponse {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    interval: Option<u64>,
+}
+
 #[derive(Deserialize)]
 struct Peer {
     ip: String,
@@ -80,6 +136,11 @@ mod tests {
                 0xef, 0x12, 0x34, 0x56, 0x78, 0x9a,
             ]),
             peer_id: PeerId::default(),
+            port: None,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: None,
         };
 
         let url = request.make_announce_url().unwrap();
@@ -87,18 +148,80 @@ mod tests {
         let expected_params = [
             "info_hash=%124Vx%9A%BC%DE%F1%23Eg%89%AB%CD%EF%124Vx%9A",
             "peer_id=%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00",
+            "uploaded=0",
+            "downloaded=0",
+            "left=0",
         ]
         .join("&");
         let full_expected_url = tracker_url.to_owned() + "?" + &expected_params;
         assert_eq!(full_expected_url, url.to_string())
     }
 
+    #[test]
+    fn include_listening_port_in_tracker_request_url() {
+        let request = AnnounceRequest {
+            tracker_url: "http://localhost:8000/announce".to_string(),
+            info_hash: Sha1::new([0x00; 20]),
+            peer_id: PeerId::default(),
+            port: Some(6881),
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: None,
+        };
+
+        let url = request.make_announce_url().unwrap();
+        assert!(url.to_string().contains("&port=6881&"));
+    }
+
+    #[test]
+    fn include_transfer_stats_in_tracker_request_url() {
+        let request = AnnounceRequest {
+            tracker_url: "http://localhost:8000/announce".to_string(),
+            info_hash: Sha1::new([0x00; 20]),
+            peer_id: PeerId::default(),
+            port: None,
+            uploaded: 1024,
+            downloaded: 2048,
+            left: 512,
+            event: None,
+        };
+
+        let url = request.make_announce_url().unwrap();
+        assert!(
+            url.to_string()
+                .ends_with("&uploaded=1024&downloaded=2048&left=512")
+        );
+    }
+
+    #[test]
+    fn include_event_in_tracker_request_url() {
+        let request = AnnounceRequest {
+            tracker_url: "http://localhost:8000/announce".to_string(),
+            info_hash: Sha1::new([0x00; 20]),
+            peer_id: PeerId::default(),
+            port: None,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: Some(AnnounceEvent::Completed),
+        };
+
+        let url = request.make_announce_url().unwrap();
+        assert!(url.to_string().ends_with("&left=0&event=completed"));
+    }
+
     #[test]
     fn invalid_tracker_url_returns_error() {
         let request = AnnounceRequest {
             tracker_url: "http://localhost:blah/announce".to_string(),
             info_hash: Sha1::new([0x00; 20]),
             peer_id: PeerId::default(),
+            port: None,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: None,
         };
         let result = request.make_announce_url();
         assert_eq!(Err(ParseError::InvalidPort), result);
@@ -110,12 +233,29 @@ mod tests {
             tracker_url: "http://bttracker.debian.org:6969/announce".to_string(),
             info_hash: Sha1::new([0x00; 20]),
             peer_id: PeerId::default(),
+            port: None,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: None,
         };
 
         let result = request.make_announce_request().unwrap();
         assert_eq!("d14:failure reason17:torrent not founde", result);
     }
 
+    #[test]
+    fn take_reannounce_interval_from_announce_response() {
+        let interval = parse_announce_response(b"d8:intervali900e5:peerslee").unwrap();
+        assert_eq!(interval, Duration::from_secs(900));
+
+        let interval = parse_announce_response(b"d5:peerslee").unwrap();
+        assert_eq!(interval, AnnounceRequest::DEFAULT_INTERVAL);
+
+        let err = parse_announce_response(b"d14:failure reason7:unknowne").unwrap_err();
+        assert!(err.to_string().contains("unknown"));
+    }
+
     #[test]
     fn parse_tracker_response_and_get_peer_list() {
         let tracker_response = "d8:intervali900e5:peersld2:ip11:88.18.61.544:porti4666eed2:ip13:85.31.128.1114:porti52664eed2:ip13:95.58.175.2324:porti26163eed2:ip14:83.148.245.1864:porti51414eed2:ip14:15.204.231.2024:porti45548eed2:ip14:93.165.240.1044:porti56439eed2:ip14:193.148.16.2114:porti15981eed2:ip13:104.28.224.824:porti16570eed2:ip15:185.193.157.1874:porti25297eed2:ip14:37.120.185.2084:porti51413eed2:ip13:82.102.23.1394:porti39206eed2:ip14:92.101.157.2504:porti58130eed2:ip13:87.58.176.2384:porti62014eed2:ip13:87.58.176.2384:porti62004eed2:ip14:118.142.44.1464:porti6988eed2:ip10:95.33.0.764:porti22936eed2:ip13:73.196.29.1454:porti51413eed2:ip15:163.172.218.2154:porti31951eed2:ip13:63.210.25.1394:porti6886eed2:ip14:82.165.117.1884:porti1eed2:ip12:98.115.1.2084:porti50413eed2:ip15:109.226.251.1304:porti1230eed2:ip14:103.136.92.2524:porti14948eed2:ip14:193.32.127.2224:porti51765eed2:ip14:45.134.212.1014:porti46296eed2:ip13:82.65.230.1594:porti63812eed2:ip13:87.58.176.2384:porti62017eed2:ip13:189.46.193.814:porti9751eed2:ip14:217.174.206.674:porti51413eed2:ip14:183.107.103.254:porti51413eed2:ip13:81.201.16.2474:porti54694eed2:ip11:78.82.25.834:porti6887eed2:ip14:46.231.240.1874:porti50000eed2:ip12:134.3.183.424:porti58578eed2:ip13:73.81.101.1304:porti51414eed2:ip14:89.142.165.1314:porti51413eed2:ip13:82.24.182.2044:porti44346eed2:ip13:87.99.116.1484:porti51413eed2:ip13:87.58.176.2384:porti62015eed2:ip13:38.162.49.1954:porti6881eed2:ip13:82.64.112.1454:porti25561eed2:ip12:212.7.200.734:porti30151eed2:ip14:37.120.210.2114:porti9099eed2:ip12:37.112.5.2244:porti6881eed2:ip12:50.35.176.534:porti62904eed2:ip14:195.206.105.374:porti57402eed2:ip13:73.235.107.364:porti6881eed2:ip14:187.193.191.434:porti51765eed2:ip14:37.120.198.1724:porti12018eed2:ip14:185.21.216.1694:porti32774eeee";
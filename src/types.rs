@@ -1,4 +1,8 @@
 use sha1::Digest;
+
+mod bitfield;
+pub use bitfield::Bitfield;
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct PeerId([u8; 20]);
 
@@ -58,9 +62,9 @@ impl Sha1 {
     }
 
     pub fn calculate(value: &[u8]) -> Self {
-        let mut hasher = sha1::Sha1::new();
+        let mut hasher = Sha1Hasher::new();
         hasher.update(value);
-        Self(hasher.finalize().into())
+        hasher.finish()
     }
 
     pub fn as_bytes(&self) -> &[u8; 20] {
@@ -76,6 +80,23 @@ impl Sha1 {
     }
 }
 
+#[derive(Clone, Default)]
+pub struct Sha1Hasher(sha1::Sha1);
+
+impl Sha1Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> Sha1 {
+        Sha1(self.0.finalize().into())
+    }
+}
+
 impl std::fmt::Display for Sha1 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", hex::encode(self.0))
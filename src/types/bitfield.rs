@@ -0,0 +1,89 @@
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitfield(Vec<u8>);
+
+impl Bitfield {
+    pub fn new(piece_count: usize) -> Self {
+        Self(vec![0; piece_count.div_ceil(8)])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn has_piece(&self, index: u32) -> bool {
+        let (byte_index, mask) = Self::position(index);
+        self.0
+            .get(byte_index)
+            .is_some_and(|byte| byte & mask == mask)
+    }
+
+    pub fn set_piece(&mut self, index: u32) {
+        let (byte_index, mask) = Self::position(index);
+        if byte_index >= self.0.len() {
+            self.0.resize(byte_index + 1, 0);
+        }
+        self.0[byte_index] |= mask;
+    }
+
+    pub fn is_complete(&self, piece_count: usize) -> bool {
+        (0..piece_count as u32).all(|index| self.has_piece(index))
+    }
+
+    fn position(index: u32) -> (usize, u8) {
+        let byte_index = index as usize / 8;
+        let mask = 0b1000_0000 >> (index % 8);
+        (byte_index, mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_bitfield_has_no_pieces() {
+        let bitfield = Bitfield::new(10);
+        assert_eq!(bitfield.as_bytes(), &[0, 0]);
+        assert!(!bitfield.has_piece(0));
+        assert!(!bitfield.has_piece(9));
+    }
+
+    #[test]
+    fn pieces_are_numbered_from_high_bit() {
+        let bitfield = Bitfield::from_bytes(&[0b1000_0000, 0b0100_0000]);
+        assert!(bitfield.has_piece(0));
+        assert!(!bitfield.has_piece(1));
+        assert!(bitfield.has_piece(9));
+    }
+
+    #[test]
+    fn set_piece() {
+        let mut bitfield = Bitfield::new(16);
+        bitfield.set_piece(3);
+        bitfield.set_piece(15);
+        assert_eq!(bitfield.as_bytes(), &[0b0001_0000, 0b0000_0001]);
+    }
+
+    #[test]
+    fn set_piece_past_end_extends_bitfield() {
+        let mut bitfield = Bitfield::default();
+        bitfield.set_piece(8);
+        assert_eq!(bitfield.as_bytes(), &[0, 0b1000_0000]);
+    }
+
+    #[test]
+    fn piece_past_end_is_missing() {
+        let bitfield = Bitfield::from_bytes(&[0xff]);
+        assert!(!bitfield.has_piece(8));
+    }
+
+    #[test]
+    fn complete_bitfield_ignores_redundant_bits() {
+        assert!(Bitfield::from_bytes(&[0xff, 0b1100_0000]).is_complete(10));
+        assert!(!Bitfield::from_bytes(&[0xff, 0b1000_0000]).is_complete(10));
+    }
+}
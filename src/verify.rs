@@ -0,0 +1,318 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{hash_pool::HashPool, storage::StorageLayout, torrent::Info, types::Bitfield};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataStatus {
+    Good,
+    Missing,
+    Corrupt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub length: usize,
+    pub status: DataStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub pieces: Vec<DataStatus>,
+    pub files: Vec<FileReport>,
+}
+
+impl VerifyReport {
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|status| *status == DataStatus::Good)
+    }
+
+    pub fn piece_count(&self, status: DataStatus) -> usize {
+        self.pieces.iter().filter(|s| **s == status).count()
+    }
+
+    pub fn verified_pieces(&self) -> Bitfield {
+        let mut bitfield = Bitfield::new(self.pieces.len());
+        for (index, status) in self.pieces.iter().enumerate() {
+            if *status == DataStatus::Good {
+                bitfield.set_piece(index as u32);
+            }
+        }
+        bitfield
+    }
+}
+
+pub fn verify(
+    info: &Info,
+    data_dir: impl AsRef<Path>,
+    hash_pool: &mut HashPool,
+) -> io::Result<VerifyReport> {
+    let layout = StorageLayout::from_info(info);
+    let mut reader = DataReader::open(data_dir.as_ref(), &layout)?;
+
+    let mut pieces = vec![DataStatus::Missing; info.pieces.len()];
+    let mut read_error = None;
+    let stored_pieces = info
+        .pieces
+        .iter()
+        .enumerate()
+        .map_while(|(index, hash)| match reader.read_piece(index as u32) {
+            Ok(data) => Some(data.map(|data| (index as u32, data, *hash))),
+            Err(err) => {
+                read_error = Some(err);
+                None
+            }
+        })
+        .flatten();
+    hash_pool.verify_all(stored_pieces, |result| {
+        pieces[result.piece_index as usize] = if result.is_valid {
+            DataStatus::Good
+        } else {
+            DataStatus::Corrupt
+        };
+    });
+    if let Some(err) = read_error {
+        return Err(err);
+    }
+
+    let files = file_reports(&layout, &reader, &pieces);
+
+    Ok(VerifyReport { pieces, files })
+}
+
+fn file_reports(
+    layout: &StorageLayout,
+    reader: &DataReader,
+    pieces: &[DataStatus],
+) -> Vec<FileReport> {
+    layout
+        .files()
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| file.has_data())
+        .map(|(file_index, file)| {
+            let file_pieces = layout.file_pieces(file_index);
+            let overlapping = &pieces[file_pieces.start as usize..file_pieces.end as usize];
+            let status = if !reader.is_file_complete(file_index, file.length) {
+                DataStatus::Missing
+            } else if overlapping.contains(&DataStatus::Corrupt) {
+                DataStatus::Corrupt
+            } else if overlapping.contains(&DataStatus::Missing) {
+                DataStatus::Missing
+            } else {
+                DataStatus::Good
+            };
+
+            FileReport {
+                path: file.path.clone(),
+                length: file.length,
+                status,
+            }
+        })
+        .collect()
+}
+
+struct DataReader<'a> {
+    layout: &'a StorageLayout,
+    files: Vec<Option<(File, u64)>>,
+}
+
+impl<'a> DataReader<'a> {
+    fn open(data_dir: &Path, layout: &'a StorageLayout) -> io::Result<Self> {
+        let files = layout
+            .files()
+            .iter()
+            .map(|file| {
+                if !file.has_data() {
+                    return Ok(None);
+                }
+                match File::open(data_dir.join(&file.path)) {
+                    Ok(handle) => {
+                        let length = handle.metadata()?.len();
+                        Ok(Some((handle, length)))
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(err),
+                }
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { layout, files })
+    }
+
+    fn is_file_complete(&self, file_index: usize, expected_length: usize) -> bool {
+        self.files[file_index]
+            .as_ref()
+            .is_some_and(|(_, length)| *length >= expected_length as u64)
+    }
+
+    fn read_piece(&mut self, piece_index: u32) -> io::Result<Option<Vec<u8>>> {
+        let piece_start = self.layout.piece_start(piece_index);
+        let piece_length = self.layout.piece_length(piece_index) as usize;
+        let mut data = vec![0; piece_length];
+        let mut data_offset = 0;
+
+        for segment in self.layout.segments(piece_start, piece_length) {
+            if !self.layout.files()[segment.file_index].has_data() {
+                data_offset += segment.length;
+                continue;
+            }
+            let Some((file, _)) = &mut self.files[segment.file_index] else {
+                return Ok(None);
+            };
+            file.seek(SeekFrom::Start(segment.file_offset))?;
+            match file.read_exact(&mut data[data_offset..data_offset + segment.length]) {
+                Ok(()) => data_offset += segment.length,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{torrent::FileEntry, types::Sha1};
+
+    use super::*;
+
+    fn make_info(files: &[(&str, &[u8])], piece_length: u32) -> Info {
+        let data = files
+            .iter()
+            .flat_map(|(_, content)| content.iter().copied())
+            .collect::<Vec<_>>();
+        Info {
+            sha1: Sha1::calculate(&data),
+            name: "dataset".to_string(),
+            piece_length,
+            length: data.len(),
+            pieces: data
+                .chunks(piece_length as usize)
+                .map(Sha1::calculate)
+                .collect(),
+            files: files
+                .iter()
+                .map(|(path, content)| FileEntry {
+                    path: PathBuf::from(path),
+                    length: content.len(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn verify_complete_single_file() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&[("data.bin", &data)], 10);
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), &data).unwrap();
+
+        let report = verify(&info, dir.path(), &mut HashPool::new(2)).unwrap();
+
+        assert!(report.is_complete());
+        assert_eq!(report.files[0].status, DataStatus::Good);
+    }
+
+    #[test]
+    fn report_corrupt_and_missing_pieces() {
+        let data = (0..25).collect::<Vec<u8>>();
+        let info = make_info(&[("data.bin", &data)], 10);
+        let mut on_disk = data[..15].to_vec();
+        on_disk[3] = 0xff;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.bin"), &on_disk).unwrap();
+
+        let report = verify(&info, dir.path(), &mut HashPool::new(2)).unwrap();
+
+        assert_eq!(
+            report.pieces,
+            vec![
+                DataStatus::Corrupt,
+                DataStatus::Missing,
+                DataStatus::Missing
+            ]
+        );
+        assert_eq!(report.files[0].status, DataStatus::Missing);
+        assert_eq!(report.verified_pieces(), Bitfield::new(3));
+    }
+
+    #[test]
+    fn report_status_of_each_file() {
+        let first = (0..8).collect::<Vec<u8>>();
+        let second = (8..16).collect::<Vec<u8>>();
+        let third = (16..24).collect::<Vec<u8>>();
+        let info = make_info(
+            &[
+                ("dataset/first", &first),
+                ("dataset/second", &second),
+                ("dataset/third", &third),
+            ],
+            8,
+        );
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("dataset")).unwrap();
+        fs::write(dir.path().join("dataset/first"), &first).unwrap();
+        fs::write(dir.path().join("dataset/second"), [0; 8]).unwrap();
+
+        let report = verify(&info, dir.path(), &mut HashPool::new(2)).unwrap();
+
+        assert_eq!(
+            report.pieces,
+            vec![DataStatus::Good, DataStatus::Corrupt, DataStatus::Missing]
+        );
+        let statuses = report
+            .files
+            .iter()
+            .map(|file| file.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![DataStatus::Good, DataStatus::Corrupt, DataStatus::Missing]
+        );
+        assert_eq!(
+            report.verified_pieces(),
+            Bitfield::from_bytes(&[0b1000_0000])
+        );
+    }
+
+    #[test]
+    fn verify_torrent_with_only_empty_files() {
+        let info = make_info(&[("dataset/empty", &[])], 8);
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("dataset")).unwrap();
+        fs::write(dir.path().join("dataset/empty"), []).unwrap();
+
+        let report = verify(&info, dir.path(), &mut HashPool::new(2)).unwrap();
+
+        assert!(report.pieces.is_empty());
+        assert_eq!(report.files[0].status, DataStatus::Good);
+    }
+
+    #[test]
+    fn serialize_report_as_json() {
+        let report = VerifyReport {
+            pieces: vec![DataStatus::Good, DataStatus::Corrupt],
+            files: vec![FileReport {
+                path: PathBuf::from("data.bin"),
+                length: 20,
+                status: DataStatus::Corrupt,
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"pieces":["good","corrupt"],"files":[{"path":"data.bin","length":20,"status":"corrupt"}]}"#
+        );
+    }
+}
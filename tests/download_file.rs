@@ -1,6 +1,8 @@
 use std::sync::mpsc;
 
+use bt_client::DownloadOptions;
 use bt_client::result::Result;
+use bt_client::storage::MemoryStorage;
 use bt_client::types::PeerId;
 
 mod test_env;
@@ -14,9 +16,12 @@ fn download_file_successfully() -> Result<()> {
     let torrent = TestEnv::read_torrent_file()?;
     let peer_id = PeerId::default();
 
+    let mut storage = MemoryStorage::new(torrent.info.piece_length, torrent.info.length);
+
+    let options = DownloadOptions::default();
     let (tx, _rx) = mpsc::channel();
-    let downloaded = torrent.download_from(vec![peer_address], peer_id, &tx)?;
-    assert_eq!(TestEnv::read_data_file()?, downloaded.content);
+    torrent.download_from(&options, vec![peer_address], peer_id, &mut storage, &tx)?;
+    assert_eq!(TestEnv::read_data_file()?, storage.content());
 
     Ok(())
 }
@@ -27,9 +32,12 @@ fn fail_to_connect_to_peer() -> Result<()> {
     let torrent = TestEnv::read_torrent_file()?;
     let peer_id = PeerId::default();
 
+    let mut storage = MemoryStorage::new(torrent.info.piece_length, torrent.info.length);
+
+    let options = DownloadOptions::default();
     let (tx, _rx) = mpsc::channel();
     torrent
-        .download_from(vec![peer_address], peer_id, &tx)
+        .download_from(&options, vec![peer_address], peer_id, &mut storage, &tx)
         .expect_err("Expected error");
 
     Ok(())